@@ -1,22 +1,45 @@
 use crate::{
+    expr::{self, ExprError},
     imm::{Imm, ImmType},
-    op::Op,
+    include::{self, Source},
+    instruction_set::InstructionSet,
+    isa::{IsaSpec, OperandKind, OperandSpec},
+    messages::{self, Lang},
+    object::{self, Symbols},
+    op::{Extension, Isa, Op},
     program::Program,
     reg::Reg,
+    word::Word,
 };
 use std::{
     fmt::Display,
-    fs::File,
-    io::{BufRead, BufReader},
+    ops::Range,
+    path::{Path, PathBuf},
 };
 
 struct Line {
     string: String,
     pub lineno: usize,
 }
+#[derive(Clone)]
 struct LinePreprocessed {
     string: String,
     pub lineno: usize,
+    /// The macro and the line of its body this line was expanded from.
+    in_macro: Option<(String, usize)>,
+    /// Which of the `\`-separated statements on its line this is, counting
+    /// from 1, if there are several.
+    statement: Option<usize>,
+    /// Whether the line is a raw word from `.byte`, `.fill` or `.space`
+    /// placed in program memory, `.word <value>`, rather than source text.
+    raw: bool,
+    /// Where each token, the mnemonic first, was written on the source line.
+    /// A token rewritten by a pass keeps the span of what it replaced, and a
+    /// line made up by a pass has none.
+    spans: Vec<Range<usize>>,
+    /// A comment for listings about the instruction on this line, e.g. how
+    /// many the `LDI` it starts expanded into.
+    note: Option<String>,
 }
 
 struct Tokenized<'a> {
@@ -27,7 +50,8 @@ struct Tokenized<'a> {
 impl Line {
     fn preprocess(self) -> Option<LinePreprocessed> {
         // remove comment
-        let cut = self.string.find(';').unwrap_or(self.string.len());
+        let cut = comment_start(&self.string);
+        let spans = spans_at(&self.string[0..cut], 0);
         let string = self.string[0..cut].trim().to_owned();
 
         if string.is_empty() {
@@ -36,30 +60,195 @@ impl Line {
             Some(LinePreprocessed {
                 string,
                 lineno: self.lineno,
+                in_macro: None,
+                statement: None,
+                raw: false,
+                spans,
+                note: None,
             })
         }
     }
 }
 
+/// The tokens of a line with their byte offsets. Any run of whitespace
+/// and/or commas separates tokens, except that whitespace within an
+/// expression, e.g. `(MAX - 1) / 2`, leaves it one token.
+fn token_spans(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    let pieces = line
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|t| !t.is_empty())
+        .map(|t| {
+            let at = t.as_ptr() as usize - line.as_ptr() as usize;
+            at..at + t.len()
+        });
+    let mut spans: Vec<Range<usize>> = vec![];
+    for piece in pieces {
+        // the mnemonic is never part of an expression
+        let operands = spans.len() > 1;
+        match spans.last_mut() {
+            Some(span)
+                if operands
+                    && !line[span.end..piece.start].contains(',')
+                    && continues_expression(&line[span.clone()], &line[piece.clone()]) =>
+            {
+                span.end = piece.end
+            }
+            _ => spans.push(piece),
+        }
+    }
+    spans.into_iter().map(move |span| (span.start, &line[span]))
+}
+
+/// Whether `next`, after whitespace, is more of the expression `token`: the
+/// space is inside parentheses or next to an arithmetic operator. Comparisons
+/// like `<` are left apart for `.if`.
+fn continues_expression(token: &str, next: &str) -> bool {
+    const OPERATORS: [&str; 7] = ["+", "-", "*", "/", "%", "<<", ">>"];
+    let open = token.matches('(').count() > token.matches(')').count();
+    open || OPERATORS.iter().any(|op| token.ends_with(op))
+        || OPERATORS.contains(&next)
+        || ["*", "/", "%", "<<", ">>", ")"]
+            .iter()
+            .any(|op| next.starts_with(op))
+}
+
+/// The spans of the tokens of `text`, which starts at byte `start` of its
+/// source line. Text of only commas has the span of the first, for the error
+/// about it.
+fn spans_at(text: &str, start: usize) -> Vec<Range<usize>> {
+    let mut spans: Vec<Range<usize>> = token_spans(text)
+        .map(|(at, t)| start + at..start + at + t.len())
+        .collect();
+    if spans.is_empty() {
+        spans.extend(text.find(',').map(|at| start + at..start + at + 1));
+    }
+    spans
+}
+
+/// Where the comment of a line starts, or its length if it has none. A
+/// comment starts with `;`, `//`, or a `#` that starts the line or is
+/// followed by a space, as `#5` is an immediate.
+pub(crate) fn comment_start(line: &str) -> usize {
+    let hash = line.match_indices('#').find(|(i, _)| {
+        line[..*i].trim().is_empty() || line[i + 1..].chars().next().is_none_or(char::is_whitespace)
+    });
+    [line.find(';'), line.find("//"), hash.map(|(i, _)| i)]
+        .into_iter()
+        .flatten()
+        .min()
+        .unwrap_or(line.len())
+}
+
 impl LinePreprocessed {
-    fn tokenize<'a>(&'a self) -> Tokenized<'a> {
-        let mut parts = self.string.splitn(2, ' ');
-        let mnenomic = parts.next().expect("Shouldn't be empty after trim.");
-        let tokens = parts
-            .next()
-            .map(|t| {
-                t.split([',', ' '])
-                    .map(str::trim)
-                    .filter(|t| !t.is_empty())
-                    .collect()
+    /// A line standing in for this one, e.g. part of its expansion.
+    fn derive(&self, string: String) -> LinePreprocessed {
+        LinePreprocessed {
+            string,
+            lineno: self.lineno,
+            in_macro: self.in_macro.clone(),
+            statement: self.statement,
+            raw: false,
+            spans: vec![],
+            note: None,
+        }
+    }
+
+    /// A raw word with `value` standing in for this line, written as its
+    /// `token`th token, if it was written at all.
+    fn raw_word(&self, value: &str, token: Option<usize>) -> LinePreprocessed {
+        let spans = token.and_then(|n| {
+            Some(vec![
+                self.spans.first()?.clone(),
+                self.spans.get(n)?.clone(),
+            ])
+        });
+        LinePreprocessed {
+            raw: true,
+            spans: spans.unwrap_or_default(),
+            ..self.derive(format!(".word {value}"))
+        }
+    }
+
+    /// Where the line's text starts on its source line, while it is still as
+    /// written there.
+    fn start(&self) -> Option<usize> {
+        let (at, _) = token_spans(&self.string).next()?;
+        Some(self.spans.first()?.start - at)
+    }
+
+    /// The part of the line's text starting at byte `at` of it, as a line of
+    /// its own.
+    fn part(&self, at: usize, text: &str) -> LinePreprocessed {
+        LinePreprocessed {
+            spans: self
+                .start()
+                .map_or(vec![], |start| spans_at(text, start + at)),
+            ..self.derive(text.trim().to_string())
+        }
+    }
+
+    /// Split a line like `CLR r0 \ PAUSE` into its statements.
+    fn statements(self) -> Vec<LinePreprocessed> {
+        if !self.string.contains('\\') {
+            return vec![self];
+        }
+        self.string
+            .split('\\')
+            .scan(0, |at, s| {
+                let part = self.part(*at, s);
+                *at += s.len() + 1;
+                Some(part)
             })
-            .unwrap_or_else(|| vec![]);
+            .enumerate()
+            .filter(|(_, s)| !s.string.is_empty())
+            .map(|(n, s)| LinePreprocessed {
+                statement: Some(n + 1),
+                ..s
+            })
+            .collect()
+    }
+
+    /// `e` as an error on this line, at the token it's about when that is
+    /// known: an operand's for [`AsmError::InOperand`] and the mnemonic's for
+    /// an error about it.
+    fn error(&self, e: AsmError) -> AsmLineError {
+        use AsmError::*;
+
+        match e {
+            InOperand(e, n) => self.error_at(*e, n + 1),
+            e @ (InvalidMnenomic(_)
+            | ExtensionDisabled(..)
+            | Deprecated(..)
+            | NotData(_)
+            | UnmatchedDirective(_)) => self.error_at(e, 0),
+            e => e.on_line(self.lineno),
+        }
+    }
+
+    /// `e` as an error at the `n`th token of this line, or just past the last
+    /// one for a token that is missing.
+    fn error_at(&self, e: AsmError, n: usize) -> AsmLineError {
+        let span = match self.spans.get(n) {
+            Some(span) => Some(span.clone()),
+            None if n == self.spans.len() => self.spans.last().map(|s| s.end..s.end),
+            None => None,
+        };
+        AsmLineError(e, self.lineno, span)
+    }
+
+    fn tokenize<'a>(&'a self) -> Tokenized<'a> {
+        let mut parts = token_spans(&self.string).map(|(_, t)| t);
+        // blank lines are skipped before tokenizing, and lines of only commas
+        // reported, but an empty mnemonic is just invalid rather than a panic
+        let mnenomic = parts.next().unwrap_or_default();
+        let tokens = parts.collect();
         Tokenized::<'a> { mnenomic, tokens }
     }
 }
 
+/// Why a line failed to assemble.
 #[derive(Debug, PartialEq)]
-enum AsmError {
+pub enum AsmError {
     InvalidMnenomic(String),
     MissingImmediate,
     InvalidImmediate(String),
@@ -67,133 +256,2326 @@ enum AsmError {
     MissingRegister,
     InvalidRegister(String),
     ExtraToken(String),
+    InvalidUtf8,
+    ExtensionDisabled(String, Extension),
+    InvalidPseudo(String),
+    PseudoOperandCount(String, usize, usize),
+    InvalidEntry(String),
+    DuplicateEntry,
+    DuplicateSymbol(String),
+    NotData(String),
+    MissingHash(String),
+    MissingComma,
+    BranchOutOfRange(usize, i32),
+    /// A deprecated mnemonic and the one that replaced it.
+    Deprecated(String, String),
+    InvalidLabel(String),
+    UndefinedConstant(String),
+    /// A constant definition whose name or value is invalid.
+    InvalidConstant(String),
+    /// A `.org` address and the address already reached.
+    OrgOverlap(usize, usize),
+    /// A value placed in program memory that isn't an instruction.
+    NotInstruction(String),
+    /// A `.macro` without a matching `.endm`.
+    UnterminatedMacro(String),
+    /// A `.rept` without a matching `.endr`.
+    UnterminatedRept,
+    /// An `.if`, `.ifdef` or `.ifndef` without a matching `.endif`.
+    UnterminatedIf,
+    /// An `.else` or `.endif` without an `.if` before it.
+    UnmatchedDirective(String),
+    /// An `.if` condition that isn't a value or a comparison of two.
+    InvalidCondition(String),
+    /// An expression with bad syntax, or dividing by zero or overflowing.
+    InvalidExpression(String),
+    /// A `.def` alias name that is a register or not an identifier.
+    InvalidAlias(String),
+    /// An `.align` boundary that isn't a positive power of two.
+    InvalidAlignment(i32),
+    /// A label, constant or symbol defined again, and the line it was first
+    /// defined on.
+    Redefined(String, usize),
+    /// Words of memory a line needs, and the depth of the memory.
+    PastDepth(usize, usize),
+    /// A name used where a label or a constant could be, that is neither.
+    UndefinedSymbol(String),
+    /// A name in `.allow` or `#allow(...)` that isn't a warning's, or none.
+    UnknownWarning(String),
+    /// An error in a line expanded from a macro, with the macro's name and
+    /// the line of its definition the expanded line came from.
+    InMacro(Box<AsmError>, String, usize),
+    /// An error in one of several statements on a line, and which.
+    InStatement(Box<AsmError>, usize),
+    /// An error in an operand, and which, counting from 0. A missing operand
+    /// is the one after the last.
+    InOperand(Box<AsmError>, usize),
 }
 
 impl Display for AsmError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            AsmError::InvalidMnenomic(x) => write!(f, "Invalid mnenomic \"{x}\"."),
-            AsmError::MissingImmediate => write!(f, "Missing an immediate."),
-            AsmError::InvalidImmediate(x) => write!(f, "Invalid immediate \"{x}\"."),
-            AsmError::ImmediateOutOfRange(x) => write!(f, "Immediate {x} is out of range."),
-            AsmError::MissingRegister => write!(f, "Missing a register."),
-            AsmError::InvalidRegister(x) => write!(f, "Invalid register \"{x}\"."),
-            AsmError::ExtraToken(x) => write!(f, "Unexpected token \"{x}\"."),
-        }
+        write!(f, "{}", messages::asm_error(self, Lang::En))
     }
 }
 
 impl AsmError {
+    /// Stable code identifying the kind of error, see `asp explain-error`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AsmError::InvalidMnenomic(_) => "E0001",
+            AsmError::ImmediateOutOfRange(_) => "E0002",
+            AsmError::InvalidImmediate(_) => "E0003",
+            AsmError::MissingImmediate => "E0004",
+            AsmError::InvalidRegister(_) => "E0005",
+            AsmError::MissingRegister => "E0006",
+            AsmError::ExtraToken(_) => "E0007",
+            AsmError::InvalidUtf8 => "E0008",
+            AsmError::ExtensionDisabled(..) => "E0010",
+            AsmError::InvalidPseudo(_) => "E0011",
+            AsmError::PseudoOperandCount(..) => "E0012",
+            AsmError::InvalidEntry(_) => "E0013",
+            AsmError::DuplicateEntry => "E0014",
+            AsmError::DuplicateSymbol(_) => "E0015",
+            AsmError::NotData(_) => "E0016",
+            AsmError::MissingHash(_) => "E0017",
+            AsmError::MissingComma => "E0018",
+            AsmError::BranchOutOfRange(..) => "E0019",
+            AsmError::Deprecated(..) => "E0020",
+            AsmError::InvalidLabel(_) => "E0021",
+            AsmError::UndefinedConstant(_) => "E0022",
+            AsmError::InvalidConstant(_) => "E0023",
+            AsmError::OrgOverlap(..) => "E0024",
+            AsmError::NotInstruction(_) => "E0025",
+            AsmError::UnterminatedMacro(_) => "E0026",
+            AsmError::UnterminatedRept => "E0027",
+            AsmError::UnterminatedIf => "E0028",
+            AsmError::UnmatchedDirective(_) => "E0029",
+            AsmError::InvalidCondition(_) => "E0030",
+            AsmError::InvalidExpression(_) => "E0031",
+            AsmError::InvalidAlias(_) => "E0032",
+            AsmError::InvalidAlignment(_) => "E0033",
+            AsmError::Redefined(..) => "E0034",
+            AsmError::PastDepth(..) => "E0035",
+            AsmError::UndefinedSymbol(_) => "E0036",
+            AsmError::UnknownWarning(_) => "E0037",
+            AsmError::InMacro(e, ..) => e.code(),
+            AsmError::InStatement(e, _) => e.code(),
+            AsmError::InOperand(e, _) => e.code(),
+        }
+    }
+
     fn on_line(self, line: usize) -> AsmLineError {
-        AsmLineError(self, line)
+        AsmLineError(self, line, None)
+    }
+
+    fn in_operand(self, n: usize) -> AsmError {
+        AsmError::InOperand(Box::new(self), n)
+    }
+}
+
+/// Something suspicious but legal in a line, reported without stopping
+/// assembly unless warnings are denied.
+#[derive(Debug, PartialEq)]
+pub enum AsmWarning {
+    /// A deprecated mnemonic the ISA still accepts, and the one that
+    /// replaced it.
+    Deprecated(String, String),
+    /// An instruction that changes nothing, e.g. `SUBI r0, 0`.
+    NoEffect(String),
+}
+
+impl AsmWarning {
+    /// The names of the warnings, for `.allow` and `#allow(...)`.
+    pub const NAMES: [&'static str; 2] = ["deprecated", "no_effect"];
+
+    /// The name `.allow` and `#allow(...)` silence the warning with.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AsmWarning::Deprecated(..) => "deprecated",
+            AsmWarning::NoEffect(_) => "no_effect",
+        }
+    }
+}
+
+/// An error, the line it is on, and where on the line the token it's about
+/// was written, when known.
+pub(crate) struct AsmLineError(AsmError, usize, Option<Range<usize>>);
+
+impl AsmLineError {
+    /// The line the error is on, counting from 1.
+    pub(crate) fn line(&self) -> usize {
+        self.1
+    }
+
+    /// The error as printed, in `lang`.
+    pub(crate) fn localized(&self, lang: Lang) -> String {
+        let message = messages::asm_error(&self.0, lang);
+        messages::line_error(self.1, self.0.code(), &message, lang)
+    }
+
+    /// The error as printed for `source`, the text that was assembled, with
+    /// the column of the token it's about and the line with a caret under
+    /// it, when that is known. `line` is the line number shown.
+    pub(crate) fn localized_in(&self, source: &[u8], line: usize, lang: Lang) -> String {
+        let message = messages::asm_error(&self.0, lang);
+        let code = self.0.code();
+        // spans are counted without the BOM, like the lines were assembled
+        let source = source.strip_prefix(b"\xef\xbb\xbf").unwrap_or(source);
+        let mut lines = source.split(|b| *b == b'\n');
+        let text = self.1.checked_sub(1).and_then(|n| lines.nth(n));
+        let text = text.unwrap_or_default();
+        let text = String::from_utf8_lossy(text.strip_suffix(b"\r").unwrap_or(text));
+        let span = self
+            .2
+            .clone()
+            .filter(|span| text.get(span.clone()).is_some());
+        let Some(span) = span else {
+            return messages::line_error(line, code, &message, lang);
+        };
+        // keep tabs so the caret lines up however they're shown
+        let indent: String = text[..span.start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        format!(
+            "{}\n    {}\n    {indent}{}",
+            messages::column_error(line, span.start + 1, code, &message, lang),
+            text.trim_end(),
+            "^".repeat(text[span].chars().count().max(1)),
+        )
+    }
+}
+
+impl Display for AsmLineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.localized(Lang::En))
+    }
+}
+
+/// A pseudo-instruction defined in the source, e.g.
+/// `.pseudo DELAY n: CLR r3 | ADDI r3, n | PAUSE`. Each use is replaced by
+/// the instructions after the colon, with parameters substituted.
+struct Pseudo {
+    name: String,
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+impl Pseudo {
+    /// Parse the text after `.pseudo`.
+    fn parse(definition: &str) -> Result<Pseudo, AsmError> {
+        let invalid = || AsmError::InvalidPseudo(definition.to_string());
+
+        let (head, body) = definition.split_once(':').ok_or_else(invalid)?;
+        let mut head = head
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|t| !t.is_empty());
+        let name = head.next().ok_or_else(invalid)?.to_string();
+        let params: Vec<String> = head.map(str::to_string).collect();
+        let body: Vec<String> = body.split('|').map(|i| i.trim().to_string()).collect();
+
+        if body.iter().any(String::is_empty) {
+            return Err(invalid());
+        }
+        Ok(Pseudo { name, params, body })
+    }
+
+    /// The instructions a use of the pseudo-instruction with `args` stands for.
+    fn expand(&self, args: &[&str]) -> Result<Vec<String>, AsmError> {
+        if args.len() != self.params.len() {
+            return Err(AsmError::PseudoOperandCount(
+                self.name.clone(),
+                self.params.len(),
+                args.len(),
+            ));
+        }
+
+        // parameters are replaced wherever they appear as a word, e.g. in
+        // `(v + 1)`, leaving the rest of the text as written
+        let substitute = |word: &str| {
+            let n = self.params.iter().position(|p| p == word)?;
+            Some(args[n].to_string())
+        };
+        Ok(self
+            .body
+            .iter()
+            .map(|instr| {
+                let (mnemonic, operands) =
+                    instr.split_once(char::is_whitespace).unwrap_or((instr, ""));
+                format!("{mnemonic} {}", replace_words(operands.trim(), substitute))
+                    .trim_end()
+                    .to_string()
+            })
+            .collect())
+    }
+}
+
+/// Record `.pseudo` definitions and replace each later use with its
+/// expansion. Expanded instructions keep the line number of the use.
+fn expand_pseudos(
+    lines: Vec<LinePreprocessed>,
+    errors: &mut Vec<AsmLineError>,
+) -> Vec<LinePreprocessed> {
+    let mut pseudos: Vec<Pseudo> = vec![];
+    let mut expanded = vec![];
+
+    for line in lines {
+        if let Some(definition) = line.string.strip_prefix(".pseudo") {
+            match Pseudo::parse(definition.trim()) {
+                Ok(p) => pseudos.push(p),
+                Err(e) => errors.push(line.error(e)),
+            }
+            continue;
+        }
+
+        let tokenized = line.tokenize();
+        let Some(pseudo) = pseudos.iter().rev().find(|p| p.name == tokenized.mnenomic) else {
+            expanded.push(line);
+            continue;
+        };
+        match pseudo.expand(&tokenized.tokens) {
+            Ok(instrs) => expanded.extend(instrs.into_iter().map(|s| line.derive(s))),
+            Err(e) => errors.push(line.error(e)),
+        }
+    }
+    expanded
+}
+
+/// Remove `.def name = rN` register aliases, replacing the name with the
+/// register on the lines after it. An alias can be redefined. Returns the
+/// aliases as last defined.
+fn take_aliases(
+    lines: Vec<LinePreprocessed>,
+    errors: &mut Vec<AsmLineError>,
+) -> (Vec<LinePreprocessed>, Vec<(String, String)>) {
+    let mut aliases: Vec<(String, String)> = vec![];
+    let mut rest = vec![];
+
+    for mut line in lines {
+        let tokenized = line.tokenize();
+        if tokenized.mnenomic != ".def" {
+            let string = replace_words(&line.string, |w| {
+                let (_, reg) = aliases.iter().find(|(a, _)| a == w)?;
+                Some(reg.clone())
+            });
+            line.string = string;
+            rest.push(line);
+            continue;
+        }
+
+        let last = tokenized.tokens.len().saturating_sub(1);
+        let alias = match tokenized.tokens[..] {
+            [name, "=", reg] | [name, reg] => match (is_identifier(name), is_register(reg)) {
+                (false, _) => Err(AsmError::InvalidAlias(name.to_string()).in_operand(0)),
+                (_, false) => Err(AsmError::InvalidRegister(reg.to_string()).in_operand(last)),
+                _ => Ok((name.to_string(), reg.to_lowercase())),
+            },
+            [] | [_] => Err(AsmError::MissingRegister.in_operand(tokenized.tokens.len())),
+            [_, _, _, extra, ..] => Err(AsmError::ExtraToken(extra.to_string()).in_operand(3)),
+            [_, _, extra] => Err(AsmError::ExtraToken(extra.to_string()).in_operand(2)),
+        };
+        match alias {
+            Ok((name, reg)) => {
+                aliases.retain(|(a, _)| *a != name);
+                aliases.push((name, reg));
+            }
+            Err(e) => errors.push(line.error(e)),
+        }
+    }
+    (rest, aliases)
+}
+
+/// `s` with each word, a run of letters, digits and underscores, replaced
+/// by `replace` where it gives a replacement.
+fn replace_words(s: &str, replace: impl Fn(&str) -> Option<String>) -> String {
+    let mut replaced = String::new();
+    let mut rest = s;
+    while !rest.is_empty() {
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let start = rest.find(is_word).unwrap_or(rest.len());
+        replaced.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c| !is_word(c)).unwrap_or(rest.len());
+        let word = &rest[..end];
+        match replace(word) {
+            Some(r) => replaced.push_str(&r),
+            None => replaced.push_str(word),
+        }
+        rest = &rest[end..];
+    }
+    replaced
+}
+
+/// Replace each `.rept <count>` ... `.endr` block with `count` copies of the
+/// lines inside it. Blocks may be nested. A block can't repeat more lines
+/// other than labels, or more times, than `depth` words of memory hold.
+fn expand_repeats(
+    lines: Vec<LinePreprocessed>,
+    depth: usize,
+    errors: &mut Vec<AsmLineError>,
+) -> Vec<LinePreprocessed> {
+    let mut expanded = vec![];
+    let mut lines = lines.into_iter();
+
+    while let Some(line) = lines.next() {
+        let tokenized = line.tokenize();
+        if tokenized.mnenomic != ".rept" {
+            expanded.push(line);
+            continue;
+        }
+
+        // the block ends at the `.endr` matching this `.rept`
+        let mut body = vec![];
+        let mut nesting = 1;
+        for l in lines.by_ref() {
+            match l.tokenize().mnenomic {
+                ".rept" => nesting += 1,
+                ".endr" => nesting -= 1,
+                _ => (),
+            }
+            if nesting == 0 {
+                break;
+            }
+            body.push(l);
+        }
+        if nesting != 0 {
+            errors.push(AsmError::UnterminatedRept.on_line(line.lineno));
+        }
+
+        let count = match tokenized.tokens[..] {
+            [] => Err(AsmError::MissingImmediate),
+            [count] => parse_number(count)
+                .ok_or_else(|| invalid_immediate(count))
+                .and_then(|n| usize::try_from(n).map_err(|_| AsmError::ImmediateOutOfRange(n))),
+            [_, extra, ..] => Err(AsmError::ExtraToken(extra.to_string())),
+        };
+        let body = expand_repeats(body, depth, errors);
+        let placed = body.iter().filter(|l| !l.string.ends_with(':')).count();
+        let count = count.and_then(|count| match count.saturating_mul(placed.max(1)) {
+            words if words > depth => Err(AsmError::PastDepth(words, depth)),
+            _ => Ok(count),
+        });
+        match count {
+            Ok(count) => {
+                for _ in 0..count {
+                    expanded.extend(body.iter().cloned());
+                }
+            }
+            Err(e @ AsmError::ExtraToken(_)) => errors.push(line.error_at(e, 2)),
+            Err(e) => errors.push(line.error_at(e, 1)),
+        }
+    }
+    expanded
+}
+
+/// Record `.macro NAME params` ... `.endm` definitions and replace each
+/// later use with the body, parameters substituted. Expanded lines keep the
+/// line number of the use and remember the line of the body they came from.
+/// Macro bodies may use pseudo-instructions, `.if` and `.rept`, but not
+/// other macros.
+fn expand_macros(
+    lines: Vec<LinePreprocessed>,
+    errors: &mut Vec<AsmLineError>,
+) -> Vec<LinePreprocessed> {
+    // each macro as a pseudo-instruction, with the line of each body line
+    let mut macros: Vec<(Pseudo, Vec<usize>)> = vec![];
+    let mut expanded = vec![];
+    let mut lines = lines.into_iter();
+
+    while let Some(line) = lines.next() {
+        let tokenized = line.tokenize();
+        if tokenized.mnenomic == ".macro" {
+            let Some(name) = tokenized.tokens.first() else {
+                errors.push(line.error_at(AsmError::InvalidPseudo(line.string.clone()), 1));
+                continue;
+            };
+            let mut body: Vec<LinePreprocessed> = vec![];
+            let mut terminated = false;
+            for l in lines.by_ref() {
+                if l.tokenize().mnenomic == ".endm" {
+                    terminated = true;
+                    break;
+                }
+                body.push(l);
+            }
+            if !terminated {
+                errors.push(line.error_at(AsmError::UnterminatedMacro(name.to_string()), 1));
+            }
+            let pseudo = Pseudo {
+                name: name.to_string(),
+                params: tokenized.tokens[1..]
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect(),
+                body: body.iter().map(|l| l.string.clone()).collect(),
+            };
+            macros.push((pseudo, body.iter().map(|l| l.lineno).collect()));
+            continue;
+        }
+
+        let found = macros
+            .iter()
+            .rev()
+            .find(|(m, _)| m.name == tokenized.mnenomic);
+        let Some((pseudo, defined)) = found else {
+            expanded.push(line);
+            continue;
+        };
+        match pseudo.expand(&tokenized.tokens) {
+            Ok(instrs) => expanded.extend(instrs.into_iter().zip(defined).map(|(string, n)| {
+                LinePreprocessed {
+                    string,
+                    lineno: line.lineno,
+                    in_macro: Some((pseudo.name.clone(), *n)),
+                    statement: line.statement,
+                    raw: false,
+                    spans: vec![],
+                    note: None,
+                }
+            })),
+            Err(e) => errors.push(line.error(e)),
+        }
+    }
+    expanded
+}
+
+/// A number in decimal, or in hex, binary or octal with a `0x`, `0b` or
+/// `0o` prefix or an `h`, `b` or `o` suffix. It may have a leading `+` or
+/// `-` and underscores between digits, e.g. `0b0001_1111` or `+1Fh`.
+pub(crate) fn parse_number(token: &str) -> Option<i32> {
+    let (negative, digits) = match token.as_bytes().first() {
+        Some(b'-') => (true, &token[1..]),
+        Some(b'+') => (false, &token[1..]),
+        _ => (false, token),
+    };
+
+    // a number always starts with a digit, so `bh` isn't one
+    if !digits.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let lower = digits.to_ascii_lowercase();
+    let (radix, digits) = if let Some(d) = lower.strip_prefix("0x") {
+        (16, d)
+    } else if let Some(d) = lower.strip_prefix("0b") {
+        (2, d)
+    } else if let Some(d) = lower.strip_prefix("0o") {
+        (8, d)
+    } else if let Some(d) = lower.strip_suffix('h') {
+        (16, d)
+    } else if let Some(d) = lower.strip_suffix('b') {
+        (2, d)
+    } else if let Some(d) = lower.strip_suffix('o') {
+        (8, d)
+    } else if let Some(d) = lower.strip_suffix('d') {
+        (10, d)
+    } else {
+        (10, lower.as_str())
+    };
+
+    if digits.starts_with('_') || digits.ends_with('_') {
+        return None;
+    }
+    let digits = digits.replace('_', "");
+    // from_str_radix accepts its own sign, which would allow `--1`
+    if !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    let value = i64::from_str_radix(&digits, radix).ok()?;
+    i32::try_from(if negative { -value } else { value }).ok()
+}
+
+/// Could `token` name a label or constant?
+fn is_identifier(token: &str) -> bool {
+    let mut chars = token.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+        && !is_register(token)
+}
+
+/// The error for an immediate that isn't a number, which is most likely a
+/// constant that was never defined if it looks like a name.
+fn invalid_immediate(imm: &str) -> AsmError {
+    match is_identifier(imm) {
+        true => AsmError::UndefinedConstant(imm.to_string()),
+        false => AsmError::InvalidImmediate(imm.to_string()),
+    }
+}
+
+/// The error for an operand that isn't a number where a label could also be,
+/// so a name is either a label or a constant that was never defined.
+fn undefined_symbol(imm: &str) -> AsmError {
+    match is_identifier(imm) {
+        true => AsmError::UndefinedSymbol(imm.to_string()),
+        false => AsmError::InvalidImmediate(imm.to_string()),
+    }
+}
+
+/// Pulls operands off a tokenized line, recording every bad operand rather
+/// than stopping at the first one. Errors are each in the operand they're
+/// about, see [`AsmError::InOperand`].
+struct Operands<'a, I: Iterator<Item = &'a str>> {
+    tokens: I,
+    /// How many operands of the line have been pulled off.
+    taken: usize,
+    errors: Vec<AsmError>,
+}
+
+impl<'a, I: Iterator<Item = &'a str>> Operands<'a, I> {
+    /// The next operand, and which it is or would be.
+    fn next(&mut self) -> (Option<&'a str>, usize) {
+        let at = self.taken;
+        let token = self.tokens.next();
+        self.taken += token.is_some() as usize;
+        (token, at)
+    }
+
+    fn imm<T, const N: u8>(&mut self) -> Option<Imm<T, N>>
+    where
+        T: ImmType<N> + Copy,
+    {
+        use AsmError::*;
+
+        let (token, at) = self.next();
+        let result = token.ok_or(MissingImmediate).and_then(|imm| {
+            let imm = imm.strip_prefix('#').unwrap_or(imm);
+
+            // Two steps are required since .parse<T> doesn't distinguish
+            // between invalid and out of range.
+            let val = parse_number(imm).ok_or_else(|| undefined_symbol(imm))?;
+            let v: T = val.try_into().or(Err(ImmediateOutOfRange(val)))?;
+            v.try_into().or(Err(ImmediateOutOfRange(val)))
+        });
+        result.map_err(|e| self.errors.push(e.in_operand(at))).ok()
+    }
+
+    fn reg(&mut self) -> Option<Reg> {
+        use AsmError::*;
+
+        let (token, at) = self.next();
+        let result = token
+            .ok_or(MissingRegister)
+            .and_then(|reg| reg.try_into().or(Err(InvalidRegister(reg.to_string()))));
+        result.map_err(|e| self.errors.push(e.in_operand(at))).ok()
+    }
+
+    /// An operand of a runtime-defined instruction.
+    fn spec(&mut self, spec: &OperandSpec) -> Option<i32> {
+        use AsmError::*;
+
+        let (min, max) = spec.range();
+        let (token, at) = self.next();
+        let result = match spec.kind {
+            OperandKind::Reg => token.ok_or(MissingRegister).and_then(|reg| {
+                reg.strip_prefix('r')
+                    .and_then(|n| n.parse::<i32>().ok())
+                    .filter(|n| (min..=max).contains(n))
+                    .ok_or(InvalidRegister(reg.to_string()))
+            }),
+            OperandKind::Signed | OperandKind::Unsigned => {
+                token.ok_or(MissingImmediate).and_then(|imm| {
+                    let imm = imm.strip_prefix('#').unwrap_or(imm);
+                    let val = parse_number(imm).ok_or_else(|| undefined_symbol(imm))?;
+                    match (min..=max).contains(&val) {
+                        true => Ok(val),
+                        false => Err(ImmediateOutOfRange(val)),
+                    }
+                })
+            }
+        };
+        result.map_err(|e| self.errors.push(e.in_operand(at))).ok()
+    }
+
+    /// A `width`-bit word of data, either unsigned or a negative two's
+    /// complement value. `None` once the tokens run out.
+    fn data_word(&mut self, width: u8) -> Option<u16> {
+        let (token, at) = self.next();
+        let token = token?;
+        let result = parse_number(token)
+            .ok_or_else(|| invalid_immediate(token))
+            .and_then(|val| data_word(val, width));
+        match result {
+            Ok(word) => Some(word),
+            Err(e) => {
+                self.errors.push(e.in_operand(at));
+                Some(0)
+            }
+        }
+    }
+
+    /// Record any leftover tokens and return all errors found on the line.
+    fn finish(mut self) -> Vec<AsmError> {
+        while let (Some(t), at) = self.next() {
+            self.errors
+                .push(AsmError::ExtraToken(t.to_string()).in_operand(at));
+        }
+        self.errors
+    }
+}
+
+/// How closely source must follow the textbook syntax.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Syntax {
+    /// Immediates need a `#`, operands are separated by single commas, and
+    /// mnemonics and registers must be written in their usual case.
+    Strict,
+    /// `#` and commas are optional, and mnemonics and registers may be
+    /// written in any case.
+    #[default]
+    Permissive,
+}
+
+fn parse_line<W: Word, I: InstructionSet<W>>(
+    line: &LinePreprocessed,
+    isa: &I,
+    syntax: Syntax,
+) -> Result<I::Instr, Vec<AsmError>> {
+    let tokenized = line.tokenize();
+
+    if line.raw {
+        let [word] = tokenized.tokens[..] else {
+            return Err(vec![AsmError::InvalidExpression(line.string.clone())]);
+        };
+        let value = parse_number(word).ok_or_else(|| vec![undefined_symbol(word).in_operand(0)])?;
+        let bits = isa.width() as u32;
+        if value < -(1 << (bits - 1)) || value >= 1 << bits {
+            return Err(vec![AsmError::ImmediateOutOfRange(value).in_operand(0)]);
+        }
+        return isa
+            .decode(W::truncate(value as u32))
+            .map_err(|_| vec![AsmError::NotInstruction(word.to_string()).in_operand(0)]);
+    }
+
+    if syntax == Syntax::Permissive {
+        let tokens: Vec<String> = tokenized
+            .tokens
+            .iter()
+            .map(|t| match is_register(t) {
+                true => t.to_ascii_lowercase(),
+                false => t.to_string(),
+            })
+            .collect();
+        let tokens: Vec<&str> = tokens.iter().map(String::as_str).collect();
+
+        // Only fall back to the usual upper case if the mnemonic is unknown
+        // as written, as a runtime-defined ISA may use lower case.
+        let result = isa.parse(tokenized.mnenomic, &tokens);
+        return match result {
+            Err(e) if matches!(e[..], [AsmError::InvalidMnenomic(_)]) => {
+                let upper = tokenized.mnenomic.to_ascii_uppercase();
+                match isa.parse(&upper, &tokens) {
+                    Err(retry) if matches!(retry[..], [AsmError::InvalidMnenomic(_)]) => Err(e),
+                    retry => retry,
+                }
+            }
+            result => result,
+        };
+    }
+
+    // the operands are still parsed so their errors are reported too
+    let mut errors = strict_errors(line, &tokenized);
+    match isa.parse(tokenized.mnenomic, &tokenized.tokens) {
+        Ok(instr) if errors.is_empty() => Ok(instr),
+        Ok(_) => Err(errors),
+        Err(e) => {
+            errors.extend(e);
+            Err(errors)
+        }
+    }
+}
+
+/// Breaks of the strict syntax: separators other than a single comma between
+/// operands, and immediates without a `#`.
+fn strict_errors(line: &LinePreprocessed, tokenized: &Tokenized) -> Vec<AsmError> {
+    let mut errors = vec![];
+
+    // the separator after each token, the one after operand n - 1 being
+    // where operand n is or would be
+    let ends: Vec<usize> = token_spans(&line.string)
+        .map(|(at, t)| at + t.len())
+        .collect();
+    let starts = token_spans(&line.string).skip(1).map(|(at, _)| at);
+    let separators = ends
+        .iter()
+        .zip(starts.chain([line.string.len()]))
+        .map(|(end, start)| line.string[*end..start].matches(',').count());
+    let last = ends.len().saturating_sub(1);
+    let unseparated = separators
+        .enumerate()
+        .position(|(n, commas)| commas != (n != 0 && n != last) as usize);
+    if let Some(n) = unseparated {
+        errors.push(AsmError::MissingComma.in_operand(n));
+    }
+
+    for (n, token) in tokenized.tokens.iter().enumerate() {
+        if !is_register(token) && !token.starts_with('#') {
+            errors.push(AsmError::MissingHash(token.to_string()).in_operand(n));
+        }
+    }
+    errors
+}
+
+/// Does the token look like a register, in any case?
+pub(crate) fn is_register(token: &str) -> bool {
+    token
+        .strip_prefix(['r', 'R'])
+        .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Parse an instruction of the built-in ISA.
+pub(crate) fn parse_op(mnemonic: &str, tokens: &[&str], isa: &Isa) -> Result<Op, Vec<AsmError>> {
+    if let Some(ext) = Op::extension_of(mnemonic) {
+        if !isa.has(ext) {
+            return Err(vec![AsmError::ExtensionDisabled(mnemonic.to_string(), ext)]);
+        }
+    }
+
+    let mut args = Operands {
+        tokens: tokens.iter().copied(),
+        taken: 0,
+        errors: vec![],
+    };
+
+    // Operands are all evaluated before being combined so that every bad
+    // operand on the line gets reported.
+    let op = match mnemonic {
+        "BR" => args.imm().map(Op::BR),
+        "BRZ" => args.imm().map(Op::BRZ),
+        "ADDI" => args.reg().zip(args.imm()).map(|(r, i)| Op::ADDI(r, i)),
+        "SUBI" => args.reg().zip(args.imm()).map(|(r, i)| Op::SUBI(r, i)),
+        "SR0" => args.imm().map(Op::SR0),
+        "SRH0" => args.imm().map(Op::SRH0),
+        "CLR" => args.reg().map(Op::CLR),
+        "MOVA" => args.reg().map(Op::MOVA),
+        "MOVR" => args.reg().map(Op::MOVR),
+        "MOVRHS" => args.reg().map(Op::MOVRHS),
+        "MOV" => args.reg().zip(args.reg()).map(|(d, s)| Op::MOV(d, s)),
+        "PAUSE" => Some(Op::PAUSE),
+        "AND" => args.reg().map(Op::AND),
+        "OR" => args.reg().map(Op::OR),
+        "XOR" => args.reg().map(Op::XOR),
+        "NOT" => args.reg().map(Op::NOT),
+        "LD" => args.reg().zip(args.reg()).map(|(d, a)| Op::LD(d, a)),
+        "ST" => args.reg().zip(args.reg()).map(|(s, a)| Op::ST(s, a)),
+        "IN" => args.reg().map(Op::IN),
+        "OUT" => args.reg().map(Op::OUT),
+        "SHL" => args.reg().map(Op::SHL),
+        "SHR" => args.reg().map(Op::SHR),
+        "ADD" => args.reg().zip(args.reg()).map(|(d, s)| Op::ADD(d, s)),
+        x => return Err(vec![AsmError::InvalidMnenomic(x.to_string())]),
+    };
+
+    let errors = args.finish();
+    match op {
+        Some(op) if errors.is_empty() => Ok(op),
+        _ => Err(errors),
+    }
+}
+
+/// Parse an instruction of a runtime-defined ISA into its instruction word.
+pub(crate) fn parse_spec(
+    mnemonic: &str,
+    tokens: &[&str],
+    spec: &IsaSpec,
+) -> Result<u32, Vec<AsmError>> {
+    let instr = match (spec.find(mnemonic), spec.renamed(mnemonic)) {
+        (Some(instr), _) => instr,
+        (None, Some(instr)) if spec.accepts_deprecated() => instr,
+        (None, Some(instr)) => {
+            return Err(vec![AsmError::Deprecated(
+                mnemonic.to_string(),
+                instr.mnemonic().to_string(),
+            )])
+        }
+        (None, None) => return Err(vec![AsmError::InvalidMnenomic(mnemonic.to_string())]),
+    };
+
+    let mut args = Operands {
+        tokens: tokens.iter().copied(),
+        taken: 0,
+        errors: vec![],
+    };
+    let values: Vec<Option<i32>> = instr.operands().iter().map(|o| args.spec(o)).collect();
+
+    let errors = args.finish();
+    match values.into_iter().collect::<Option<Vec<i32>>>() {
+        Some(values) if errors.is_empty() => Ok(instr.encode(&values)),
+        _ => Err(errors),
+    }
+}
+
+/// Split raw file contents into numbered lines, stripping a leading UTF-8 BOM
+/// and CRLF line endings. Lines that aren't valid UTF-8 are reported as errors
+/// rather than dropped.
+fn split_lines(contents: &[u8]) -> Vec<Result<Line, AsmLineError>> {
+    let contents = contents.strip_prefix(b"\xef\xbb\xbf").unwrap_or(contents);
+
+    contents
+        .split(|b| *b == b'\n')
+        .enumerate()
+        .map(|(n, l)| {
+            let lineno = n + 1; // file lineno start at 1
+            let l = l.strip_suffix(b"\r").unwrap_or(l);
+            match String::from_utf8(l.to_vec()) {
+                Ok(string) => Ok(Line { string, lineno }),
+                Err(_) => Err(AsmError::InvalidUtf8.on_line(lineno)),
+            }
+        })
+        .collect()
+}
+
+/// The name and value tokens of a constant definition, `NAME EQU value` or
+/// `.equ NAME, value`.
+fn constant_definition<'a, 'b>(tokenized: &'b Tokenized<'a>) -> Option<(&'a str, &'b [&'a str])> {
+    match (tokenized.mnenomic, &tokenized.tokens[..]) {
+        (".equ", [name, value @ ..]) => Some((*name, value)),
+        (".equ", []) => Some(("", &[][..])),
+        (name, [equ, value @ ..]) if equ.eq_ignore_ascii_case("EQU") => Some((name, value)),
+        _ => None,
+    }
+}
+
+/// A number, the value of one of `constants`, or an expression of them,
+/// optionally after a `#`.
+fn constant_value(token: &str, constants: &[(String, i32)]) -> Result<i32, AsmError> {
+    let token = token.strip_prefix('#').unwrap_or(token);
+    let lookup = |t: &str| {
+        parse_number(t).or_else(|| constants.iter().find(|(c, _)| c == t).map(|(_, v)| *v))
+    };
+    if !expr::is_expression(token) {
+        return lookup(token).ok_or_else(|| invalid_immediate(token));
+    }
+    expr::eval(token, lookup).map_err(|e| match e {
+        ExprError::Undefined(name) => invalid_immediate(&name),
+        ExprError::Invalid => AsmError::InvalidExpression(token.to_string()),
+    })
+}
+
+/// Remove lines of only commas, which separate nothing, reporting each at
+/// its first comma.
+fn reject_commas(
+    lines: Vec<LinePreprocessed>,
+    errors: &mut Vec<AsmLineError>,
+) -> Vec<LinePreprocessed> {
+    lines
+        .into_iter()
+        .filter(|line| {
+            let empty = line.tokenize().mnenomic.is_empty();
+            if empty {
+                let e = AsmError::ExtraToken(",".to_string());
+                let e = match line.statement {
+                    Some(n) => AsmError::InStatement(Box::new(e), n),
+                    None => e,
+                };
+                errors.push(line.error_at(e, 0));
+            }
+            !empty
+        })
+        .collect()
+}
+
+/// Remove `.allow <warning>, ...` directives, returning the warnings they
+/// silence throughout the file.
+fn take_allows(
+    lines: Vec<LinePreprocessed>,
+    errors: &mut Vec<AsmLineError>,
+) -> (Vec<LinePreprocessed>, Vec<String>) {
+    let mut allowed = vec![];
+    let mut rest = vec![];
+
+    for line in lines {
+        let tokenized = line.tokenize();
+        if tokenized.mnenomic != ".allow" {
+            rest.push(line);
+            continue;
+        }
+        if tokenized.tokens.is_empty() {
+            errors.push(line.error_at(AsmError::UnknownWarning(String::new()), 1));
+        }
+        for (n, name) in tokenized.tokens.iter().enumerate() {
+            match AsmWarning::NAMES.contains(name) {
+                true => allowed.push(name.to_string()),
+                false => {
+                    errors.push(line.error_at(AsmError::UnknownWarning(name.to_string()), n + 1))
+                }
+            }
+        }
+    }
+    (rest, allowed)
+}
+
+/// The warnings silenced on single lines by a `#allow(<warning>, ...)` in
+/// their comment, e.g. `BR 0 ;#allow(no_effect)`, with the line of each.
+fn line_allows(contents: &[u8], errors: &mut Vec<AsmLineError>) -> Vec<(usize, String)> {
+    let mut allowed = vec![];
+    for line in split_lines(contents).into_iter().flatten() {
+        let comment = &line.string[comment_start(&line.string)..];
+        let Some((_, names)) = comment.split_once("#allow(") else {
+            continue;
+        };
+        let names = names.split_once(')').map_or(names, |(names, _)| names);
+        for name in names.split(',').map(str::trim) {
+            match AsmWarning::NAMES.contains(&name) {
+                true => allowed.push((line.lineno, name.to_string())),
+                false => {
+                    errors.push(AsmError::UnknownWarning(name.to_string()).on_line(line.lineno))
+                }
+            }
+        }
+    }
+    allowed
+}
+
+/// Keep only the lines in the taken branch of each `.if <condition>`,
+/// `.ifdef NAME` or `.ifndef NAME` block, up to its `.else` or `.endif`.
+/// Conditions can use `defines` and constants defined on earlier kept lines.
+/// A condition is a value, true if it isn't 0, or two values compared with
+/// `==`, `!=`, `<`, `<=`, `>` or `>=`.
+fn take_conditionals(
+    lines: Vec<LinePreprocessed>,
+    defines: &[(String, i32)],
+    errors: &mut Vec<AsmLineError>,
+) -> Vec<LinePreprocessed> {
+    let mut constants = defines.to_vec();
+    // the open blocks: the `.if` line, whether its condition held, and
+    // whether its `.else` was reached
+    let mut blocks: Vec<(usize, bool, bool)> = vec![];
+    let mut kept = vec![];
+
+    for line in lines {
+        let tokenized = line.tokenize();
+        let active = blocks.iter().all(|(_, taken, in_else)| taken != in_else);
+        match (tokenized.mnenomic, &tokenized.tokens[..]) {
+            (".if" | ".ifdef" | ".ifndef", tokens) => {
+                // conditions in skipped blocks are never checked
+                let taken = match active {
+                    true => condition(tokenized.mnenomic, tokens, &constants),
+                    false => Ok(false),
+                };
+                let taken = taken.unwrap_or_else(|e| {
+                    errors.push(line.error(e));
+                    false
+                });
+                blocks.push((line.lineno, taken, false));
+            }
+            (".else", _) => match blocks.last_mut() {
+                Some((_, _, in_else @ false)) => *in_else = true,
+                _ => errors.push(line.error(AsmError::UnmatchedDirective(".else".to_string()))),
+            },
+            (".endif", _) => {
+                if blocks.pop().is_none() {
+                    errors.push(line.error(AsmError::UnmatchedDirective(".endif".to_string())));
+                }
+            }
+            _ if !active => (),
+            _ => {
+                // invalid definitions are reported by `take_constants`
+                if let Some((name, [value])) = constant_definition(&tokenized) {
+                    if let Ok(value) = constant_value(value, &constants) {
+                        constants.push((name.to_string(), value));
+                    }
+                }
+                kept.push(line);
+            }
+        }
     }
+
+    for (lineno, ..) in blocks {
+        errors.push(AsmError::UnterminatedIf.on_line(lineno));
+    }
+    kept
+}
+
+/// Whether the condition of an `.if`, `.ifdef` or `.ifndef` holds.
+fn condition(
+    directive: &str,
+    tokens: &[&str],
+    constants: &[(String, i32)],
+) -> Result<bool, AsmError> {
+    let value = |t: &str| constant_value(t, constants);
+    let defined = |name: &str| constants.iter().any(|(c, _)| c == name);
+    match (directive, tokens) {
+        (_, []) => Err(AsmError::MissingImmediate),
+        (".ifdef", [name]) => Ok(defined(name)),
+        (".ifndef", [name]) => Ok(!defined(name)),
+        (".ifdef" | ".ifndef", [_, extra, ..]) => Err(AsmError::ExtraToken(extra.to_string())),
+        (_, [a]) => Ok(value(a)? != 0),
+        (_, [a, op, b]) => {
+            let (a, b) = (value(a)?, value(b)?);
+            match *op {
+                "==" => Ok(a == b),
+                "!=" => Ok(a != b),
+                "<" => Ok(a < b),
+                "<=" => Ok(a <= b),
+                ">" => Ok(a > b),
+                ">=" => Ok(a >= b),
+                _ => Err(AsmError::InvalidCondition(tokens.join(" "))),
+            }
+        }
+        _ => Err(AsmError::InvalidCondition(tokens.join(" "))),
+    }
+}
+
+/// Remove constant definitions, `NAME EQU value` or `.equ NAME, value`, and
+/// replace operands naming a constant with its value, keeping any `#`. A
+/// value may be a constant defined before it, and constants may be used
+/// before they are defined. `defines` are constants set from outside the
+/// source, e.g. on the command line. Expressions using labels are left for
+/// [`take_labels`], so the constants are returned for it too.
+fn take_constants(
+    lines: Vec<LinePreprocessed>,
+    defines: &[(String, i32)],
+    errors: &mut Vec<AsmLineError>,
+) -> (Vec<LinePreprocessed>, Vec<(String, i32)>) {
+    let mut constants: Vec<(String, i32)> = defines.to_vec();
+    // where each constant in the source was defined
+    let mut defined: Vec<(String, usize)> = vec![];
+    let mut rest = vec![];
+    let labels: Vec<(String, usize)> = lines
+        .iter()
+        .filter_map(|l| Some((l.string.strip_suffix(':')?.to_string(), l.lineno)))
+        .collect();
+
+    for line in lines {
+        let tokenized = line.tokenize();
+        let Some((name, value)) = constant_definition(&tokenized) else {
+            rest.push(line);
+            continue;
+        };
+
+        // the tokens of the name and the value
+        let named = (tokenized.mnenomic == ".equ") as usize;
+        let value = match value {
+            [] => Err((AsmError::MissingImmediate, tokenized.tokens.len() + 1)),
+            [value] => constant_value(value, &constants).map_err(|e| (e, 2)),
+            [_, extra, ..] => Err((AsmError::ExtraToken(extra.to_string()), 3)),
+        };
+        let other = defined.iter().chain(&labels).find(|(c, _)| c == name);
+        let result = match (value, other) {
+            _ if !is_identifier(name) => Err((AsmError::InvalidConstant(name.to_string()), named)),
+            (_, Some((_, other))) => Err((AsmError::Redefined(name.to_string(), *other), named)),
+            _ if constants.iter().any(|(c, _)| c == name) => {
+                Err((AsmError::DuplicateSymbol(name.to_string()), named))
+            }
+            (Ok(value), _) => Ok((name.to_string(), value)),
+            (Err(e), _) => Err(e),
+        };
+        match result {
+            Ok(constant) => {
+                defined.push((constant.0.clone(), line.lineno));
+                constants.push(constant);
+            }
+            // a label defined later is the redefinition
+            Err((AsmError::Redefined(name, other), _)) if other > line.lineno => {
+                errors.push(AsmError::Redefined(name, line.lineno).on_line(other))
+            }
+            Err((e, at)) => errors.push(line.error_at(e, at)),
+        }
+    }
+
+    let mut substituted = vec![];
+    let mut in_data = false;
+    for mut line in rest {
+        let tokenized = line.tokenize();
+        match tokenized.mnenomic {
+            ".data" => in_data = true,
+            ".text" => in_data = false,
+            _ => {}
+        }
+        // a name that isn't a constant could have been meant as a label, except
+        // in the data memory
+        let labelled = match tokenized.mnenomic {
+            ".byte" | ".fill" | ".space" => !in_data,
+            mnemonic => !mnemonic.starts_with('.') || mnemonic == ".entry",
+        };
+        // constants and expressions become numbers, anything else is kept
+        let value = |t: &str| {
+            let (prefix, name) = match (t.strip_prefix('#'), t.strip_prefix('@')) {
+                (Some(name), _) => ("#", name),
+                (_, Some(name)) => ("@", name),
+                _ => ("", t),
+            };
+            let known = constants.iter().any(|(c, _)| c == name);
+            if !known && !expr::is_expression(name) {
+                return None;
+            }
+            match constant_value(name, &constants) {
+                // left for `take_labels`
+                Err(AsmError::InvalidImmediate(n)) if n == "$" => None,
+                Err(AsmError::UndefinedConstant(n)) if labels.iter().any(|(l, _)| *l == n) => None,
+                Err(AsmError::UndefinedConstant(n)) if labelled => {
+                    Some(Err(AsmError::UndefinedSymbol(n)))
+                }
+                value => Some(value.map(|v| format!("{prefix}{v}"))),
+            }
+        };
+        if !tokenized.tokens.iter().any(|t| value(t).is_some()) {
+            substituted.push(line);
+            continue;
+        }
+        let mut operands = vec![];
+        let mut failed = false;
+        for (n, t) in tokenized.tokens.iter().enumerate() {
+            match value(t) {
+                Some(Ok(v)) => operands.push(v),
+                Some(Err(e)) => {
+                    errors.push(line.error_at(e, n + 1));
+                    failed = true;
+                }
+                None => operands.push(t.to_string()),
+            }
+        }
+        // a line with a bad value is already reported, so isn't parsed
+        if !failed {
+            line.string = format!("{} {}", tokenized.mnenomic, operands.join(", "));
+            substituted.push(line);
+        }
+    }
+    (substituted, constants)
 }
 
-struct AsmLineError(AsmError, usize);
+/// Replace each `LDI rX, value` with the shortest `CLR`, `ADDI` and `SUBI`
+/// sequence leaving the byte `value` in `rX`, counting up from 0 or down
+/// from 256 since registers wrap around. Each step is as large as the ISA's
+/// immediates allow. Runs once constants are known, since the length
+/// depends on the value. An ISA without `ADDI` and `SUBI` has no `LDI`.
+fn expand_ldi<W: Word, I: InstructionSet<W>>(
+    lines: Vec<LinePreprocessed>,
+    isa: &I,
+    errors: &mut Vec<AsmLineError>,
+) -> Vec<LinePreprocessed> {
+    let mut expanded = vec![];
+    let mut steps = None;
+
+    for line in lines {
+        let tokenized = line.tokenize();
+        if tokenized.mnenomic != "LDI" {
+            expanded.push(line);
+            continue;
+        }
+        let (up, down) =
+            *steps.get_or_insert_with(|| (immediate_max(isa, "ADDI"), immediate_max(isa, "SUBI")));
+        if up == 0 || down == 0 {
+            expanded.push(line);
+            continue;
+        }
+
+        let mut args = Operands {
+            tokens: tokenized.tokens.into_iter(),
+            taken: 0,
+            errors: vec![],
+        };
+        let reg = args.reg();
+        let value = args.data_word(8);
+        if reg.is_some() && value.is_none() {
+            args.errors.push(AsmError::MissingImmediate.in_operand(1));
+        }
+        let line_errors = args.finish();
+        let (Some(reg), Some(value), true) = (reg, value, line_errors.is_empty()) else {
+            errors.extend(line_errors.into_iter().map(|e| line.error(e)));
+            continue;
+        };
+
+        let (mnemonic, distance, max) = match value {
+            0..=127 => ("ADDI", value as usize, up),
+            _ => ("SUBI", 256 - value as usize, down),
+        };
+        let count = 1 + distance.div_ceil(max);
+        let note = match count {
+            1 => format!("{}: 1 instruction", line.string),
+            n => format!("{}: {n} instructions", line.string),
+        };
+        expanded.push(LinePreprocessed {
+            note: Some(note),
+            ..line.derive(format!("CLR {reg}"))
+        });
+        for step in (0..distance).step_by(max) {
+            let step = max.min(distance - step);
+            expanded.push(line.derive(format!("{mnemonic} {reg}, {step}")));
+        }
+    }
+    expanded
+}
+
+/// The largest immediate, up to 255, that `mnemonic r0, n` accepts in `isa`,
+/// or 0 if it has no such instruction.
+fn immediate_max<W: Word, I: InstructionSet<W>>(isa: &I, mnemonic: &str) -> usize {
+    let values: Vec<usize> = (1..=255).collect();
+    values.partition_point(|n| isa.parse(mnemonic, &["r0", &n.to_string()]).is_ok())
+}
+
+/// Replace each `JMP target` with `BR @target`, so the offset to a label or
+/// absolute address is worked out by the assembler. A target out of the
+/// branch's range is reported like any other branch.
+fn expand_jmp(
+    lines: Vec<LinePreprocessed>,
+    errors: &mut Vec<AsmLineError>,
+) -> Vec<LinePreprocessed> {
+    let mut expanded = vec![];
+
+    for line in lines {
+        let tokenized = line.tokenize();
+        if tokenized.mnenomic != "JMP" {
+            expanded.push(line);
+            continue;
+        }
+        match tokenized.tokens[..] {
+            [] => errors.push(line.error_at(AsmError::MissingImmediate, 1)),
+            [target] => {
+                let target = target.strip_prefix('@').unwrap_or(target);
+                expanded.push(LinePreprocessed {
+                    spans: line.spans.clone(),
+                    ..line.derive(format!("BR @{target}"))
+                });
+            }
+            [_, extra, ..] => {
+                errors.push(line.error_at(AsmError::ExtraToken(extra.to_string()), 2))
+            }
+        }
+    }
+    expanded
+}
+
+/// `value` as a `width`-bit word, either unsigned or a negative two's
+/// complement value.
+fn data_word(value: i32, width: u8) -> Result<u16, AsmError> {
+    let bits = width.clamp(1, 16) as u32;
+    match value >= -(1 << (bits - 1)) && value < 1 << bits {
+        true => Ok((value as u32 & ((1 << bits) - 1)) as u16),
+        false => Err(AsmError::ImmediateOutOfRange(value)),
+    }
+}
+
+/// Split off the `.data` section. Lines after `.data` up to the next `.text`
+/// are `.byte` directives listing values for the data memory, `.fill <count>,
+/// <value>` and `.space <count>` blocks of values or zeros, or `.align`
+/// padding it with zeros. Elsewhere these place their values in program
+/// memory as raw words. A block can't be longer than the memory it's in,
+/// `depth` words of program memory or `data_depth` of data memory, and data
+/// values must fit in `data_width` bits.
+fn take_data(
+    lines: Vec<LinePreprocessed>,
+    depth: usize,
+    data_depth: usize,
+    data_width: u8,
+    errors: &mut Vec<AsmLineError>,
+) -> (Vec<LinePreprocessed>, Vec<u16>) {
+    let mut in_data = false;
+    let mut data = vec![];
+    let mut text = vec![];
+
+    for line in lines {
+        let tokenized = line.tokenize();
+        match (tokenized.mnenomic, in_data) {
+            (".data", _) => in_data = true,
+            (".text", _) => in_data = false,
+            // raw words in program memory, checked when parsed
+            (".byte", false) => {
+                for (n, token) in tokenized.tokens.into_iter().enumerate() {
+                    // labels and expressions of them are evaluated once
+                    // labels are known
+                    if expr::is_expression(token) || is_identifier(token) {
+                        text.push(line.raw_word(token, Some(n + 1)));
+                        continue;
+                    }
+                    let mut args = Operands {
+                        tokens: vec![token].into_iter(),
+                        taken: n,
+                        errors: vec![],
+                    };
+                    if let Some(byte) = args.data_word(8) {
+                        text.push(line.raw_word(&format!("{byte:#04x}"), Some(n + 1)));
+                    }
+                    errors.extend(args.errors.into_iter().map(|e| line.error(e)));
+                }
+            }
+            // the value is checked against the word size when parsed, and
+            // labels and expressions of them are evaluated once labels are known
+            (".fill" | ".space", false) => match block(&tokenized).and_then(|(count, value)| {
+                let deferred = expr::is_expression(value) || is_identifier(value);
+                match deferred || parse_number(value).is_some() {
+                    _ if count > depth => Err(AsmError::PastDepth(count, depth)),
+                    true => Ok((count, value)),
+                    false => Err(invalid_immediate(value).in_operand(1)),
+                }
+            }) {
+                Ok((count, value)) => {
+                    // `.space` has no value written
+                    let token = (tokenized.tokens.len() == 2).then_some(2);
+                    text.extend((0..count).map(|_| line.raw_word(value, token)))
+                }
+                Err(e) => errors.push(line.error(e)),
+            },
+            (_, false) => text.push(line),
+            (".byte", true) => {
+                let mut args = Operands {
+                    tokens: tokenized.tokens.into_iter(),
+                    taken: 0,
+                    errors: vec![],
+                };
+                while let Some(word) = args.data_word(data_width) {
+                    data.push(word);
+                }
+                errors.extend(args.errors.into_iter().map(|e| line.error(e)));
+            }
+            (".fill" | ".space", true) => {
+                let word = block(&tokenized).and_then(|(count, value)| {
+                    if data.len() + count > data_depth {
+                        return Err(AsmError::PastDepth(data.len() + count, data_depth));
+                    }
+                    let mut args = Operands {
+                        tokens: vec![value].into_iter(),
+                        taken: 1,
+                        errors: vec![],
+                    };
+                    let word = args.data_word(data_width);
+                    args.errors
+                        .into_iter()
+                        .next()
+                        .map_or(Ok((count, word)), Err)
+                });
+                match word {
+                    Ok((count, word)) => data.extend((0..count).map(|_| word.unwrap_or_default())),
+                    Err(e) => errors.push(line.error(e)),
+                }
+            }
+            (".align", true) => {
+                let result = match tokenized.tokens[..] {
+                    [] => Err(AsmError::MissingImmediate),
+                    [n] => parse_number(n)
+                        .ok_or_else(|| invalid_immediate(n))
+                        .and_then(|n| alignment(n).ok_or(AsmError::InvalidAlignment(n))),
+                    [_, extra, ..] => Err(AsmError::ExtraToken(extra.to_string())),
+                };
+                match result {
+                    Ok(n) => data.resize(data.len().next_multiple_of(n), 0),
+                    Err(e @ AsmError::ExtraToken(_)) => errors.push(line.error_at(e, 2)),
+                    Err(e) => errors.push(line.error_at(e, 1)),
+                }
+            }
+            (x, true) => errors.push(line.error(AsmError::NotData(x.to_string()))),
+        }
+    }
+    (text, data)
+}
+
+/// The count and value of a `.fill <count>, <value>` or `.space <count>`
+/// block, whose value is 0.
+fn block<'a>(tokenized: &Tokenized<'a>) -> Result<(usize, &'a str), AsmError> {
+    let (count, value) = match (tokenized.mnenomic, &tokenized.tokens[..]) {
+        (_, []) => return Err(AsmError::MissingImmediate.in_operand(0)),
+        (".fill", [_]) => return Err(AsmError::MissingImmediate.in_operand(1)),
+        (".fill", [count, value]) => (*count, *value),
+        (_, [count]) => (*count, "0"),
+        (".fill", [_, _, extra, ..]) => {
+            return Err(AsmError::ExtraToken(extra.to_string()).in_operand(2))
+        }
+        (_, [_, extra, ..]) => return Err(AsmError::ExtraToken(extra.to_string()).in_operand(1)),
+    };
+    let count = parse_number(count).ok_or_else(|| invalid_immediate(count).in_operand(0))?;
+    let count =
+        usize::try_from(count).map_err(|_| AsmError::ImmediateOutOfRange(count).in_operand(0))?;
+    Ok((count, value))
+}
+
+/// Remove a `.entry <target>` directive, returning its line for
+/// [`take_labels`] to resolve. The target is an instruction index counted
+/// from 0, or a label or expression of them, which is rewritten as the
+/// absolute target it names, e.g. `.entry @start`.
+fn take_entry(
+    lines: Vec<LinePreprocessed>,
+    errors: &mut Vec<AsmLineError>,
+) -> (Vec<LinePreprocessed>, Option<LinePreprocessed>) {
+    let mut entry = None;
+    let mut rest = vec![];
+
+    for mut line in lines {
+        let tokenized = line.tokenize();
+        if tokenized.mnenomic != ".entry" {
+            rest.push(line);
+            continue;
+        }
+        if entry.is_some() {
+            errors.push(line.error_at(AsmError::DuplicateEntry, 0));
+            continue;
+        }
+
+        let result = match tokenized.tokens[..] {
+            [] => Err(AsmError::MissingImmediate),
+            [target] => match parse_number(target) {
+                Some(n) if n < 0 => Err(AsmError::InvalidEntry(target.to_string())),
+                Some(_) => Ok(target.to_string()),
+                None => Ok(format!("@{}", target.strip_prefix('@').unwrap_or(target))),
+            },
+            [_, extra, ..] => Err(AsmError::ExtraToken(extra.to_string())),
+        };
+        match result {
+            Ok(target) => {
+                line.string = format!(".entry {target}");
+                entry = Some(line);
+            }
+            Err(e @ AsmError::ExtraToken(_)) => errors.push(line.error_at(e, 2)),
+            Err(e) => errors.push(line.error_at(e, 1)),
+        }
+    }
+    (rest, entry)
+}
+
+/// The address a `.entry` line resolved by [`take_labels`] starts at, `None`
+/// if its target isn't one. An instruction index is counted from `base`,
+/// past the memory `gaps` skip before it.
+fn entry_address(line: &LinePreprocessed, base: usize, gaps: &[Gap]) -> Option<usize> {
+    let tokenized = line.tokenize();
+    let target = tokenized.tokens.first()?;
+    match target.strip_prefix('@') {
+        Some(address) => parse_number(address).and_then(|a| usize::try_from(a).ok()),
+        None => parse_number(target)
+            .and_then(|n| usize::try_from(n).ok())
+            .map(|n| base + n + gap_words(gaps, n)),
+    }
+}
+
+/// Remove `.global <name>` and `.extern <name>` directives. A global exports
+/// the instruction after it. Operands naming either kind of symbol become
+/// relocations for the linker, and are assembled as 0 until then.
+fn take_symbols(
+    lines: Vec<LinePreprocessed>,
+    errors: &mut Vec<AsmLineError>,
+) -> (Vec<LinePreprocessed>, Symbols) {
+    let mut symbols = Symbols::default();
+    let mut names: Vec<(String, usize)> = vec![];
+    let mut rest = vec![];
+
+    for line in lines {
+        let tokenized = line.tokenize();
+        let directive = tokenized.mnenomic;
+        if directive != ".global" && directive != ".extern" {
+            rest.push(line);
+            continue;
+        }
+
+        let name = match tokenized.tokens[..] {
+            [] => Err(AsmError::MissingImmediate),
+            [name] => match names.iter().find(|(n, _)| n == name) {
+                Some((_, first)) => Err(AsmError::Redefined(name.to_string(), *first)),
+                None => Ok(name.to_string()),
+            },
+            [_, extra, ..] => Err(AsmError::ExtraToken(extra.to_string())),
+        };
+        match name {
+            Ok(name) if directive == ".global" => {
+                symbols.globals.push((name.clone(), rest.len()));
+                names.push((name, line.lineno));
+            }
+            Ok(name) => names.push((name, line.lineno)),
+            Err(e @ AsmError::ExtraToken(_)) => errors.push(line.error_at(e, 2)),
+            Err(e) => errors.push(line.error_at(e, 1)),
+        }
+    }
+
+    for (index, line) in rest.iter_mut().enumerate() {
+        let tokenized = line.tokenize();
+        let Some(name) = tokenized.tokens.iter().find(|t| {
+            names
+                .iter()
+                .any(|(n, _)| n == t.strip_prefix('@').unwrap_or(t))
+        }) else {
+            continue;
+        };
+        let name = name.strip_prefix('@').unwrap_or(name);
+        symbols.relocations.push((index, name.to_string()));
+        let operands: Vec<&str> = tokenized
+            .tokens
+            .iter()
+            .map(|t| match t.strip_prefix('@').unwrap_or(t) == name {
+                true => "#0",
+                false => t,
+            })
+            .collect();
+        line.string = format!("{} {}", tokenized.mnenomic, operands.join(", "));
+    }
+    (rest, symbols)
+}
+
+/// Memory skipped by `.org` or `.align`, before the line at index `at`.
+struct Gap {
+    at: usize,
+    len: usize,
+    /// The word filling the skipped memory, which decodes as an instruction.
+    fill: i32,
+}
+
+/// Words skipped by `gaps` before the line at `index`.
+fn gap_words(gaps: &[Gap], index: usize) -> usize {
+    gaps.iter().filter(|g| g.at <= index).map(|g| g.len).sum()
+}
+
+/// Remove each `.org <address>[, <fill>]`, skipping memory up to `address`
+/// so the next instruction is placed there, and each `.align <n>[, <fill>]`,
+/// skipping up to a multiple of `n`. The skipped memory is filled with the
+/// instruction encoded by `fill`, 0 by default, once the program is parsed.
+/// `base` is the address of the first instruction, and nothing can be
+/// placed from `depth` on. The symbols' indices are updated for the removed
+/// lines.
+fn take_org<W: Word, I: InstructionSet<W>>(
+    lines: Vec<LinePreprocessed>,
+    isa: &I,
+    base: usize,
+    depth: usize,
+    symbols: &mut Symbols,
+    errors: &mut Vec<AsmLineError>,
+) -> (Vec<LinePreprocessed>, Vec<Gap>) {
+    let mut address = base;
+    let mut rest = vec![];
+    let mut gaps = vec![];
+    // where each line, and the end, moves to once directives are removed
+    let mut moved = vec![];
+
+    for line in lines {
+        moved.push(rest.len());
+        let tokenized = line.tokenize();
+        if tokenized.mnenomic != ".org" && tokenized.mnenomic != ".align" {
+            // directives and labels aren't placed in memory
+            let placed = !line.string.starts_with('.') || line.raw;
+            if placed && !line.string.ends_with(':') {
+                address += 1;
+            }
+            rest.push(line);
+            continue;
+        }
+
+        let number = |t: &str| {
+            let t = t.strip_prefix('#').unwrap_or(t);
+            parse_number(t).ok_or_else(|| invalid_immediate(t))
+        };
+        let org = match tokenized.tokens[..] {
+            [] => Err(AsmError::MissingImmediate.in_operand(0)),
+            [_, _, extra, ..] => Err(AsmError::ExtraToken(extra.to_string()).in_operand(2)),
+            [target, ref fill @ ..] => number(target)
+                .and_then(|target| match tokenized.mnenomic {
+                    ".align" => alignment(target)
+                        .map(|n| address.next_multiple_of(n))
+                        .ok_or(AsmError::InvalidAlignment(target)),
+                    _ => usize::try_from(target).map_err(|_| AsmError::ImmediateOutOfRange(target)),
+                })
+                .map_err(|e| e.in_operand(0))
+                .and_then(|target| {
+                    let fill = fill.first().map_or(Ok(0), |f| number(f));
+                    let fill = fill.and_then(|fill| match isa.decode(W::truncate(fill as u32)) {
+                        Ok(_) => Ok(fill),
+                        Err(_) => Err(AsmError::NotInstruction(fill.to_string())),
+                    });
+                    fill.map(|fill| (target, fill)).map_err(|e| e.in_operand(1))
+                }),
+        };
+        match org {
+            Ok((target, _)) if target < address => {
+                errors.push(line.error_at(AsmError::OrgOverlap(target, address), 1))
+            }
+            Ok((target, _)) if target > depth => {
+                errors.push(line.error_at(AsmError::PastDepth(target, depth), 1))
+            }
+            Ok((target, _)) if target == address => {}
+            Ok((target, fill)) => {
+                gaps.push(Gap {
+                    at: rest.len(),
+                    len: target - address,
+                    fill,
+                });
+                address = target;
+            }
+            Err(e) => errors.push(line.error(e)),
+        }
+    }
+    moved.push(rest.len());
+    symbols.globals.iter_mut().for_each(|(_, n)| *n = moved[*n]);
+    symbols
+        .relocations
+        .iter_mut()
+        .for_each(|(n, _)| *n = moved[*n]);
+    (rest, gaps)
+}
+
+/// The boundary for `.align <n>`, which must be a power of two.
+fn alignment(n: i32) -> Option<usize> {
+    usize::try_from(n).ok().filter(|n| n.is_power_of_two())
+}
+
+/// Move a label at the start of a line, e.g. `loop: SUBI r0, 1`, to a line
+/// of its own, so later passes see the instruction's mnemonic first.
+fn split_labels(lines: Vec<LinePreprocessed>) -> Vec<LinePreprocessed> {
+    lines
+        .into_iter()
+        .flat_map(|line| match line.string.split_once(':') {
+            Some((label, rest))
+                if !label.is_empty()
+                    && !label.starts_with('.')
+                    && !label.contains(char::is_whitespace) =>
+            {
+                let at = label.len() + 1;
+                let mut split = vec![line.part(0, &line.string[..at])];
+                if !rest.trim().is_empty() {
+                    split.push(line.part(at, rest));
+                }
+                split
+            }
+            _ => vec![line],
+        })
+        .collect()
+}
+
+/// Remove `<name>:` labels, rewriting operands naming them as absolute
+/// branch targets, e.g. `BR loop` as `BR @3`. Either may come first, so
+/// labels are collected before any are replaced. `base` is the address of
+/// the first line, and `gaps` the memory skipped before others. The symbols'
+/// and gaps' indices are updated for the removed lines.
+/// Expressions left by [`take_constants`] are evaluated with each label as
+/// its address, e.g. `BR END-START`, and `$` as the line's own address.
+///
+/// A label that is a number, e.g. `1:`, is local and can be defined many
+/// times. `1b` names the nearest `1:` at or before the line using it, and
+/// `1f` the nearest one after it.
+fn take_labels(
+    lines: Vec<LinePreprocessed>,
+    constants: &[(String, i32)],
+    symbols: &mut Symbols,
+    gaps: &mut [Gap],
+    base: usize,
+    errors: &mut Vec<AsmLineError>,
+) -> Vec<LinePreprocessed> {
+    let mut labels: Vec<(String, usize)> = vec![];
+    // where each label was defined
+    let mut defined: Vec<usize> = vec![];
+    let mut locals: Vec<(String, usize)> = vec![];
+    let mut rest = vec![];
+    // where each line, and the end, moves to once labels are removed
+    let mut moved = vec![];
+
+    for line in lines {
+        moved.push(rest.len());
+        let Some(name) = line.string.strip_suffix(':') else {
+            rest.push(line);
+            continue;
+        };
+        if !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()) {
+            locals.push((name.to_string(), rest.len()));
+        } else if !is_identifier(name) {
+            errors.push(line.error_at(AsmError::InvalidLabel(name.to_string()), 0));
+        } else if let Some(n) = labels.iter().position(|(l, _)| l == name) {
+            let e = AsmError::Redefined(name.to_string(), defined[n]);
+            errors.push(line.error_at(e, 0));
+        } else {
+            labels.push((name.to_string(), rest.len()));
+            defined.push(line.lineno);
+        }
+    }
+    moved.push(rest.len());
+    symbols.globals.iter_mut().for_each(|(_, n)| *n = moved[*n]);
+    symbols
+        .relocations
+        .iter_mut()
+        .for_each(|(n, _)| *n = moved[*n]);
+    gaps.iter_mut().for_each(|g| g.at = moved[g.at]);
+    let at = |n: usize| base + n + gap_words(gaps, n);
+
+    let mut replaced = vec![];
+    for (index, mut line) in rest.into_iter().enumerate() {
+        let raw = line.raw;
+        let tokenized = line.tokenize();
+        let local = |name: &str| {
+            let (number, forward) = match name.split_at(name.len().saturating_sub(1)) {
+                (number, "f") => (number, true),
+                (number, "b") => (number, false),
+                _ => return None,
+            };
+            let mut defined = locals.iter().filter(|(l, _)| l == number);
+            match forward {
+                true => defined.find(|(_, n)| *n > index),
+                false => defined.rfind(|(_, n)| *n <= index),
+            }
+        };
+        let address = |t: &str| {
+            let name = t.strip_prefix('@').unwrap_or(t);
+            if name == "$" {
+                return Some(at(index));
+            }
+            labels
+                .iter()
+                .find(|(l, _)| l == name)
+                .or_else(|| local(name))
+                .map(|(_, n)| at(*n))
+        };
+        let value = |t: &str| {
+            let t = t.strip_prefix('#').unwrap_or(t);
+            let lookup = |name: &str| {
+                let label = address(name).map(|a| a as i32);
+                label.or_else(|| constant_value(name, constants).ok())
+            };
+            expr::eval(t, lookup).map_err(|e| match e {
+                ExprError::Undefined(name) => undefined_symbol(&name),
+                ExprError::Invalid => AsmError::InvalidExpression(t.to_string()),
+            })
+        };
+        // e.g. `1b` with `1:` only defined after it, rather than binary 1
+        let missing = |t: &str| {
+            let number = t.strip_prefix('@').unwrap_or(t);
+            let number = number.strip_suffix(['f', 'b'])?;
+            locals.iter().any(|(l, _)| l == number).then_some(())
+        };
+        let replace = |t: &str| match address(t) {
+            // a raw word is the address itself rather than a branch to it
+            Some(address) if raw => Some(Ok(address.to_string())),
+            Some(address) => Some(Ok(format!("@{address}"))),
+            None if missing(t).is_some() => Some(Err(AsmError::InvalidLabel(t.to_string()))),
+            // an absolute target worked out from labels, e.g. `@$+2`
+            None if t.strip_prefix('@').is_some_and(expr::is_expression) => {
+                Some(value(&t[1..]).map(|v| format!("@{v}")))
+            }
+            None if expr::is_expression(t.strip_prefix('#').unwrap_or(t)) => {
+                Some(value(t).map(|v| v.to_string()))
+            }
+            None => None,
+        };
+        if !tokenized.tokens.iter().any(|t| replace(t).is_some()) {
+            replaced.push(line);
+            continue;
+        }
+
+        let mut operands = vec![];
+        let mut failed = false;
+        for (n, t) in tokenized.tokens.iter().enumerate() {
+            match replace(t) {
+                Some(Ok(t)) => operands.push(t),
+                Some(Err(e)) => {
+                    errors.push(line.error_at(e, n + 1));
+                    failed = true;
+                }
+                None => operands.push(t.to_string()),
+            }
+        }
+        if !failed {
+            line.string = format!("{} {}", tokenized.mnenomic, operands.join(", "));
+            replaced.push(line);
+        }
+    }
+    replaced
+}
+
+/// Rewrite absolute branch targets, e.g. `BR @0x12`, as the displacement
+/// from the instruction's own address. `base` is the address of the first
+/// line, and `gaps` the memory skipped before others. Returns the target of
+/// each line that had one.
+fn resolve_absolute(
+    lines: &mut [LinePreprocessed],
+    base: usize,
+    gaps: &[Gap],
+) -> Vec<Option<usize>> {
+    let mut targets = vec![None; lines.len()];
+
+    for (index, line) in lines.iter_mut().enumerate() {
+        let tokenized = line.tokenize();
+        let Some(target) = tokenized.tokens.iter().find_map(|t| {
+            t.strip_prefix('@')
+                .and_then(parse_number)
+                .and_then(|a| usize::try_from(a).ok())
+        }) else {
+            continue;
+        };
+        let address = base + index + gap_words(gaps, index);
+        let offset = format!("#{}", target as i64 - address as i64);
+        let operands: Vec<&str> = tokenized
+            .tokens
+            .iter()
+            .map(|t| match t.starts_with('@') {
+                true => offset.as_str(),
+                false => t,
+            })
+            .collect();
+        line.string = format!("{} {}", tokenized.mnenomic, operands.join(", "));
+        targets[index] = Some(target);
+    }
+    targets
+}
+
+/// An assembled file: its instructions, the contents of its `.data`
+/// section, and the symbols it exports and refers to.
+#[derive(Debug, PartialEq)]
+pub struct Module<T> {
+    pub instrs: Vec<T>,
+    pub data: Vec<u16>,
+    pub symbols: Symbols,
+    /// Register aliases from `.def`, as (name, register), for listings.
+    pub aliases: Vec<(String, String)>,
+    /// The parts of `instrs` that only fill memory skipped by `.org` and
+    /// `.align`.
+    pub gaps: Vec<Range<usize>>,
+    /// Comments for listings by the index of the instruction they're about,
+    /// e.g. how many instructions an `LDI` became.
+    pub notes: Vec<(usize, String)>,
+}
+
+impl<T> Module<T> {
+    /// `text` with each register that has exactly one alias shown by it.
+    pub fn show_aliases(&self, text: &str) -> String {
+        replace_words(text, |w| {
+            let mut names = self
+                .aliases
+                .iter()
+                .filter(|(_, r)| r.eq_ignore_ascii_case(w));
+            match (names.next(), names.next()) {
+                (Some((name, _)), None) => Some(name.clone()),
+                _ => None,
+            }
+        })
+    }
+}
+
+/// How to assemble a source, besides the instruction set. The default is
+/// permissive syntax, English messages and nothing defined.
+#[derive(Debug, Clone)]
+pub struct AssembleOptions {
+    pub syntax: Syntax,
+    /// Constants set outside the source, e.g. with `-D`, for `.if`
+    /// conditions and operands.
+    pub defines: Vec<(String, i32)>,
+    /// Language errors and warnings are printed in.
+    pub lang: Lang,
+    /// Directories to look for `.include` files in, after the directory of
+    /// the file including them.
+    pub include_paths: Vec<PathBuf>,
+    /// Print warnings as errors, producing no module if there are any.
+    pub deny_warnings: bool,
+    /// Address in program memory of the first instruction, which labels,
+    /// `@<address>` targets, `$` and `.org` count from.
+    pub base: usize,
+    /// Words of program memory, which nothing can be placed past.
+    pub depth: usize,
+    /// Words of data memory, which the `.data` section can't be longer than.
+    pub data_depth: usize,
+    /// Bits per word of data memory, up to 16, which `.data` values must fit.
+    pub data_width: u8,
+}
+
+/// The most words of memory a 16-bit address reaches, the depth assumed
+/// unless one is given.
+pub const MAX_DEPTH: usize = 1 << 16;
+
+impl Default for AssembleOptions {
+    fn default() -> Self {
+        AssembleOptions {
+            syntax: Syntax::default(),
+            defines: vec![],
+            lang: Lang::default(),
+            include_paths: vec![],
+            deny_warnings: false,
+            base: 0,
+            depth: MAX_DEPTH,
+            data_depth: MAX_DEPTH,
+            data_width: 8,
+        }
+    }
+}
+
+/// Parse every line for `isa`, collecting all errors along the way.
+pub(crate) fn assemble<W: Word, I: InstructionSet<W>>(
+    contents: &[u8],
+    isa: &I,
+) -> Result<Module<I::Instr>, Vec<AsmLineError>> {
+    assemble_with(contents, isa, &AssembleOptions::default())
+}
+
+/// Like [`assemble`], with the syntax and defines of `opts`.
+pub(crate) fn assemble_with<W: Word, I: InstructionSet<W>>(
+    contents: &[u8],
+    isa: &I,
+    opts: &AssembleOptions,
+) -> Result<Module<I::Instr>, Vec<AsmLineError>> {
+    assemble_warned(contents, isa, opts, &mut vec![])
+}
+
+/// Like [`assemble_with`], collecting warnings about suspicious but legal
+/// lines in `warnings`, with their line numbers, in line order.
+fn assemble_warned<W: Word, I: InstructionSet<W>>(
+    contents: &[u8],
+    isa: &I,
+    opts: &AssembleOptions,
+    warnings: &mut Vec<(usize, AsmWarning)>,
+) -> Result<Module<I::Instr>, Vec<AsmLineError>> {
+    let (syntax, defines) = (opts.syntax, &opts.defines[..]);
+    let mut errors: Vec<AsmLineError> = vec![];
+    warnings.extend(
+        deprecations(contents, isa)
+            .into_iter()
+            .map(|(line, old, new)| (line, AsmWarning::Deprecated(old, new))),
+    );
+
+    let preprocessed: Vec<LinePreprocessed> = split_lines(contents)
+        .into_iter()
+        .filter_map(|l| l.map_err(|e| errors.push(e)).ok())
+        .filter_map(|l| l.preprocess())
+        .flat_map(LinePreprocessed::statements)
+        .collect();
+    let preprocessed = reject_commas(preprocessed, &mut errors);
+    let (preprocessed, allowed) = take_allows(preprocessed, &mut errors);
+    let line_allowed = line_allows(contents, &mut errors);
+    let preprocessed = split_labels(preprocessed);
+    // macros first, so their parameters can control `.if` and `.rept`
+    let preprocessed = expand_macros(preprocessed, &mut errors);
+    let preprocessed = take_conditionals(preprocessed, defines, &mut errors);
+    let (preprocessed, aliases) = take_aliases(preprocessed, &mut errors);
+    let preprocessed = expand_repeats(preprocessed, opts.depth, &mut errors);
+    let preprocessed = expand_pseudos(preprocessed, &mut errors);
+    let (preprocessed, constants) = take_constants(preprocessed, defines, &mut errors);
+    let preprocessed = expand_ldi(preprocessed, isa, &mut errors);
+    let preprocessed = expand_jmp(preprocessed, &mut errors);
+    let (preprocessed, data) = take_data(
+        preprocessed,
+        opts.depth,
+        opts.data_depth,
+        opts.data_width,
+        &mut errors,
+    );
+    let (preprocessed, entry) = take_entry(preprocessed, &mut errors);
+    // the jump to the entry point comes first, unless it's the first line
+    let jumps = entry
+        .as_ref()
+        .is_some_and(|l| l.tokenize().tokens.first().and_then(|t| parse_number(t)) != Some(0));
+    let base = opts.base + jumps as usize;
+    let (preprocessed, mut symbols) = take_symbols(preprocessed, &mut errors);
+    let (mut preprocessed, mut gaps) = take_org(
+        preprocessed,
+        isa,
+        base,
+        opts.depth,
+        &mut symbols,
+        &mut errors,
+    );
+    // the entry point is resolved with labels, as the last line so none move
+    preprocessed.extend(entry);
+    let mut preprocessed = take_labels(
+        preprocessed,
+        &constants,
+        &mut symbols,
+        &mut gaps,
+        base,
+        &mut errors,
+    );
+    let entry = match preprocessed.last().map(|l| l.tokenize().mnenomic) {
+        Some(".entry") => preprocessed.pop().filter(|_| jumps),
+        _ => None,
+    };
+    let targets = resolve_absolute(&mut preprocessed, base, &gaps);
 
-impl Display for AsmLineError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Line {}: {}", self.1, self.0)
-    }
-}
+    let mut parsed: Vec<I::Instr> = preprocessed
+        .iter()
+        .zip(targets)
+        .filter_map(|(l, target)| {
+            parse_line(l, isa, syntax)
+                .map_err(|es| {
+                    errors.extend(es.into_iter().map(|e| {
+                        let AsmLineError(e, lineno, span) = l.error(e);
+                        let e = match (e, target) {
+                            (AsmError::ImmediateOutOfRange(offset), Some(target)) => {
+                                AsmError::BranchOutOfRange(target, offset)
+                            }
+                            (e, _) => e,
+                        };
+                        let e = match &l.in_macro {
+                            Some((name, line)) => {
+                                AsmError::InMacro(Box::new(e), name.clone(), *line)
+                            }
+                            None => e,
+                        };
+                        let e = match l.statement {
+                            Some(n) => AsmError::InStatement(Box::new(e), n),
+                            None => e,
+                        };
+                        AsmLineError(e, lineno, span)
+                    }))
+                })
+                .ok()
+                .inspect(|instr| {
+                    // raw words and macros may do nothing on purpose
+                    let written = l.in_macro.is_none() && !l.raw;
+                    if let Some(w) = isa.warning(instr).filter(|_| written) {
+                        warnings.push((l.lineno, w));
+                    }
+                })
+        })
+        .collect();
+    warnings.sort_by_key(|(line, _)| *line);
+    warnings.retain(|(line, w)| {
+        let name = w.name();
+        !allowed.iter().any(|a| a == name)
+            && !line_allowed.iter().any(|(l, a)| l == line && a == name)
+    });
 
-fn parse_line(line: &LinePreprocessed) -> Result<Op, AsmError> {
-    use AsmError::*;
+    // Every line has parsed if there's output, so lines and instructions
+    // share indices until the gaps are filled.
+    let mut notes: Vec<(usize, String)> = preprocessed
+        .iter()
+        .enumerate()
+        .filter_map(|(n, l)| Some((n, l.note.clone()?)))
+        .collect();
 
-    fn get_imm<'a, T, const N: u8>(
-        tokens: &mut impl Iterator<Item = &'a str>,
-    ) -> Result<Imm<T, N>, AsmError>
-    where
-        T: ImmType<N> + Copy,
-    {
-        let imm = tokens.next().ok_or(MissingImmediate)?;
-        let imm = imm.strip_prefix('#').unwrap_or(imm);
+    // Fill the memory skipped by `.org` and `.align`, once every line has
+    // parsed, so instructions are indexed by their address again.
+    let mut filled = vec![];
+    if errors.is_empty() {
+        for gap in gaps.iter().rev() {
+            let at = gap.at.min(parsed.len());
+            let fill = (0..gap.len).filter_map(|_| isa.decode(W::truncate(gap.fill as u32)).ok());
+            parsed.splice(at..at, fill);
+            let shift = |n: &mut usize| *n += gap.len * (*n >= gap.at) as usize;
+            symbols.globals.iter_mut().for_each(|(_, n)| shift(n));
+            symbols.relocations.iter_mut().for_each(|(n, _)| shift(n));
+            notes.iter_mut().for_each(|(n, _)| shift(n));
+        }
+        let mut skipped = 0;
+        for gap in &gaps {
+            let start = gap.at + skipped;
+            filled.push(start..start + gap.len);
+            skipped += gap.len;
+        }
+    }
 
-        // Two steps are required since .parse<T> doesn't distinguish
-        // between invalid and out of range.
-        let val: i32 = imm.parse().or(Err(InvalidImmediate(imm.to_string())))?;
-        let v: T = val.try_into().or(Err(ImmediateOutOfRange(val)))?;
-        v.try_into().or(Err(ImmediateOutOfRange(val)))
+    // Start at the entry point by branching over the instructions before it.
+    if let Some(line) = entry {
+        let address = entry_address(&line, base, &gaps);
+        let jump = address
+            .filter(|a| (base..base + parsed.len()).contains(a))
+            .and_then(|a| isa.jump((a - opts.base) as i32));
+        match jump {
+            Some(jump) => {
+                parsed.insert(0, jump);
+                symbols.globals.iter_mut().for_each(|(_, n)| *n += 1);
+                symbols.relocations.iter_mut().for_each(|(n, _)| *n += 1);
+                notes.iter_mut().for_each(|(n, _)| *n += 1);
+                filled.iter_mut().for_each(|r| *r = r.start + 1..r.end + 1);
+            }
+            None => {
+                let tokenized = line.tokenize();
+                let target = tokenized.tokens.first().copied().unwrap_or_default();
+                let target = target.strip_prefix('@').unwrap_or(target);
+                errors.push(line.error_at(AsmError::InvalidEntry(target.to_string()), 1));
+            }
+        }
     }
 
-    fn get_reg<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<Reg, AsmError> {
-        let reg = tokens.next().ok_or(MissingRegister)?;
-        reg.try_into().or(Err(InvalidRegister(reg.to_string())))
+    // Only produce output if there are no errors
+    if errors.is_empty() {
+        Ok(Module {
+            instrs: parsed,
+            data,
+            symbols,
+            aliases,
+            gaps: filled,
+            notes,
+        })
+    } else {
+        errors.sort_by_key(|e| e.1);
+        // a line repeated by `.fill` or `.rept` can fail the same way each time
+        errors.dedup_by(|a, b| a.1 == b.1 && a.0 == b.0);
+        Err(errors)
     }
+}
 
-    let tokenized = line.tokenize();
-    let mut tokens = tokenized.tokens.into_iter();
-
-    let op = match tokenized.mnenomic {
-        "BR" => Op::BR(get_imm(&mut tokens)?),
-        "BRZ" => Op::BRZ(get_imm(&mut tokens)?),
-        "ADDI" => Op::ADDI(get_reg(&mut tokens)?, get_imm(&mut tokens)?),
-        "SUBI" => Op::SUBI(get_reg(&mut tokens)?, get_imm(&mut tokens)?),
-        "SR0" => Op::SR0(get_imm(&mut tokens)?),
-        "SRH0" => Op::SRH0(get_imm(&mut tokens)?),
-        "CLR" => Op::CLR(get_reg(&mut tokens)?),
-        "MOVA" => Op::MOVA(get_reg(&mut tokens)?),
-        "MOVR" => Op::MOVR(get_reg(&mut tokens)?),
-        "MOVRHS" => Op::MOVRHS(get_reg(&mut tokens)?),
-        "MOV" => Op::MOV(get_reg(&mut tokens)?, get_reg(&mut tokens)?),
-        "PAUSE" => Op::PAUSE,
-        x => return Err(InvalidMnenomic(x.to_string())),
+/// Read and assemble a file with the files it `.include`s, printing any
+/// errors.
+fn assemble_file<W: Word, I: InstructionSet<W>>(
+    path: &Path,
+    isa: &I,
+    opts: &AssembleOptions,
+) -> Option<Module<I::Instr>> {
+    let source = match include::expand(path, &opts.include_paths) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("{e}");
+            return None;
+        }
     };
+    parse_included(&source.text, &source, isa, opts)
+}
 
-    if let Some(t) = tokens.next() {
-        return Err(ExtraToken(t.to_string()));
-    }
+/// Assemble source already read into memory, printing any errors.
+pub fn parse_with<W: Word, I: InstructionSet<W>>(
+    contents: &[u8],
+    isa: &I,
+    opts: &AssembleOptions,
+) -> Option<Module<I::Instr>> {
+    parse_located(contents, isa, opts, |line| (None, line))
+}
 
-    Ok(op)
+/// Like [`parse_with`] for text expanded from `source`, either all of it or
+/// one of its `.program`s, reporting each problem in the included file and
+/// line it came from, e.g. `lib/motor.s: Line 3: error[E0001]: ...`.
+pub fn parse_included<W: Word, I: InstructionSet<W>>(
+    contents: &[u8],
+    source: &Source,
+    isa: &I,
+    opts: &AssembleOptions,
+) -> Option<Module<I::Instr>> {
+    parse_located(contents, isa, opts, |line| source.origin(line))
 }
 
-pub fn parse_file(file: File) -> Option<Program> {
-    fn inner(file: File) -> Result<Program, Vec<AsmLineError>> {
-        let reader = BufReader::new(file);
+fn parse_located<'a, W: Word, I: InstructionSet<W>>(
+    contents: &[u8],
+    isa: &I,
+    opts: &AssembleOptions,
+    locate: impl Fn(usize) -> (Option<&'a Path>, usize),
+) -> Option<Module<I::Instr>> {
+    let (lang, deny_warnings) = (opts.lang, opts.deny_warnings);
+    // the message is given the line number within its own file
+    let print = |line: usize, message: &dyn Fn(usize) -> String| match locate(line) {
+        (Some(file), line) => eprintln!("{}: {}", file.display(), message(line)),
+        (None, line) => eprintln!("{}", message(line)),
+    };
+    let mut warnings = vec![];
+    let result = assemble_warned(contents, isa, opts, &mut warnings);
+    for (line, w) in &warnings {
+        let message = messages::asm_warning(w, lang);
+        print(*line, &|line| match deny_warnings {
+            true => messages::line_denied(line, &message, lang),
+            false => messages::line_warning(line, &message, lang),
+        });
+    }
+    match result {
+        Ok(_) if deny_warnings && !warnings.is_empty() => None,
+        Ok(parsed) => Some(parsed),
+        Err(errs) => {
+            for e in errs.iter().take(messages::ERROR_LIMIT) {
+                print(e.1, &|line| e.localized_in(contents, line, lang));
+            }
+            if errs.len() > messages::ERROR_LIMIT {
+                let more = errs.len() - messages::ERROR_LIMIT;
+                eprintln!("{}", messages::more_errors(more, lang));
+            }
+            None
+        }
+    }
+}
 
-        let preprocessed = reader
-            .lines()
-            .filter_map(|l| l.ok())
-            .enumerate()
-            .map(|(n, l)| Line {
-                string: l,
-                lineno: n + 1, // file lineno start at 1
-            })
-            .filter_map(|l| l.preprocess());
+/// Each line using a deprecated mnemonic the ISA still accepts, with the
+/// mnemonic and its replacement.
+pub(crate) fn deprecations<W: Word, I: InstructionSet<W>>(
+    contents: &[u8],
+    isa: &I,
+) -> Vec<(usize, String, String)> {
+    let lines = split_lines(contents)
+        .into_iter()
+        .filter_map(|l| l.ok()?.preprocess())
+        .flat_map(LinePreprocessed::statements)
+        .collect();
+    split_labels(lines)
+        .into_iter()
+        .filter_map(|l| {
+            let old = l.tokenize().mnenomic.to_string();
+            let new = isa.replacement(&old)?;
+            Some((l.lineno, old, new))
+        })
+        .collect()
+}
 
-        let mut errors: Vec<AsmLineError> = vec![];
+/// Split a source file into one source per `.program <name>` section,
+/// printing any errors. Lines before the first `.program` are shared by
+/// every program, e.g. `.pseudo` definitions. The lines of other programs
+/// are left blank so errors keep their line numbers. Empty if the file has
+/// no sections.
+pub fn split_programs(contents: &[u8]) -> Option<Vec<(String, Vec<u8>)>> {
+    let raw: Vec<&[u8]> = contents.split(|b| *b == b'\n').collect();
+    let mut errors = vec![];
+    let mut names: Vec<String> = vec![];
+    let mut headers = vec![];
+    // the program each line belongs to, `None` for the shared lines
+    let mut owners: Vec<Option<usize>> = vec![];
 
-        // convert to Ops and record all errors along the way
-        let ops = preprocessed
-            .filter_map(|l| {
-                parse_line(&l)
-                    .map_err(|e| errors.push(e.on_line(l.lineno)))
-                    .ok()
-            })
-            .collect();
+    for (n, bytes) in raw.iter().enumerate() {
+        let line = Line {
+            string: String::from_utf8_lossy(bytes).into_owned(),
+            lineno: n + 1,
+        };
+        if let Some(line) = line.preprocess() {
+            let tokenized = line.tokenize();
+            if tokenized.mnenomic == ".program" {
+                let name = match tokenized.tokens[..] {
+                    [] => Err(AsmError::MissingImmediate),
+                    [name] if names.iter().any(|n| n == name) => {
+                        Err(AsmError::DuplicateSymbol(name.to_string()))
+                    }
+                    [name] => Ok(name.to_string()),
+                    [_, extra, ..] => Err(AsmError::ExtraToken(extra.to_string())),
+                };
+                match name {
+                    Ok(name) => names.push(name),
+                    Err(e @ AsmError::ExtraToken(_)) => errors.push(line.error_at(e, 2)),
+                    Err(e) => errors.push(line.error_at(e, 1)),
+                }
+                headers.push(n);
+            }
+        }
+        owners.push(names.len().checked_sub(1));
+    }
 
-        // Only create a program if there are no errors
-        if errors.is_empty() {
-            Ok(Program { ops })
-        } else {
-            Err(errors)
+    if !errors.is_empty() {
+        for e in errors {
+            eprintln!("{e}");
         }
+        return None;
     }
 
-    match inner(file) {
-        Ok(program) => Some(program),
-        Err(errs) => {
-            for e in errs {
-                println!("{}", e);
+    let programs = names
+        .into_iter()
+        .enumerate()
+        .map(|(k, name)| {
+            let lines: Vec<&[u8]> = raw
+                .iter()
+                .enumerate()
+                .map(|(n, bytes)| match owners[n] {
+                    _ if headers.contains(&n) => &b""[..],
+                    None => bytes,
+                    Some(owner) if owner == k => bytes,
+                    Some(_) => &b""[..],
+                })
+                .collect();
+            (name, lines.join(&b'\n'))
+        })
+        .collect();
+    Some(programs)
+}
+
+/// Assemble a standalone program, resolving any branches to its own
+/// `.global` symbols. Files it `.include`s are looked for beside it.
+pub fn parse_file(path: &Path, isa: &Isa) -> Option<Program> {
+    let module = parse_file_with(path, isa)?;
+    match object::resolve(isa, module.instrs, module.symbols) {
+        // the default options keep data to bytes
+        Ok(ops) => Some(Program {
+            ops,
+            data: module.data.iter().map(|w| *w as u8).collect(),
+        }),
+        Err(errors) => {
+            for e in errors {
+                eprintln!("{e}");
             }
             None
         }
     }
 }
 
+/// Assemble a file into a module of any instruction set.
+pub fn parse_file_with<W: Word, I: InstructionSet<W>>(
+    path: &Path,
+    isa: &I,
+) -> Option<Module<I::Instr>> {
+    assemble_file(path, isa, &AssembleOptions::default())
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::imm::{I5, U3, U4};
+    use crate::{
+        binary::Decoded,
+        imm::{I5, U3, U4},
+        instruction_set::WithData,
+    };
 
     use super::*;
 
@@ -210,6 +2592,9 @@ mod tests {
             ("BR ; remove comment", Some("BR")),
             ("  ADDI; trim", Some("ADDI")),
             ("  ; empty", None),
+            ("CLR r0 // C style", Some("CLR r0")),
+            ("# shell style", None),
+            ("ADDI r0, #5 # immediate", Some("ADDI r0, #5")),
         ];
         for (test, expected) in cases {
             let l = Line {
@@ -232,21 +2617,141 @@ mod tests {
         use AsmError::*;
         use Reg::*;
         // don't need to test empty strings since they should be filtered out
-        let cases: Vec<(&str, Result<Op, AsmError>)> = vec![
+        let cases: Vec<(&str, Result<Op, Vec<AsmError>>)> = vec![
             ("PAUSE", Ok(Op::PAUSE)),
             ("ADDI r3, 7", Ok(Op::ADDI(R3, U3::new(7).unwrap()))),
-            ("ADDI r3, 8", Err(ImmediateOutOfRange(8))),
+            ("ADDI r3, 8", Err(vec![ImmediateOutOfRange(8)])),
             ("BR -14", Ok(Op::BR(I5::new(-14).unwrap()))),
             ("BRZ 2", Ok(Op::BRZ(I5::new(2).unwrap()))),
-            ("MOV r3r2", Err(InvalidRegister("r3r2".to_string()))),
+            (
+                "MOV r3r2",
+                Err(vec![InvalidRegister("r3r2".to_string()), MissingRegister]),
+            ),
             ("MOV r3,    r2", Ok(Op::MOV(R3, R2))),
-            ("SRH0", Err(MissingImmediate)),
+            ("MOV\tr3,\tr2", Ok(Op::MOV(R3, R2))),
+            ("MOV r3 \t r2", Ok(Op::MOV(R3, R2))),
+            ("ADDI\tr1 5", Ok(Op::ADDI(R1, U3::new(5).unwrap()))),
+            ("SRH0", Err(vec![MissingImmediate])),
             ("SRH0 1", Ok(Op::SRH0(U4::new(1).unwrap()))),
             ("SRH0 #1", Ok(Op::SRH0(U4::new(1).unwrap()))),
-            ("CLR r0, extra", Err(ExtraToken("extra".to_string()))),
-            ("SR0 numbers", Err(InvalidImmediate("numbers".to_string()))),
-            ("SBI", Err(InvalidMnenomic("SBI".to_string()))),
-            ("CLR", Err(MissingRegister)),
+            ("CLR r0, extra", Err(vec![ExtraToken("extra".to_string())])),
+            (
+                "SR0 numbers",
+                Err(vec![UndefinedSymbol("numbers".to_string())]),
+            ),
+            ("SR0 1x", Err(vec![InvalidImmediate("1x".to_string())])),
+            ("SBI", Err(vec![InvalidMnenomic("SBI".to_string())])),
+            ("CLR", Err(vec![MissingRegister])),
+            (
+                "ADDI rr5, 99",
+                Err(vec![
+                    InvalidRegister("rr5".to_string()),
+                    ImmediateOutOfRange(99),
+                ]),
+            ),
+            (
+                "MOV r4, r9, r1",
+                Err(vec![
+                    InvalidRegister("r4".to_string()),
+                    InvalidRegister("r9".to_string()),
+                    ExtraToken("r1".to_string()),
+                ]),
+            ),
+        ];
+
+        for (line, result) in cases {
+            let l = Line {
+                string: line.to_string(),
+                lineno: 0,
+            }
+            .preprocess()
+            .unwrap();
+            let errors = |es: Vec<AsmError>| es.into_iter().map(|e| l.error(e).0).collect();
+            assert_eq!(
+                parse_line(&l, &Isa::default(), Syntax::default()).map_err(errors),
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_asm_extension() {
+        use AsmError::*;
+        use Reg::*;
+
+        let base = Isa::default();
+        let logic = Isa::default().with(Extension::Logic);
+        let mem = Isa::default().with(Extension::Mem);
+        let io = Isa::default().with(Extension::Io);
+        let shift = Isa::default().with(Extension::Shift);
+        let cases = [
+            ("AND r1", &logic, Ok(Op::AND(R1))),
+            ("NOT r3", &logic, Ok(Op::NOT(R3))),
+            (
+                "LD r1, r2",
+                &logic,
+                Err(vec![ExtensionDisabled("LD".to_string(), Extension::Mem)]),
+            ),
+            ("LD r1, r2", &mem, Ok(Op::LD(R1, R2))),
+            ("ST r0, r3", &mem, Ok(Op::ST(R0, R3))),
+            ("IN r2", &io, Ok(Op::IN(R2))),
+            ("OUT r0", &io, Ok(Op::OUT(R0))),
+            ("SHR r1", &shift, Ok(Op::SHR(R1))),
+            ("ADD r0, r2", &shift, Ok(Op::ADD(R0, R2))),
+            (
+                "XOR r4",
+                &logic,
+                Err(vec![InvalidRegister("r4".to_string())]),
+            ),
+            (
+                "OR r2",
+                &base,
+                Err(vec![ExtensionDisabled("OR".to_string(), Extension::Logic)]),
+            ),
+        ];
+
+        for (line, isa, result) in cases {
+            let l = Line {
+                string: line.to_string(),
+                lineno: 0,
+            }
+            .preprocess()
+            .unwrap();
+            let errors = |es: Vec<AsmError>| es.into_iter().map(|e| l.error(e).0).collect();
+            assert_eq!(
+                parse_line(&l, isa, Syntax::default()).map_err(errors),
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_asm_with_spec() {
+        use crate::isa::InstrSpec;
+        use AsmError::*;
+
+        let spec = IsaSpec::new(
+            "test",
+            8,
+            vec![
+                InstrSpec::new("JMP", "01iiiiii", &["i:signed"]).unwrap(),
+                InstrSpec::new("INC", "10000rrr", &["r:reg"]).unwrap(),
+                InstrSpec::new("ADD", "11aaabbb", &["a:reg", "b:reg"]).unwrap(),
+                InstrSpec::new("HALT", "00000000", &[]).unwrap(),
+            ],
+        )
+        .unwrap();
+
+        let cases: Vec<(&str, Result<u8, Vec<AsmError>>)> = vec![
+            ("HALT", Ok(0)),
+            ("JMP -32", Ok(0b01_100000)),
+            ("JMP 32", Err(vec![ImmediateOutOfRange(32)])),
+            ("INC r7", Ok(0b10000_111)),
+            ("INC r8", Err(vec![InvalidRegister("r8".to_string())])),
+            ("ADD r1, r6", Ok(0b11_001_110)),
+            ("ADD r1", Err(vec![MissingRegister])),
+            ("HALT 1", Err(vec![ExtraToken("1".to_string())])),
+            ("BR 2", Err(vec![InvalidMnenomic("BR".to_string())])),
         ];
 
         for (line, result) in cases {
@@ -256,7 +2761,1281 @@ mod tests {
             }
             .preprocess()
             .unwrap();
-            assert_eq!(parse_line(&l), result);
+            let errors = |es: Vec<AsmError>| es.into_iter().map(|e| l.error(e).0).collect();
+            assert_eq!(
+                parse_line::<u8, _>(&l, &spec, Syntax::default()).map_err(errors),
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_deprecated() {
+        use crate::isa::InstrSpec;
+
+        let spec = || {
+            IsaSpec::new(
+                "v2",
+                8,
+                vec![
+                    InstrSpec::new("SRH", "0101iiii", &["i:unsigned"])
+                        .unwrap()
+                        .with_deprecated(&["SRH0"]),
+                    InstrSpec::new("PAUSE", "11111111", &[]).unwrap(),
+                ],
+            )
+            .unwrap()
+        };
+        let source = b"SRH 1\nSRH0 3 ; old\nPAUSE";
+        let module = assemble::<u8, _>(source, &spec()).ok().unwrap();
+        assert_eq!(module.instrs, vec![0x51, 0x53, 0xff]);
+        assert_eq!(
+            deprecations::<u8, _>(source, &spec()),
+            vec![(2, "SRH0".to_string(), "SRH".to_string())]
+        );
+
+        let denied = spec().deny_deprecated();
+        let errors = assemble::<u8, _>(source, &denied).err().unwrap();
+        assert_eq!(
+            errors[0].to_string(),
+            "Line 2: error[E0020]: \"SRH0\" is deprecated, use \"SRH\"."
+        );
+        assert!(deprecations::<u8, _>(source, &denied).is_empty());
+    }
+
+    #[test]
+    fn test_pseudo() {
+        use AsmError::*;
+
+        let p = Pseudo::parse("DELAY n, r: CLR r | ADDI r, n | PAUSE").unwrap();
+        assert_eq!(
+            p.expand(&["5", "r3"]),
+            Ok(vec![
+                "CLR r3".to_string(),
+                "ADDI r3, 5".to_string(),
+                "PAUSE".to_string()
+            ])
+        );
+        assert_eq!(
+            p.expand(&["5"]),
+            Err(PseudoOperandCount("DELAY".to_string(), 2, 1))
+        );
+        assert!(Pseudo::parse("DELAY n").is_err());
+        assert!(Pseudo::parse(": PAUSE").is_err());
+        assert!(Pseudo::parse("STOP: PAUSE |").is_err());
+
+        let isa = Isa::default();
+        let ops = assemble(b".pseudo WAIT: CLR r3 | PAUSE\nWAIT", &isa);
+        assert_eq!(ops.ok().unwrap().instrs, vec![Op::CLR(Reg::R3), Op::PAUSE]);
+
+        // errors point at the use
+        let errors = assemble(b".pseudo WAIT: CLR r3 | PAUSE\nBR -2\nWAIT 1", &isa);
+        assert_eq!(errors.unwrap_err()[0].1, 3);
+    }
+
+    #[test]
+    fn test_numbers() {
+        let cases = [
+            ("12", Some(12)),
+            ("+12", Some(12)),
+            ("-12", Some(-12)),
+            ("0x1F", Some(31)),
+            ("1Fh", Some(31)),
+            ("0b0001_1111", Some(31)),
+            ("0001_1111b", Some(31)),
+            ("-0o17", Some(-15)),
+            ("17o", Some(15)),
+            ("10d", Some(10)),
+            ("1_000", Some(1000)),
+            ("_1", None),
+            ("1_", None),
+            ("0x", None),
+            ("bh", None),
+            ("--1", None),
+            ("+-1", None),
+            ("12x", None),
+            ("0b2", None),
+        ];
+        for (token, expected) in cases {
+            assert_eq!(parse_number(token), expected, "{token}");
+        }
+    }
+
+    #[test]
+    fn test_syntax() {
+        let isa = Isa::default();
+        let parse = |s: &str, syntax| {
+            let line = Line {
+                string: s.to_string(),
+                lineno: 1,
+            };
+            let line = line.preprocess().unwrap();
+            parse_line(&line, &isa, syntax)
+                .map_err(|es| es.into_iter().map(|e| line.error(e).0).collect::<Vec<_>>())
+        };
+        let addi = Ok(Op::ADDI(Reg::R1, U3::new(5).unwrap()));
+
+        assert_eq!(parse("addi R1 5", Syntax::Permissive), addi);
+        assert_eq!(parse("ADDI r1, #5", Syntax::Strict), addi);
+        assert_eq!(
+            parse("addi r1, #5", Syntax::Strict),
+            Err(vec![AsmError::InvalidMnenomic("addi".to_string())])
+        );
+        assert_eq!(
+            parse("ADDI r1 5", Syntax::Strict),
+            Err(vec![
+                AsmError::MissingComma,
+                AsmError::MissingHash("5".to_string())
+            ])
+        );
+        assert_eq!(
+            parse("ADDI r1,, #5", Syntax::Strict),
+            Err(vec![AsmError::MissingComma])
+        );
+        assert_eq!(
+            parse("FOO r1", Syntax::Permissive),
+            Err(vec![AsmError::InvalidMnenomic("FOO".to_string())])
+        );
+    }
+
+    #[test]
+    fn test_entry() {
+        use crate::isa::{Branch, InstrSpec};
+        use AsmError::*;
+
+        let isa = Isa::default();
+        let ops = assemble(b"PAUSE\n.entry 1\nCLR r0", &isa);
+        let br = || Op::BR(I5::new(2).unwrap());
+        assert_eq!(
+            ops.ok().unwrap().instrs,
+            vec![br(), Op::PAUSE, Op::CLR(Reg::R0)]
+        );
+
+        let ops = assemble(b".entry 0\nPAUSE", &isa);
+        assert_eq!(ops.ok().unwrap().instrs, vec![Op::PAUSE]);
+
+        // a label or an expression of them names the address to start at
+        let ops = assemble(b".entry start\nPAUSE\nstart: CLR r0", &isa);
+        assert_eq!(
+            ops.ok().unwrap().instrs,
+            vec![br(), Op::PAUSE, Op::CLR(Reg::R0)]
+        );
+        let ops = assemble(b".entry start+1\nstart: PAUSE\nCLR r0", &isa);
+        assert_eq!(
+            ops.ok().unwrap().instrs,
+            vec![br(), Op::PAUSE, Op::CLR(Reg::R0)]
+        );
+
+        // a runtime-defined ISA needs a branch marked as always taken
+        let spec = |branch: Option<Branch>| {
+            let jmp = InstrSpec::new("JMP", "01iiiiii", &["i:signed"]).unwrap();
+            let jmp = match branch {
+                Some(branch) => jmp.with_branch(branch).unwrap(),
+                None => jmp,
+            };
+            let halt = InstrSpec::new("HALT", "00000000", &[]).unwrap();
+            IsaSpec::new("test", 8, vec![jmp, halt]).unwrap()
+        };
+        let src = b".entry start\nHALT\nstart: HALT";
+        let module = assemble::<u8, _>(src, &spec(Some(Branch::Always)));
+        assert_eq!(module.ok().unwrap().instrs, vec![0b01_000010, 0, 0]);
+        for branch in [None, Some(Branch::Conditional)] {
+            let errors = assemble::<u8, _>(src, &spec(branch)).unwrap_err();
+            assert_eq!(
+                errors.into_iter().map(|e| e.0).collect::<Vec<_>>(),
+                vec![InvalidEntry("2".to_string())]
+            );
+        }
+
+        let cases = [
+            (&b".entry 1\nPAUSE"[..], InvalidEntry("1".to_string())),
+            (
+                &b".entry start\nPAUSE"[..],
+                InvalidEntry("start".to_string()),
+            ),
+            (&b".entry 0\n.entry 0"[..], DuplicateEntry),
+            (
+                &b".entry end\nPAUSE\nend:"[..],
+                InvalidEntry("2".to_string()),
+            ),
+        ];
+        for (src, error) in cases {
+            let errors = assemble(src, &isa).unwrap_err();
+            assert_eq!(
+                errors.into_iter().map(|e| e.0).collect::<Vec<_>>(),
+                vec![error]
+            );
         }
     }
+
+    #[test]
+    fn test_symbols() {
+        let isa = Isa::default();
+        let src = b".extern wait\n.global main\nCLR r0\nBRZ main\nBR wait\n.global end";
+        let Module {
+            instrs, symbols, ..
+        } = assemble(src, &isa).ok().unwrap();
+        let br0 = I5::new(0).unwrap();
+        assert_eq!(instrs, vec![Op::CLR(Reg::R0), Op::BRZ(br0), Op::BR(br0)]);
+        assert_eq!(
+            symbols.globals,
+            vec![("main".to_string(), 0), ("end".to_string(), 3)]
+        );
+        assert_eq!(
+            symbols.relocations,
+            vec![(1, "main".to_string()), (2, "wait".to_string())]
+        );
+
+        let symbols = assemble(b".entry 1\nPAUSE\n.global x\nBR x", &isa)
+            .ok()
+            .unwrap()
+            .symbols;
+        assert_eq!(symbols.globals, vec![("x".to_string(), 2)]);
+        assert_eq!(symbols.relocations, vec![(2, "x".to_string())]);
+
+        let errors = assemble(b".global x\n.extern x\nPAUSE", &isa).unwrap_err();
+        assert_eq!(errors[0].0, AsmError::Redefined("x".to_string(), 1));
+    }
+
+    #[test]
+    fn test_absolute() {
+        let isa = Isa::default();
+        let br = |i| Op::BR(I5::new(i).unwrap());
+
+        let instrs = assemble(b"PAUSE\nBR @0\nBR @0x10\nBRZ @2", &isa)
+            .ok()
+            .unwrap()
+            .instrs;
+        assert_eq!(instrs[1..], [br(-1), br(14), Op::BRZ(I5::new(-1).unwrap())]);
+
+        // the jump to the entry point moves everything along by one
+        let instrs = assemble(b".entry 1\nPAUSE\nBR @0", &isa)
+            .ok()
+            .unwrap()
+            .instrs;
+        assert_eq!(instrs[2], br(-2));
+
+        let errors = assemble(b"BR @20", &isa).err().unwrap();
+        assert_eq!(errors[0].0, AsmError::BranchOutOfRange(20, 20));
+
+        let module = assemble(b".global x\nPAUSE\nBR @x", &isa).ok().unwrap();
+        assert_eq!(module.symbols.relocations, vec![(1, "x".to_string())]);
+    }
+
+    #[test]
+    fn test_base() {
+        let isa = Isa::default();
+        let br = |i| Op::BR(I5::new(i).unwrap());
+        let opts = AssembleOptions {
+            base: 4,
+            ..Default::default()
+        };
+
+        let src = b"CLR r0\nBR @4\n.org 7\nloop: BR @5\nBR loop";
+        let module = assemble_with(src, &isa, &opts).ok().unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Op::CLR(Reg::R0),
+                br(-1),
+                Op::ADDI(Reg::R0, U3::new(0).unwrap()),
+                br(-2),
+                br(-1),
+            ]
+        );
+
+        let errors = assemble_with(b"PAUSE\n.org 3", &isa, &opts).unwrap_err();
+        assert_eq!(errors[0].0, AsmError::OrgOverlap(3, 5));
+    }
+
+    #[test]
+    fn test_labels() {
+        let isa = Isa::default();
+        let src = b"CLR r0\nloop: SUBI r0, 1\nBRZ done\nBR @loop\ndone:\nPAUSE";
+        let instrs = assemble(src, &isa).ok().unwrap().instrs;
+        let imm = |n| I5::new(n).unwrap();
+        assert_eq!(
+            instrs,
+            vec![
+                Op::CLR(Reg::R0),
+                Op::SUBI(Reg::R0, U3::new(1).unwrap()),
+                Op::BRZ(imm(2)),
+                Op::BR(imm(-2)),
+                Op::PAUSE,
+            ]
+        );
+
+        // labels don't count as instructions for globals, and work with .entry
+        let module = assemble(
+            b".entry 1\nstart:\nPAUSE\n.global main\nmain: BR start",
+            &isa,
+        )
+        .ok()
+        .unwrap();
+        assert_eq!(module.instrs[2], Op::BR(imm(-1)));
+        assert_eq!(module.symbols.globals, vec![("main".to_string(), 2)]);
+
+        // a branch to a missing label, but a missing constant in data memory
+        let src = b"BR loop\nBR @loop+1\n.byte nowhere\n.data\n.byte size";
+        let errors = assemble(src, &isa).unwrap_err();
+        let errors: Vec<AsmError> = errors.into_iter().map(|e| e.0).collect();
+        assert_eq!(
+            errors,
+            vec![
+                AsmError::UndefinedSymbol("loop".to_string()),
+                AsmError::UndefinedSymbol("loop".to_string()),
+                AsmError::UndefinedSymbol("nowhere".to_string()),
+                AsmError::UndefinedConstant("size".to_string()),
+            ]
+        );
+
+        let errors = assemble(b"a:\na:\nr1:\nPAUSE", &isa).unwrap_err();
+        let errors: Vec<AsmError> = errors.into_iter().map(|e| e.0).collect();
+        assert_eq!(
+            errors,
+            vec![
+                AsmError::Redefined("a".to_string(), 1),
+                AsmError::InvalidLabel("r1".to_string()),
+            ]
+        );
+
+        let src = b"A EQU 1\nB:\nPAUSE\nB EQU 2\nA: PAUSE";
+        let errors: Vec<(AsmError, usize)> = assemble(src, &isa)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.0, e.1))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (AsmError::Redefined("B".to_string(), 2), 4),
+                (AsmError::Redefined("A".to_string(), 1), 5),
+            ]
+        );
+
+        let far = format!("BR end\n{}end: PAUSE", "PAUSE\n".repeat(20));
+        let errors = assemble(far.as_bytes(), &isa).unwrap_err();
+        assert_eq!(errors[0].0, AsmError::BranchOutOfRange(21, 21));
+    }
+
+    #[test]
+    fn test_constants() {
+        let isa = Isa::default();
+        let src = b"SPEED EQU 5\n.equ FAST, SPEED\nADDI r1, SPEED\nSR0 #FAST\n\
+                    ADDI r2, LATER\nLATER equ 2\n.data\n.byte SPEED";
+        let module = assemble(src, &isa).ok().unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Op::ADDI(Reg::R1, U3::new(5).unwrap()),
+                Op::SR0(U4::new(5).unwrap()),
+                Op::ADDI(Reg::R2, U3::new(2).unwrap()),
+            ]
+        );
+        assert_eq!(module.data, vec![5]);
+
+        let src = b"A EQU 1\nA EQU 2\nr1 EQU 3\n.equ B\nC EQU D\nADDI r0, SLOW";
+        let errors: Vec<(AsmError, usize)> = assemble(src, &isa)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.0, e.1))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (AsmError::Redefined("A".to_string(), 1), 2),
+                (AsmError::InvalidConstant("r1".to_string()), 3),
+                (AsmError::MissingImmediate, 4),
+                (AsmError::UndefinedConstant("D".to_string()), 5),
+                (AsmError::UndefinedSymbol("SLOW".to_string()), 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_org() {
+        let isa = Isa::default();
+        let src = b"CLR r0\n.org 3\nloop: PAUSE\n.org 5, 0xff\nBR loop";
+        let module = assemble(src, &isa).ok().unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Op::CLR(Reg::R0),
+                Op::ADDI(Reg::R0, U3::new(0).unwrap()),
+                Op::ADDI(Reg::R0, U3::new(0).unwrap()),
+                Op::PAUSE,
+                Op::PAUSE,
+                Op::BR(I5::new(-2).unwrap()),
+            ]
+        );
+        assert_eq!(module.gaps, vec![1..3, 4..5]);
+
+        let src = b"CLR r0\nCLR r1\n.org 1\n.org x\n.org 4, 1, 2\n.org 2000000000";
+        let errors: Vec<(AsmError, usize)> = assemble(src, &isa)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.0, e.1))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (AsmError::OrgOverlap(1, 2), 3),
+                (AsmError::UndefinedConstant("x".to_string()), 4),
+                (AsmError::ExtraToken("2".to_string()), 5),
+                (AsmError::PastDepth(2000000000, MAX_DEPTH), 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_align() {
+        let isa = Isa::default();
+        let src = b"CLR r0\n.align 4, 0xff\ntable: .byte 1\n.align 1\n.align 2\nBR table\n\
+                    .data\n.byte 1, 2, 3\n.align 4\n.byte 4";
+        let module = assemble(src, &isa).ok().unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Op::CLR(Reg::R0),
+                Op::PAUSE,
+                Op::PAUSE,
+                Op::PAUSE,
+                Op::ADDI(Reg::R1, U3::new(0).unwrap()),
+                Op::ADDI(Reg::R0, U3::new(0).unwrap()),
+                Op::BR(I5::new(-2).unwrap()),
+            ]
+        );
+        assert_eq!(module.data, vec![1, 2, 3, 0, 4]);
+
+        let src = b".align 3\n.align 0\n.align\n.data\n.align -2";
+        let errors: Vec<(AsmError, usize)> = assemble(src, &isa)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.0, e.1))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (AsmError::InvalidAlignment(3), 1),
+                (AsmError::InvalidAlignment(0), 2),
+                (AsmError::MissingImmediate, 3),
+                (AsmError::InvalidAlignment(-2), 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_byte_in_text() {
+        let isa = Isa::default();
+        let src = b".byte 0xff, 0b011000_01\nBR table\ntable: .byte 0\n.data\n.byte 0xf0";
+        let module = assemble(src, &isa).ok().unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Op::PAUSE,
+                Op::CLR(Reg::R1),
+                Op::BR(I5::new(1).unwrap()),
+                Op::ADDI(Reg::R0, U3::new(0).unwrap()),
+            ]
+        );
+        assert_eq!(module.data, vec![0xf0]);
+
+        // a label is its address, like an expression of labels
+        let src = b"PAUSE\n.byte end, end-0\n.fill 1, end\nend: PAUSE";
+        let module = assemble(src, &isa).ok().unwrap();
+        let addi = || Op::ADDI(Reg::R0, U3::new(1).unwrap());
+        assert_eq!(
+            module.instrs,
+            vec![Op::PAUSE, addi(), addi(), addi(), Op::PAUSE]
+        );
+
+        let errors = assemble(b"PAUSE\n.byte 1, 0xf0, 256", &isa).unwrap_err();
+        let errors: Vec<(AsmError, usize)> = errors.into_iter().map(|e| (e.0, e.1)).collect();
+        assert_eq!(
+            errors,
+            vec![
+                (AsmError::ImmediateOutOfRange(256), 2),
+                (AsmError::NotInstruction("0xf0".to_string()), 2),
+            ]
+        );
+
+        // a table needn't decode once program memory may hold data
+        let src = b"BR end\ntable: .byte 0xD5, 0xFE\nend: PAUSE";
+        let module = assemble(src, &WithData(&isa)).ok().unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Decoded::Instr(Op::BR(I5::new(3).unwrap())),
+                Decoded::Data(0xd5),
+                Decoded::Data(0xfe),
+                Decoded::Instr(Op::PAUSE),
+            ]
+        );
+        let isa = WithData(&isa);
+        assert_eq!(isa.format(&module.instrs[1]), ".byte 0xd5");
+    }
+
+    #[test]
+    fn test_fill() {
+        let isa = Isa::default();
+        let src = b"BR end\n.fill 2, 0xff\n.space 1\n.fill 0, 1\nend: PAUSE\n\
+                    .data\n.byte 7\n.fill 2, 9\n.space 3";
+        let module = assemble(src, &isa).ok().unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Op::BR(I5::new(4).unwrap()),
+                Op::PAUSE,
+                Op::PAUSE,
+                Op::ADDI(Reg::R0, U3::new(0).unwrap()),
+                Op::PAUSE,
+            ]
+        );
+        assert_eq!(module.data, vec![7, 9, 9, 0, 0, 0]);
+
+        let src = b".fill 4, 0xf0\n.fill 2\n.space -1\n.space 1, 2\n.fill x, 1\n\
+                    .fill 2000000000, 0\n.data\n.fill 2, 256\n.space 70000";
+        let errors: Vec<(AsmError, usize)> = assemble(src, &isa)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.0, e.1))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (AsmError::NotInstruction("0xf0".to_string()), 1),
+                (AsmError::MissingImmediate, 2),
+                (AsmError::ImmediateOutOfRange(-1), 3),
+                (AsmError::ExtraToken("2".to_string()), 4),
+                (AsmError::UndefinedConstant("x".to_string()), 5),
+                (AsmError::PastDepth(2000000000, MAX_DEPTH), 6),
+                (AsmError::ImmediateOutOfRange(256), 8),
+                (AsmError::PastDepth(70000, MAX_DEPTH), 9),
+            ]
+        );
+
+        // raw words can't be written as source
+        let errors = assemble(b".word 0x60", &isa).unwrap_err();
+        assert_eq!(errors[0].0, AsmError::InvalidMnenomic(".word".to_string()));
+    }
+
+    #[test]
+    fn test_macros() {
+        let isa = Isa::default();
+        let src = b".pseudo NOP: ADDI r0, 0\n\
+                    .macro DELAY n, reg ; count down\n\
+                    ADDI reg, n\n\
+                    SUBI reg, 1\n\
+                    NOP\n\
+                    .endm\n\
+                    DELAY 3, r2\n\
+                    PAUSE";
+        let module = assemble(src, &isa).ok().unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Op::ADDI(Reg::R2, U3::new(3).unwrap()),
+                Op::SUBI(Reg::R2, U3::new(1).unwrap()),
+                Op::ADDI(Reg::R0, U3::new(0).unwrap()),
+                Op::PAUSE,
+            ]
+        );
+
+        // parameters are substituted inside expressions and directives
+        let src = b".macro STEP v, n, x\n\
+                    ADDI r1, (v + 1)\n\
+                    .rept n\nSUBI r1, v\n.endr\n\
+                    .if x == 1\nPAUSE\n.else\nCLR r1\n.endif\n\
+                    .endm\n\
+                    STEP 2, 2, 1\nSTEP 1, 0, 0";
+        let module = assemble(src, &isa).ok().unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Op::ADDI(Reg::R1, U3::new(3).unwrap()),
+                Op::SUBI(Reg::R1, U3::new(2).unwrap()),
+                Op::SUBI(Reg::R1, U3::new(2).unwrap()),
+                Op::PAUSE,
+                Op::ADDI(Reg::R1, U3::new(2).unwrap()),
+                Op::CLR(Reg::R1),
+            ]
+        );
+
+        let src = b".macro SET reg\nCLR reg\n.endm\nSET r5\nSET\n.macro OPEN";
+        let errors: Vec<(AsmError, usize)> = assemble(src, &isa)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.0, e.1))
+            .collect();
+        let in_macro = AsmError::InMacro(
+            Box::new(AsmError::InvalidRegister("r5".to_string())),
+            "SET".to_string(),
+            2,
+        );
+        assert_eq!(in_macro.code(), "E0005");
+        assert_eq!(
+            in_macro.to_string(),
+            "Invalid register \"r5\". In macro \"SET\" from line 2."
+        );
+        assert_eq!(
+            errors,
+            vec![
+                (in_macro, 4),
+                (AsmError::PseudoOperandCount("SET".to_string(), 1, 0), 5),
+                (AsmError::UnterminatedMacro("OPEN".to_string()), 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repeats() {
+        let isa = Isa::default();
+        let src =
+            b".rept 2\nMOVR r1\n.rept 0b10 ; inner\nPAUSE\n.endr\n.endr\n.rept 0\nCLR r0\n.endr";
+        let module = assemble(src, &isa).ok().unwrap();
+        let step = || [Op::MOVR(Reg::R1), Op::PAUSE, Op::PAUSE];
+        assert_eq!(
+            module.instrs,
+            step().into_iter().chain(step()).collect::<Vec<_>>()
+        );
+
+        let src = b".rept -1\n.endr\n.rept x\n.endr\n.rept 2\nPAUSE";
+        let errors: Vec<(AsmError, usize)> = assemble(src, &isa)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.0, e.1))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (AsmError::ImmediateOutOfRange(-1), 1),
+                (AsmError::UndefinedConstant("x".to_string()), 3),
+                (AsmError::UnterminatedRept, 5),
+            ]
+        );
+
+        let src = b".rept 100000000\nPAUSE\n.endr\n\
+                    .rept 300\n.rept 300\n1:\nPAUSE\n.endr\n.endr";
+        let errors: Vec<(AsmError, usize)> = assemble(src, &isa)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.0, e.1))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (AsmError::PastDepth(100000000, MAX_DEPTH), 1),
+                (AsmError::PastDepth(90000, MAX_DEPTH), 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let isa = Isa::default();
+        let src = b"SPEED EQU 2\n\
+                    .if SPEED >= 2\n\
+                    ADDI r0, SPEED\n\
+                    .ifdef FAST\n\
+                    SUBI r0, 1\n\
+                    .else\n\
+                    SUBI r0, 2\n\
+                    .endif\n\
+                    .else\n\
+                    SUB r0\n\
+                    .endif\n\
+                    .ifndef FAST\n\
+                    PAUSE\n\
+                    .endif";
+        let module = assemble(src, &isa).ok().unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Op::ADDI(Reg::R0, U3::new(2).unwrap()),
+                Op::SUBI(Reg::R0, U3::new(2).unwrap()),
+                Op::PAUSE,
+            ]
+        );
+
+        let opts = AssembleOptions {
+            defines: vec![("FAST".to_string(), 1)],
+            ..Default::default()
+        };
+        let module = assemble_with(src, &isa, &opts);
+        assert_eq!(
+            module.ok().unwrap().instrs,
+            vec![
+                Op::ADDI(Reg::R0, U3::new(2).unwrap()),
+                Op::SUBI(Reg::R0, U3::new(1).unwrap()),
+            ]
+        );
+
+        let src = b".if 1 = 1\n.endif\n.else\n.if 0\n.if LATER\n.endif";
+        let errors: Vec<(AsmError, usize)> = assemble(src, &isa)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.0, e.1))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (AsmError::InvalidCondition("1 = 1".to_string()), 1),
+                (AsmError::UnmatchedDirective(".else".to_string()), 3),
+                (AsmError::UnterminatedIf, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expressions() {
+        let isa = Isa::default();
+        let src = b"MAX EQU 7\nHALF EQU (MAX-1)/2\nADDI r0, (MAX-1)/2\nSR0 #HALF<<1\nBR -1-1";
+        let module = assemble(src, &isa).ok().unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Op::ADDI(Reg::R0, U3::new(3).unwrap()),
+                Op::SR0(U4::new(6).unwrap()),
+                Op::BR(I5::new(-2).unwrap()),
+            ]
+        );
+
+        // spaces don't split an expression, but still separate operands
+        let src = b"MAX EQU 7\nHALF EQU ( MAX - 1 ) / 2\nADDI r0, (MAX - 1) / 2\n\
+                    ADDI r1 MAX - HALF\nSR0 #HALF << 1\nBR -1 - 1\n\
+                    .if MAX - 1 > 5\nADDI r2, MAX %4\n.endif";
+        let module = assemble(src, &isa).ok().unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Op::ADDI(Reg::R0, U3::new(3).unwrap()),
+                Op::ADDI(Reg::R1, U3::new(4).unwrap()),
+                Op::SR0(U4::new(6).unwrap()),
+                Op::BR(I5::new(-2).unwrap()),
+                Op::ADDI(Reg::R2, U3::new(3).unwrap()),
+            ]
+        );
+        let errors = assemble(b"BR -1 -1", &isa).unwrap_err();
+        assert_eq!(errors[0].0, AsmError::ExtraToken("-1".to_string()));
+
+        let errors = assemble(b"ADDI r0, 8/0\nADDI r0, MIN+1\nADDI r0, 4*2", &isa).unwrap_err();
+        let errors: Vec<(AsmError, usize)> = errors.into_iter().map(|e| (e.0, e.1)).collect();
+        assert_eq!(
+            errors,
+            vec![
+                (AsmError::InvalidExpression("8/0".to_string()), 1),
+                (AsmError::UndefinedSymbol("MIN".to_string()), 2),
+                (AsmError::ImmediateOutOfRange(8), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_label_arithmetic() {
+        let isa = Isa::default();
+        let src = b"ONE EQU 1\n\
+                    start: BR end-start\n\
+                    table: .byte 0x01, 0x05\n\
+                    table_end: .byte table_end-table+ONE\n\
+                    end: ADDI r0, end-table";
+        let module = assemble(src, &isa).ok().unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Op::BR(I5::new(4).unwrap()),
+                Op::ADDI(Reg::R1, U3::new(0).unwrap()),
+                Op::ADDI(Reg::R1, U3::new(1).unwrap()),
+                Op::ADDI(Reg::R3, U3::new(0).unwrap()),
+                Op::ADDI(Reg::R0, U3::new(3).unwrap()),
+            ]
+        );
+
+        // the whole operand is one expression, spaces and all
+        let src = b"BR end\nTABLE: .byte 1, 2\nTABLE_END: .byte TABLE_END - TABLE\n\
+                    .fill 1, TABLE_END - TABLE + 1\nend: PAUSE";
+        let module = assemble(src, &isa).ok().unwrap();
+        assert_eq!(
+            module.instrs[3..5]
+                .iter()
+                .map(Op::to_binary)
+                .collect::<Vec<u8>>(),
+            vec![2, 3]
+        );
+
+        let src = b"a: .byte b-a+0x200\nb: ADDI r0, b/(a-a)\nBR b-c";
+        let errors: Vec<(AsmError, usize)> = assemble(src, &isa)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.0, e.1))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (AsmError::ImmediateOutOfRange(0x201), 1),
+                (AsmError::InvalidExpression("b/(a-a)".to_string()), 2),
+                (AsmError::UndefinedSymbol("c".to_string()), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_current_address() {
+        let isa = Isa::default();
+        let src = b"START EQU 1\nCLR r0\nBRZ @$+2\nBR $\ntable: .byte 1, 2\n\
+                    ADDI r0, $-table\nBR @START";
+        let module = assemble(src, &isa).ok().unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Op::CLR(Reg::R0),
+                Op::BRZ(I5::new(2).unwrap()),
+                Op::BR(I5::new(0).unwrap()),
+                Op::ADDI(Reg::R1, U3::new(0).unwrap()),
+                Op::ADDI(Reg::R2, U3::new(0).unwrap()),
+                Op::ADDI(Reg::R0, U3::new(2).unwrap()),
+                Op::BR(I5::new(-5).unwrap()),
+            ]
+        );
+
+        let errors: Vec<(AsmError, usize)> = assemble(b"X EQU $\n.byte $", &isa)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.0, e.1))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (AsmError::InvalidImmediate("$".to_string()), 1),
+                (AsmError::InvalidImmediate("$".to_string()), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_local_labels() {
+        let isa = Isa::default();
+        let src = b"1: SUBI r0, 1\n\
+                    BRZ 1f\n\
+                    BR 1b\n\
+                    1:\n\
+                    2: BR 1b\n\
+                    BR 2b\n\
+                    BR 0b11";
+        let module = assemble(src, &isa).ok().unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Op::SUBI(Reg::R0, U3::new(1).unwrap()),
+                Op::BRZ(I5::new(2).unwrap()),
+                Op::BR(I5::new(-2).unwrap()),
+                Op::BR(I5::new(0).unwrap()),
+                Op::BR(I5::new(-1).unwrap()),
+                Op::BR(I5::new(3).unwrap()),
+            ]
+        );
+
+        let errors = assemble(b"BR 1b\n1: BR 1f\nBR 1", &isa).unwrap_err();
+        let errors: Vec<(AsmError, usize)> = errors.into_iter().map(|e| (e.0, e.1)).collect();
+        assert_eq!(
+            errors,
+            vec![
+                (AsmError::InvalidLabel("1b".to_string()), 1),
+                (AsmError::InvalidLabel("1f".to_string()), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ldi() {
+        use crate::isa::InstrSpec;
+
+        let isa = Isa::default();
+        let src = b"TOP EQU 255\nLDI r1, 0\nLDI r2, 9\nLDI r3, TOP\nLDI r0, -9\nend: BR end";
+        let module = assemble(src, &isa).ok().unwrap();
+        let u3 = |n| U3::new(n).unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Op::CLR(Reg::R1),
+                Op::CLR(Reg::R2),
+                Op::ADDI(Reg::R2, u3(7)),
+                Op::ADDI(Reg::R2, u3(2)),
+                Op::CLR(Reg::R3),
+                Op::SUBI(Reg::R3, u3(1)),
+                Op::CLR(Reg::R0),
+                Op::SUBI(Reg::R0, u3(7)),
+                Op::SUBI(Reg::R0, u3(2)),
+                Op::BR(I5::new(0).unwrap()),
+            ]
+        );
+        assert_eq!(
+            module.notes,
+            vec![
+                (0, "LDI r1, 0: 1 instruction".to_string()),
+                (1, "LDI r2, 9: 3 instructions".to_string()),
+                (4, "LDI r3, 255: 2 instructions".to_string()),
+                (6, "LDI r0, -9: 3 instructions".to_string()),
+            ]
+        );
+
+        // steps are as large as the target's immediates
+        let spec = IsaSpec::new(
+            "wide",
+            16,
+            vec![
+                InstrSpec::new("ADDI", "0000_00rr_iiii_iiii", &["r:reg", "i:unsigned"]).unwrap(),
+                InstrSpec::new("SUBI", "0001_00rr_iiii_iiii", &["r:reg", "i:unsigned"]).unwrap(),
+                InstrSpec::new("CLR", "0011_0000_0000_00rr", &["r:reg"]).unwrap(),
+            ],
+        )
+        .unwrap();
+        let module = assemble::<u16, _>(b"LDI r1, 100\nLDI r2, 200", &spec)
+            .ok()
+            .unwrap();
+        assert_eq!(module.instrs, vec![0x3001, 0x0164, 0x3002, 0x1238]);
+        let spec = IsaSpec::new("bare", 8, vec![]).unwrap();
+        let errors = assemble::<u8, _>(b"LDI r1, 100", &spec).unwrap_err();
+        assert_eq!(errors[0].0, AsmError::InvalidMnenomic("LDI".to_string()));
+
+        let errors = assemble(b"LDI r1\nLDI r9, 1\nLDI r1, 256\nLDI r1, 1, 2", &isa).unwrap_err();
+        let errors: Vec<(AsmError, usize)> = errors.into_iter().map(|e| (e.0, e.1)).collect();
+        assert_eq!(
+            errors,
+            vec![
+                (AsmError::MissingImmediate, 1),
+                (AsmError::InvalidRegister("r9".to_string()), 2),
+                (AsmError::ImmediateOutOfRange(256), 3),
+                (AsmError::ExtraToken("2".to_string()), 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_jmp() {
+        let isa = Isa::default();
+        let src = b"JMP end\nstart: CLR r0\nJMP start\nJMP 0\nend: JMP @end";
+        let module = assemble(src, &isa).ok().unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Op::BR(I5::new(4).unwrap()),
+                Op::CLR(Reg::R0),
+                Op::BR(I5::new(-1).unwrap()),
+                Op::BR(I5::new(-3).unwrap()),
+                Op::BR(I5::new(0).unwrap()),
+            ]
+        );
+
+        let src = b"JMP far\nJMP\nJMP a, b\n.org 20\nfar: PAUSE";
+        let errors: Vec<(AsmError, usize)> = assemble(src, &isa)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.0, e.1))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (AsmError::BranchOutOfRange(20, 20), 1),
+                (AsmError::MissingImmediate, 2),
+                (AsmError::ExtraToken("b".to_string()), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_columns() {
+        let isa = Isa::default();
+        let src = b"PAUSE\n\tADDI r0, x ; x is undefined\nCLR r0 \\ MOV r1 r9\nSUBI r0, 9\n\
+                    MOV r1 r2 r1\nCLR r1 CLR\nADDI r0, ADDI\nMOV r1\n  ,\nPAUSE \\ ,,";
+        let errors = assemble(src, &isa).unwrap_err();
+        let printed: Vec<String> = errors
+            .iter()
+            .map(|e| e.localized_in(src, e.line(), Lang::En))
+            .collect();
+        assert_eq!(
+            printed,
+            vec![
+                "Line 2, column 11: error[E0036]: \"x\" is not defined as a label or a constant.\n    \
+                 \tADDI r0, x ; x is undefined\n    \t         ^",
+                "Line 3, column 17: error[E0005]: Invalid register \"r9\". In statement 2.\n    \
+                 CLR r0 \\ MOV r1 r9\n                    ^^",
+                "Line 4, column 10: error[E0002]: Immediate 9 is out of range.\n    \
+                 SUBI r0, 9\n             ^",
+                // repeated tokens are found where they were written
+                "Line 5, column 11: error[E0007]: Unexpected token \"r1\".\n    \
+                 MOV r1 r2 r1\n              ^^",
+                "Line 6, column 8: error[E0007]: Unexpected token \"CLR\".\n    \
+                 CLR r1 CLR\n           ^^^",
+                "Line 7, column 10: error[E0036]: \"ADDI\" is not defined as a label or a constant.\n    \
+                 ADDI r0, ADDI\n             ^^^^",
+                // a missing operand is just past the last one
+                "Line 8, column 7: error[E0006]: Missing a register.\n    \
+                 MOV r1\n          ^",
+                // a line of only commas is at the first
+                "Line 9, column 3: error[E0007]: Unexpected token \",\".\n    \
+                 \x20 ,\n      ^",
+                "Line 10, column 9: error[E0007]: Unexpected token \",\". In statement 2.\n    \
+                 PAUSE \\ ,,\n            ^",
+            ]
+        );
+
+        // a token replaced by a pass is still found where it was written
+        let opts = AssembleOptions {
+            syntax: Syntax::Strict,
+            ..Default::default()
+        };
+        let src = b"SPEED EQU 9\nADDI r0, #SPEED\nMOV r1 r2";
+        let errors = assemble_with(src, &isa, &opts).unwrap_err();
+        let printed: Vec<String> = errors
+            .iter()
+            .map(|e| e.localized_in(src, e.line(), Lang::En))
+            .collect();
+        assert_eq!(
+            printed,
+            vec![
+                "Line 2, column 10: error[E0002]: Immediate 9 is out of range.\n    \
+                 ADDI r0, #SPEED\n             ^^^^^^",
+                "Line 3, column 8: error[E0018]: Operands must be separated by commas.\n    \
+                 MOV r1 r2\n           ^^",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_warnings() {
+        let isa = Isa::default();
+        let src = b".macro ZERO reg\nSUBI reg, 0\n.endm\n\
+                    MOV r1, r1\nADDI r0, 0\nZERO r2\n.byte 0x44\nSUBI r3, 0";
+        let mut warnings = vec![];
+        let module = assemble_warned(src, &isa, &AssembleOptions::default(), &mut warnings);
+        assert!(module.is_ok());
+        assert_eq!(
+            warnings,
+            vec![
+                (4, AsmWarning::NoEffect("MOV r1, r1".to_string())),
+                (8, AsmWarning::NoEffect("SUBI r3, 0".to_string())),
+            ]
+        );
+        assert_eq!(
+            messages::asm_warning(&warnings[0].1, Lang::En),
+            "\"MOV r1, r1\" has no effect."
+        );
+
+        // silenced on one line, or the whole file
+        let mut warnings = vec![];
+        let src = b"MOV r1, r1 ;#allow(no_effect)\nMOV r2, r2";
+        assert!(assemble_warned(src, &isa, &AssembleOptions::default(), &mut warnings).is_ok());
+        assert_eq!(
+            warnings,
+            vec![(2, AsmWarning::NoEffect("MOV r2, r2".to_string()))]
+        );
+        let mut warnings = vec![];
+        let src = b"MOV r1, r1\n.allow deprecated, no_effect\nSUBI r3, 0";
+        assert!(assemble_warned(src, &isa, &AssembleOptions::default(), &mut warnings).is_ok());
+        assert_eq!(warnings, vec![]);
+
+        let src = b".allow noeffect\n.allow\nPAUSE ; #allow(no_effect, unused)";
+        let errors = assemble(src, &isa).unwrap_err();
+        assert_eq!(
+            errors.into_iter().map(|e| (e.0, e.1)).collect::<Vec<_>>(),
+            vec![
+                (AsmError::UnknownWarning("noeffect".to_string()), 1),
+                (AsmError::UnknownWarning(String::new()), 2),
+                (AsmError::UnknownWarning("unused".to_string()), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recovery() {
+        let isa = Isa::default();
+        let errors = |src: &[u8], syntax| -> Vec<(AsmError, usize)> {
+            let opts = AssembleOptions {
+                syntax,
+                ..Default::default()
+            };
+            assemble_with(src, &isa, &opts)
+                .unwrap_err()
+                .into_iter()
+                .map(|e| (e.0, e.1))
+                .collect()
+        };
+        assert_eq!(
+            errors(b"ADDI rr5, 99\nMOV r9 r8 r7", Syntax::Permissive),
+            vec![
+                (AsmError::InvalidRegister("rr5".to_string()), 1),
+                (AsmError::ImmediateOutOfRange(99), 1),
+                (AsmError::InvalidRegister("r9".to_string()), 2),
+                (AsmError::InvalidRegister("r8".to_string()), 2),
+                (AsmError::ExtraToken("r7".to_string()), 2),
+            ]
+        );
+        assert_eq!(
+            errors(b"ADDI rr5 99", Syntax::Strict),
+            vec![
+                (AsmError::MissingComma, 1),
+                (AsmError::MissingHash("rr5".to_string()), 1),
+                (AsmError::MissingHash("99".to_string()), 1),
+                (AsmError::InvalidRegister("rr5".to_string()), 1),
+                (AsmError::ImmediateOutOfRange(99), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_statements() {
+        let isa = Isa::default();
+        let src = b"CLR r0 \\ ADDI r0, 5 \\ PAUSE ; done \\ ignored\nloop: SUBI r0, 1 \\ BR loop\\";
+        let module = assemble(src, &isa).ok().unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Op::CLR(Reg::R0),
+                Op::ADDI(Reg::R0, U3::new(5).unwrap()),
+                Op::PAUSE,
+                Op::SUBI(Reg::R0, U3::new(1).unwrap()),
+                Op::BR(I5::new(-1).unwrap()),
+            ]
+        );
+
+        let errors: Vec<(AsmError, usize)> = assemble(b"PAUSE\nCLR r0 \\ ADDI r9, 1", &isa)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.0, e.1))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![(
+                AsmError::InStatement(Box::new(AsmError::InvalidRegister("r9".to_string())), 2),
+                2
+            )]
+        );
+    }
+
+    #[test]
+    fn test_aliases() {
+        let isa = Isa::default();
+        let src = b".def counter = r2\n\
+                    .def step, R1\n\
+                    loop: MOV counter, step\n\
+                    .def step = r3 ; reused\n\
+                    CLR step\n\
+                    ADDI counter, 1\n\
+                    BR loop";
+        let module = assemble(src, &isa).ok().unwrap();
+        assert_eq!(
+            module.instrs,
+            vec![
+                Op::MOV(Reg::R2, Reg::R1),
+                Op::CLR(Reg::R3),
+                Op::ADDI(Reg::R2, U3::new(1).unwrap()),
+                Op::BR(I5::new(-3).unwrap()),
+            ]
+        );
+        assert_eq!(
+            module.aliases,
+            vec![
+                ("counter".to_string(), "r2".to_string()),
+                ("step".to_string(), "r3".to_string()),
+            ]
+        );
+        assert_eq!(module.show_aliases("MOV r2, r3"), "MOV counter, step");
+        assert_eq!(module.show_aliases("CLR r0"), "CLR r0");
+
+        let src = b".def r4 = r2\n.def x = 5\n.def y\n.def z = r1 r2";
+        let errors: Vec<(AsmError, usize)> = assemble(src, &isa)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.0, e.1))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (AsmError::InvalidAlias("r4".to_string()), 1),
+                (AsmError::InvalidRegister("5".to_string()), 2),
+                (AsmError::MissingRegister, 3),
+                (AsmError::ExtraToken("r2".to_string()), 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_programs() {
+        let source = b".pseudo NOP: ADDI r0, 0\n\
+                       .program one\n\
+                       NOP\n\
+                       .program two ; second\n\
+                       PAUSE\r\n\
+                       CLR r0";
+        let programs = split_programs(source).unwrap();
+        assert_eq!(programs.len(), 2);
+        assert_eq!(programs[0].0, "one");
+        assert_eq!(programs[0].1, b".pseudo NOP: ADDI r0, 0\n\nNOP\n\n\n");
+        assert_eq!(programs[1].0, "two");
+        assert_eq!(
+            programs[1].1,
+            b".pseudo NOP: ADDI r0, 0\n\n\n\nPAUSE\r\nCLR r0"
+        );
+
+        let isa = Isa::default();
+        let one = assemble(&programs[0].1, &isa).ok().unwrap();
+        assert_eq!(one.instrs, vec![Op::ADDI(Reg::R0, U3::new(0).unwrap())]);
+
+        assert_eq!(split_programs(b"PAUSE").unwrap(), vec![]);
+        assert!(split_programs(b".program a\n.program a").is_none());
+        assert!(split_programs(b".program").is_none());
+    }
+
+    #[test]
+    fn test_data() {
+        use AsmError::*;
+
+        let isa = Isa::default();
+        let src = b".data\n.byte 1, 255 -1\n.text\nPAUSE\n.data\n.byte 7";
+        let module = assemble(src, &isa).ok().unwrap();
+        assert_eq!(module.instrs, vec![Op::PAUSE]);
+        assert_eq!(module.data, vec![1, 255, 255, 7]);
+
+        let errors = assemble(b".data\n.byte 256, x\nPAUSE", &isa).unwrap_err();
+        assert_eq!(
+            errors.into_iter().map(|e| e.0).collect::<Vec<_>>(),
+            vec![
+                ImmediateOutOfRange(256),
+                UndefinedConstant("x".to_string()),
+                NotData("PAUSE".to_string()),
+            ]
+        );
+
+        // wider data words hold wider values, sign extended to their width
+        let opts = AssembleOptions {
+            data_width: 12,
+            ..Default::default()
+        };
+        let src = b".data\n.byte 300, -1\n.fill 2, 4095";
+        let module = assemble_with(src, &isa, &opts).ok().unwrap();
+        assert_eq!(module.data, vec![300, 0xfff, 4095, 4095]);
+        let errors = assemble_with(b".data\n.byte 4096, -2049", &isa, &opts).unwrap_err();
+        assert_eq!(
+            errors.into_iter().map(|e| e.0).collect::<Vec<_>>(),
+            vec![ImmediateOutOfRange(4096), ImmediateOutOfRange(-2049)]
+        );
+    }
+
+    #[test]
+    fn test_split_lines() {
+        let lines = split_lines(b"\xef\xbb\xbfPAUSE\r\nCLR r0\n\xffBR 0\r\nMOVA r2");
+        let lines: Vec<Result<(String, usize), usize>> = lines
+            .into_iter()
+            .map(|l| l.map(|l| (l.string, l.lineno)).map_err(|e| e.1))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                Ok(("PAUSE".to_string(), 1)),
+                Ok(("CLR r0".to_string(), 2)),
+                Err(3),
+                Ok(("MOVA r2".to_string(), 4)),
+            ]
+        );
+    }
 }
@@ -1,47 +1,311 @@
 use std::{fmt::Display, fs::File, io::Read};
 
 use crate::{
-    op::{InvalidOpcode, Op},
+    instruction_set::InstructionSet,
+    messages::{self, Lang, ERROR_LIMIT},
+    op::{InvalidOpcode, Isa},
+    word::Word,
     Program,
 };
 
 #[derive(Debug)]
-enum BinaryFileError {
+pub(crate) enum BinaryFileError {
     ReadError,
-    BinaryError(InvalidOpcode, usize),
+    /// The bad opcode, its address, and notes on what might be wrong.
+    BinaryError(InvalidOpcode, usize, Vec<String>),
+    PartialWord(usize),
 }
 
 impl Display for BinaryFileError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             BinaryFileError::ReadError => write!(f, "Failed to read file."),
-            BinaryFileError::BinaryError(e, position) => {
-                write!(f, "Error at 0x{position:04x}: {e}")
+            BinaryFileError::PartialWord(len) => {
+                write!(f, "File length {len} is not a whole number of words.")
+            }
+            BinaryFileError::BinaryError(e, address, notes) => {
+                write!(f, "Error[{}] at 0x{address:04x}: {e}", e.code())?;
+                for note in notes {
+                    write!(f, "\n    {note}")?;
+                }
+                Ok(())
             }
         }
     }
 }
 
-pub fn parse_file(file: File) -> Option<Program> {
-    fn inner(mut file: File) -> Result<Program, BinaryFileError> {
-        use BinaryFileError::*;
+pub fn parse_file(file: File, isa: &Isa) -> Option<Program> {
+    parse_file_with(file, isa).map(|ops| Program { ops, data: vec![] })
+}
 
-        let mut contents = vec![];
-        file.read_to_end(&mut contents).or(Err(ReadError))?;
+/// Read machine code for any instruction set, checking that every word
+/// decodes to an instruction. Words are stored big-endian in `W::BYTES` bytes.
+pub fn parse_file_with<W: Word, I: InstructionSet<W>>(
+    file: File,
+    isa: &I,
+) -> Option<Vec<I::Instr>> {
+    parse_file_at(file, isa, 0)
+}
 
-        contents
-            .iter()
-            .enumerate()
-            .map(|(idx, b)| (*b).try_into().map_err(|e| BinaryError(e, idx)))
-            .collect::<Result<Vec<Op>, BinaryFileError>>()
-            .map(|ops| Program { ops })
+/// Like [`parse_file_with`] for an image loaded at word address `base`, so
+/// errors give the address in memory rather than in the file. Reads from any
+/// reader, e.g. part of a file.
+pub fn parse_file_at<W: Word, I: InstructionSet<W>, R: Read>(
+    mut file: R,
+    isa: &I,
+    base: usize,
+) -> Option<Vec<I::Instr>> {
+    let mut contents = vec![];
+    let result = match file.read_to_end(&mut contents) {
+        Ok(_) => decode(&contents, isa, base),
+        Err(_) => Err(vec![BinaryFileError::ReadError]),
+    };
+
+    match result {
+        Ok(instrs) => Some(instrs),
+        Err(errors) => {
+            print_errors(&errors);
+            None
+        }
     }
+}
+
+/// Print `errors`, up to [`ERROR_LIMIT`] of them and then how many more
+/// there were.
+fn print_errors(errors: &[BinaryFileError]) {
+    for e in errors.iter().take(ERROR_LIMIT) {
+        eprintln!("{e}");
+    }
+    if errors.len() > ERROR_LIMIT {
+        eprintln!(
+            "{}",
+            messages::more_errors(errors.len() - ERROR_LIMIT, Lang::En)
+        );
+    }
+}
+
+/// A word of machine code, see [`parse_flow_at`].
+#[derive(Debug, PartialEq)]
+pub enum Decoded<I, W> {
+    Instr(I),
+    /// A word no path from the first instruction reaches, e.g. a table.
+    Data(W),
+}
 
-    match inner(file) {
-        Ok(program) => Some(program),
-        Err(e) => {
-            println!("{e}");
+/// Like [`parse_file_at`], only decoding the words reached by following
+/// branches from the first word. The rest are left as data, so tables in
+/// the program memory aren't mistaken for instructions.
+pub fn parse_flow_at<W: Word, I: InstructionSet<W>, R: Read>(
+    mut file: R,
+    isa: &I,
+    base: usize,
+) -> Option<Vec<Decoded<I::Instr, W>>> {
+    let mut contents = vec![];
+    let result = match file.read_to_end(&mut contents) {
+        Ok(_) => decode_flow(&contents, isa, base),
+        Err(_) => Err(vec![BinaryFileError::ReadError]),
+    };
+
+    match result {
+        Ok(decoded) => Some(decoded),
+        Err(errors) => {
+            print_errors(&errors);
             None
         }
     }
 }
+
+/// Decode the words reachable from the first, reporting every reachable
+/// word that isn't an instruction.
+pub(crate) fn decode_flow<W: Word, I: InstructionSet<W>>(
+    contents: &[u8],
+    isa: &I,
+    base: usize,
+) -> Result<Vec<Decoded<I::Instr, W>>, Vec<BinaryFileError>> {
+    if !contents.len().is_multiple_of(W::BYTES) {
+        return Err(vec![BinaryFileError::PartialWord(contents.len())]);
+    }
+
+    let words: Vec<W> = contents.chunks(W::BYTES).map(W::read_be).collect();
+    let mut decoded: Vec<Option<Result<I::Instr, InvalidOpcode>>> =
+        words.iter().map(|_| None).collect();
+    let mut pending = vec![0];
+    while let Some(idx) = pending.pop() {
+        if idx >= words.len() || decoded[idx].is_some() {
+            continue;
+        }
+        let instr = isa.decode(words[idx]);
+        if let Ok(instr) = &instr {
+            let target = isa.branch_offset(instr).map(|o| idx as i64 + o as i64);
+            if let Some(target) = target.filter(|t| *t >= 0) {
+                pending.push(target as usize);
+            }
+            if isa.falls_through(instr) {
+                pending.push(idx + 1);
+            }
+        }
+        decoded[idx] = Some(instr);
+    }
+
+    let mut errors = vec![];
+    let decoded: Vec<Decoded<I::Instr, W>> = decoded
+        .into_iter()
+        .zip(&words)
+        .enumerate()
+        .filter_map(|(idx, (d, word))| match d {
+            Some(Ok(instr)) => Some(Decoded::Instr(instr)),
+            Some(Err(e)) => {
+                let notes = diagnose(isa, &words, idx, base);
+                errors.push(BinaryFileError::BinaryError(e, base + idx, notes));
+                None
+            }
+            None => Some(Decoded::Data(*word)),
+        })
+        .collect();
+
+    match errors.is_empty() {
+        true => Ok(decoded),
+        false => Err(errors),
+    }
+}
+
+/// Decode every word of `contents`, reporting every word that isn't an
+/// instruction rather than stopping at the first.
+pub(crate) fn decode<W: Word, I: InstructionSet<W>>(
+    contents: &[u8],
+    isa: &I,
+    base: usize,
+) -> Result<Vec<I::Instr>, Vec<BinaryFileError>> {
+    if !contents.len().is_multiple_of(W::BYTES) {
+        return Err(vec![BinaryFileError::PartialWord(contents.len())]);
+    }
+
+    let words: Vec<W> = contents.chunks(W::BYTES).map(W::read_be).collect();
+    let mut errors = vec![];
+    let instrs: Vec<I::Instr> = words
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, word)| {
+            isa.decode(*word)
+                .map_err(|e| {
+                    let notes = diagnose(isa, &words, idx, base);
+                    errors.push(BinaryFileError::BinaryError(e, base + idx, notes))
+                })
+                .ok()
+        })
+        .collect();
+
+    match errors.is_empty() {
+        true => Ok(instrs),
+        false => Err(errors),
+    }
+}
+
+/// Notes on the word at `idx` that failed to decode: its value among its
+/// neighbours, the unused bit pattern it falls in, and the instructions one
+/// bit away, to tell a misaligned or wrong file from a corrupt one.
+fn diagnose<W: Word, I: InstructionSet<W>>(
+    isa: &I,
+    words: &[W],
+    idx: usize,
+    base: usize,
+) -> Vec<String> {
+    let width = isa.width() as usize;
+    let word: u32 = words[idx].into();
+    let mut notes = vec![];
+
+    let start = idx.saturating_sub(4);
+    let end = (idx + 5).min(words.len());
+    let hex: Vec<String> = (start..end)
+        .map(|n| {
+            let w = format!("{:0digits$x}", words[n].into(), digits = W::BYTES * 2);
+            match n == idx {
+                true => format!("[{w}]"),
+                false => w,
+            }
+        })
+        .collect();
+    notes.push(format!("0x{:04x}: {}", base + start, hex.join(" ")));
+
+    let decodes = |w: u32| isa.decode(W::truncate(w)).ok();
+    let unused = (1..=width).find(|k| {
+        let free = width - k;
+        let prefix = word >> free << free;
+        (0..1u32 << free).all(|low| decodes(prefix | low).is_none())
+    });
+    if let Some(k) = unused {
+        let bits: Vec<char> = (0..width)
+            .map(|n| match n < k {
+                true => char::from(b'0' + ((word >> (width - 1 - n)) & 1) as u8),
+                false => 'x',
+            })
+            .collect();
+        let groups: Vec<String> = bits.chunks(4).map(|c| c.iter().collect()).collect();
+        let pattern = groups.join("_");
+        notes.push(format!("bit pattern {pattern} is unused in this ISA"));
+    }
+
+    let nearest: Vec<String> = (0..width)
+        .rev()
+        .map(|bit| word ^ (1 << bit))
+        .filter_map(|w| decodes(w).map(|i| format!("{w:0width$b} ({})", isa.format(&i))))
+        .collect();
+    if !nearest.is_empty() {
+        notes.push(format!("nearest valid encodings: {}", nearest.join(", ")));
+    }
+    notes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let isa = Isa::default();
+        assert_eq!(decode(&[0xff, 0x00], &isa, 0).unwrap().len(), 2);
+
+        // the io extension's opcodes are invalid without it
+        let errors = decode(&[0b111100_00, 0xff, 0b111101_01], &isa, 0x40).unwrap_err();
+        let addresses: Vec<usize> = errors
+            .iter()
+            .map(|e| match e {
+                BinaryFileError::BinaryError(_, addr, _) => *addr,
+                _ => panic!("{e}"),
+            })
+            .collect();
+        assert_eq!(addresses, vec![0x40, 0x42]);
+    }
+
+    #[test]
+    fn test_decode_flow() {
+        use crate::op::Op;
+
+        let isa = Isa::default();
+        // BR 3 jumps over a table of two words that aren't instructions
+        let code = [0b100_00011, 0b1111_0000, 0b1111_0001, 0b011000_00, 0xff];
+        assert!(decode(&code, &isa, 0).is_err());
+        let decoded = decode_flow(&code, &isa, 0).unwrap();
+        assert_eq!(decoded[1], Decoded::Data(0b1111_0000));
+        assert_eq!(decoded[2], Decoded::Data(0b1111_0001));
+        assert_eq!(decoded[4], Decoded::Instr(Op::PAUSE));
+
+        // a reachable bad word is still an error
+        let errors = decode_flow(&[0b011000_00, 0b1111_0000], &isa, 0).unwrap_err();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let isa = Isa::default();
+        let words = [0x00u8, 0xff, 0b1111_0000, 0x12];
+        assert_eq!(
+            diagnose(&isa, &words, 2, 0x40),
+            vec![
+                "0x0040: 00 ff [f0] 12",
+                "bit pattern 1111_0xxx is unused in this ISA",
+                "nearest valid encodings: 01110000 (MOV r0, r0), 10110000 (BRZ -16)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,60 @@
+use crate::word::{self, Word};
+
+/// CRC-32 (IEEE 802.3, as used by zip and Ethernet) of `bytes`.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in bytes {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// CRC-32 of machine code, over the words as stored in a HEX file.
+pub fn image_crc32<W: Word>(words: &[W]) -> u32 {
+    crc32(&word::to_bytes(words))
+}
+
+/// Pad `words` with zeros up to `addr`, then store the CRC-32 of everything
+/// before `addr` there, most significant word first. Returns the CRC, or
+/// `None` if `addr` is inside the program.
+pub fn embed_crc32<W: Word>(words: &mut Vec<W>, addr: usize) -> Option<u32> {
+    if addr < words.len() {
+        return None;
+    }
+    words.resize(addr, W::truncate(0));
+
+    let crc = image_crc32(words);
+    let bytes = crc.to_be_bytes();
+    words.extend(bytes.chunks(W::BYTES).map(W::read_be));
+    Some(crc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_embed() {
+        let mut words = vec![0x31u8, 0x32];
+        assert_eq!(embed_crc32(&mut words, 1), None);
+
+        let crc = embed_crc32(&mut words, 3).unwrap();
+        assert_eq!(crc, crc32(&[0x31, 0x32, 0]));
+        assert_eq!(words.len(), 7);
+        assert_eq!(words[3..], crc.to_be_bytes());
+
+        let mut words = vec![0x1234u16];
+        let crc = embed_crc32(&mut words, 1).unwrap();
+        assert_eq!(words[1..], [(crc >> 16) as u16, crc as u16]);
+    }
+}
@@ -0,0 +1,216 @@
+use std::fmt::Display;
+
+/// Bit layout of an instruction word, written MSB first.
+///
+/// `0` and `1` are fixed opcode bits and letters mark the bits of an operand
+/// field, e.g. `"100iiiii"` for a branch with a 5-bit immediate `i`.
+/// Underscores may be used as separators and are ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Encoding {
+    width: u8,
+    fixed_mask: u32,
+    fixed_bits: u32,
+    fields: Vec<(char, u32)>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EncodingError {
+    Empty,
+    TooWide(usize),
+    InvalidChar(char),
+}
+
+impl Display for EncodingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncodingError::Empty => write!(f, "Encoding has no bits."),
+            EncodingError::TooWide(n) => write!(f, "Encoding has {n} bits, at most 32 allowed."),
+            EncodingError::InvalidChar(c) => write!(f, "Invalid character '{c}' in encoding."),
+        }
+    }
+}
+
+impl Encoding {
+    pub fn parse(pattern: &str) -> Result<Encoding, EncodingError> {
+        let bits: Vec<char> = pattern.chars().filter(|c| *c != '_').collect();
+        if bits.is_empty() {
+            return Err(EncodingError::Empty);
+        }
+        if bits.len() > 32 {
+            return Err(EncodingError::TooWide(bits.len()));
+        }
+
+        let mut encoding = Encoding {
+            width: bits.len() as u8,
+            fixed_mask: 0,
+            fixed_bits: 0,
+            fields: vec![],
+        };
+
+        for (n, c) in bits.iter().enumerate() {
+            let bit = 1 << (bits.len() - 1 - n);
+            match c {
+                '0' => encoding.fixed_mask |= bit,
+                '1' => {
+                    encoding.fixed_mask |= bit;
+                    encoding.fixed_bits |= bit;
+                }
+                c if c.is_ascii_alphabetic() => match encoding.field_mut(*c) {
+                    Some(mask) => *mask |= bit,
+                    None => encoding.fields.push((*c, bit)),
+                },
+                c => return Err(EncodingError::InvalidChar(*c)),
+            }
+        }
+        Ok(encoding)
+    }
+
+    fn field_mut(&mut self, field: char) -> Option<&mut u32> {
+        self.fields
+            .iter_mut()
+            .find(|(c, _)| *c == field)
+            .map(|(_, mask)| mask)
+    }
+
+    fn field_mask(&self, field: char) -> Option<u32> {
+        self.fields
+            .iter()
+            .find(|(c, _)| *c == field)
+            .map(|(_, mask)| *mask)
+    }
+
+    /// Number of bits in the instruction word.
+    pub fn width(&self) -> u8 {
+        self.width
+    }
+
+    /// Names of the operand fields, in order of first appearance.
+    pub fn fields(&self) -> impl Iterator<Item = char> + '_ {
+        self.fields.iter().map(|(c, _)| *c)
+    }
+
+    /// Number of bits in an operand field, 0 if the field doesn't exist.
+    pub fn field_width(&self, field: char) -> u8 {
+        self.field_mask(field).unwrap_or(0).count_ones() as u8
+    }
+
+    /// Does `word` have this encoding's fixed opcode bits?
+    pub fn matches(&self, word: u32) -> bool {
+        word & self.fixed_mask == self.fixed_bits
+    }
+
+    /// Can some word match both encodings?
+    pub fn overlaps(&self, other: &Encoding) -> bool {
+        let common = self.fixed_mask & other.fixed_mask;
+        self.width == other.width && self.fixed_bits & common == other.fixed_bits & common
+    }
+
+    /// The instruction word with only the fixed opcode bits set.
+    pub fn opcode(&self) -> u32 {
+        self.fixed_bits
+    }
+
+    /// Scatter the low bits of `value` into `field`, returning the field bits.
+    pub fn insert(&self, field: char, value: u32) -> u32 {
+        deposit(self.field_mask(field).unwrap_or(0), value)
+    }
+
+    /// Gather the bits of `field` out of `word` into an unsigned value.
+    pub fn extract(&self, field: char, word: u32) -> u32 {
+        gather(self.field_mask(field).unwrap_or(0), word)
+    }
+}
+
+/// Bits of `pattern` (written MSB first, `_` ignored) that are the character `c`.
+pub const fn pattern_mask(pattern: &str, c: char) -> u32 {
+    let bytes = pattern.as_bytes();
+    let mut mask = 0;
+    let mut n = 0;
+    while n < bytes.len() {
+        if bytes[n] != b'_' {
+            mask <<= 1;
+            if bytes[n] == c as u8 {
+                mask |= 1;
+            }
+        }
+        n += 1;
+    }
+    mask
+}
+
+/// Scatter the low bits of `value` into the set bits of `mask`, low to high.
+pub fn deposit(mask: u32, value: u32) -> u32 {
+    let mut out = 0;
+    let mut value = value;
+    for bit in (0..32).map(|n| 1 << n).filter(|b| mask & b != 0) {
+        if value & 1 != 0 {
+            out |= bit;
+        }
+        value >>= 1;
+    }
+    out
+}
+
+/// Gather the set bits of `mask` out of `word` into the low bits, low to high.
+pub fn gather(mask: u32, word: u32) -> u32 {
+    let mut out = 0;
+    for (n, bit) in (0..32)
+        .map(|n| 1 << n)
+        .filter(|b| mask & b != 0)
+        .enumerate()
+    {
+        if word & bit != 0 {
+            out |= 1 << n;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoding() {
+        let e = Encoding::parse("000_iii_rr").unwrap();
+        assert_eq!(e.width(), 8);
+        assert_eq!(e.fields().collect::<Vec<char>>(), vec!['i', 'r']);
+        assert_eq!(e.field_width('i'), 3);
+        assert_eq!(e.opcode(), 0);
+        assert_eq!(e.insert('i', 5) | e.insert('r', 2), 0b000_101_10);
+        assert_eq!(e.extract('i', 0b000_101_10), 5);
+        assert_eq!(e.extract('r', 0b000_101_10), 2);
+        assert!(e.matches(0b000_111_11));
+        assert!(!e.matches(0b001_111_11));
+
+        // split fields are gathered in order
+        let e = Encoding::parse("a1aa").unwrap();
+        assert_eq!(e.insert('a', 0b101), 0b1001);
+        assert_eq!(e.extract('a', 0b1001), 0b101);
+
+        assert_eq!(Encoding::parse("__"), Err(EncodingError::Empty));
+        assert_eq!(
+            Encoding::parse("10x2"),
+            Err(EncodingError::InvalidChar('2'))
+        );
+    }
+
+    #[test]
+    fn test_pattern_mask() {
+        assert_eq!(pattern_mask("000_iii_rr", 'i'), 0b000_111_00);
+        assert_eq!(pattern_mask("0111_dd_ss", '1'), 0b0111_00_00);
+        assert_eq!(deposit(0b1011, 0b101), 0b1001);
+        assert_eq!(gather(0b1011, 0b1001), 0b101);
+    }
+
+    #[test]
+    fn test_overlap() {
+        let br = Encoding::parse("100iiiii").unwrap();
+        let brz = Encoding::parse("101iiiii").unwrap();
+        let pause = Encoding::parse("11111111").unwrap();
+        let ext = Encoding::parse("111xxxxx").unwrap();
+        assert!(!br.overlaps(&brz));
+        assert!(pause.overlaps(&ext));
+        assert!(!pause.overlaps(&br));
+    }
+}
@@ -0,0 +1,414 @@
+use crate::imm::ImmType;
+
+struct Explanation {
+    code: &'static str,
+    title: &'static str,
+    cause: &'static str,
+    example: &'static str,
+    fix: &'static str,
+    show_ranges: bool,
+}
+
+const EXPLANATIONS: &[Explanation] = &[
+    Explanation {
+        code: "E0001",
+        title: "Invalid mnemonic",
+        cause: "The first word of the line is not an instruction the assembler knows. \
+                Mnemonics are case sensitive and must be written in upper case.",
+        example: "SUB r0, 1",
+        fix: "SUBI r0, 1",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0002",
+        title: "Immediate out of range",
+        cause: "The immediate is a valid number but does not fit in the instruction's \
+                immediate field.",
+        example: "ADDI r0, 9",
+        fix: "ADDI r0, 7\nADDI r0, 2",
+        show_ranges: true,
+    },
+    Explanation {
+        code: "E0003",
+        title: "Invalid immediate",
+        cause: "The immediate could not be read as a number. Numbers are \
+                decimal, or hex, binary or octal with a `0x`, `0b` or `0o` \
+                prefix or `h`, `b` or `o` suffix. It may optionally be \
+                prefixed with `#`.",
+        example: "SR0 0x1G",
+        fix: "SR0 0x1F",
+        show_ranges: true,
+    },
+    Explanation {
+        code: "E0004",
+        title: "Missing immediate",
+        cause: "The instruction takes an immediate but none was given.",
+        example: "BRZ",
+        fix: "BRZ 2",
+        show_ranges: true,
+    },
+    Explanation {
+        code: "E0005",
+        title: "Invalid register",
+        cause: "The operand is not one of the registers r0, r1, r2 or r3, \
+                or for an ISA file, not a register below its register count.",
+        example: "CLR r4",
+        fix: "CLR r3",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0006",
+        title: "Missing register",
+        cause: "The instruction takes a register but none was given.",
+        example: "MOV r1",
+        fix: "MOV r1, r0",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0007",
+        title: "Unexpected token",
+        cause: "The line has more operands than the instruction takes.",
+        example: "CLR r0, r1",
+        fix: "CLR r0\nCLR r1",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0008",
+        title: "Line is not valid UTF-8",
+        cause: "The source file contains bytes that are not valid UTF-8 text, \
+                often from a file saved in another encoding.",
+        example: "PAUSE ; caf\\xe9",
+        fix: "PAUSE ; cafe",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0009",
+        title: "Invalid opcode",
+        cause: "A byte of the machine code input does not encode any instruction. \
+                The file may not be machine code, or may be corrupt. \
+                The error shows the surrounding words, the unused bit \
+                pattern the byte falls in, and the valid encodings one bit \
+                away: many bad bytes in a row suggest the wrong file or \
+                instruction set, a lone one with a close neighbour suggests \
+                corruption.",
+        example: "11010000",
+        fix: "11000000 (MOVA r0)",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0010",
+        title: "Instruction requires an extension",
+        cause: "The instruction belongs to an optional ISA extension that is not enabled. \
+                Enable it with `--isa` if your CPU implements it.",
+        example: "AND r1",
+        fix: "asp file.s --isa ext",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0011",
+        title: "Invalid pseudo-instruction definition",
+        cause: "A `.pseudo` directive must give a name, any parameters, a colon, \
+                and one or more instructions separated by `|`.",
+        example: ".pseudo DELAY n ADDI r3, n",
+        fix: ".pseudo DELAY n: CLR r3 | ADDI r3, n | PAUSE",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0012",
+        title: "Wrong number of pseudo-instruction operands",
+        cause: "A pseudo-instruction was used with a different number of operands \
+                than its `.pseudo` definition has parameters.",
+        example: ".pseudo DELAY n: CLR r3 | ADDI r3, n | PAUSE\nDELAY",
+        fix: ".pseudo DELAY n: CLR r3 | ADDI r3, n | PAUSE\nDELAY 5",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0013",
+        title: "Invalid entry point",
+        cause: "The `.entry` address must be the index of an instruction, counted from 0. \
+                The program reaches it with a leading `BR`, so it must be within a \
+                branch's range, and the instruction set must have a branch.",
+        example: ".entry 20",
+        fix: ".entry 4",
+        show_ranges: true,
+    },
+    Explanation {
+        code: "E0014",
+        title: "Duplicate entry point",
+        cause: "A program can only have one `.entry` directive.",
+        example: ".entry 2\n.entry 3",
+        fix: ".entry 2",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0015",
+        title: "Duplicate symbol",
+        cause: "A name can only be declared once per file, with either `.global` or `.extern`.",
+        example: ".global loop\nCLR r0\n.global loop\nBR loop",
+        fix: ".global loop\nCLR r0\nBR loop",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0016",
+        title: "Instruction in the .data section",
+        cause: "After `.data`, lines may only list data with `.byte`. \
+                Switch back to instructions with `.text`.",
+        example: ".data\n.byte 1, 2\nCLR r0",
+        fix: ".data\n.byte 1, 2\n.text\nCLR r0",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0017",
+        title: "Immediate without #",
+        cause: "With `--syntax strict`, every immediate must be prefixed \
+                with `#`.",
+        example: "ADDI r1, 5",
+        fix: "ADDI r1, #5",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0018",
+        title: "Operands not separated by commas",
+        cause: "With `--syntax strict`, the mnemonic is followed by a space \
+                and operands are separated by a single comma.",
+        example: "MOV r0 r1",
+        fix: "MOV r0, r1",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0019",
+        title: "Branch target out of range",
+        cause: "A branch to an absolute address, written with `@`, is further \
+                from the branch than its immediate can reach. Branch through \
+                another instruction closer to the target.",
+        example: "BR @40",
+        fix: "BR @15\n...\nBR @30 ; at 15\n...\nBR @40 ; at 30",
+        show_ranges: true,
+    },
+    Explanation {
+        code: "E0020",
+        title: "Deprecated mnemonic",
+        cause: "The instruction was renamed in the instruction set file and \
+                deprecated mnemonics are denied. Use the new name, which \
+                `asp fix --isa-file` can do for a whole file.",
+        example: "SRH0 3",
+        fix: "SRH 3",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0021",
+        title: "Invalid label",
+        cause: "A label must start with a letter or underscore and contain \
+                only letters, digits and underscores. Register names can't \
+                be labels.",
+        example: "2nd: SUBI r0, 1",
+        fix: "second: SUBI r0, 1",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0022",
+        title: "Undefined constant",
+        cause: "A value that can only be a constant, such as in another constant's \
+                definition, `.rept`, `.if` or the data memory, is a name, but no \
+                constant of that name is defined with `EQU` or `.equ`. Names are case \
+                sensitive.",
+        example: "FAST EQU SPEED + 1",
+        fix: "SPEED EQU 5\nFAST EQU SPEED + 1",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0023",
+        title: "Invalid constant",
+        cause: "A constant's name must start with a letter or underscore and \
+                contain only letters, digits and underscores. Register names \
+                can't be constants.",
+        example: "r1 EQU 5",
+        fix: "SPEED EQU 5",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0024",
+        title: "Overlapping .org",
+        cause: "A .org address is before the next instruction's address, so \
+                the code after it would overwrite the code before it.",
+        example: "CLR r0\nCLR r1\n.org 1",
+        fix: "CLR r0\nCLR r1\n.org 2",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0025",
+        title: "Not an instruction",
+        cause: "Commands that analyse a program's instructions, like `asp stats` \
+                and `asp diff`, need every value placed in program memory with \
+                `.byte` or `.org` to decode as one for the target ISA. Values \
+                that don't belong in the .data section.",
+        example: ".byte 0xf0",
+        fix: ".data\n.byte 0xf0",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0026",
+        title: "Unterminated macro",
+        cause: "Every line after `.macro` is part of the macro's body until \
+                `.endm`, so a missing `.endm` swallows the rest of the file.",
+        example: ".macro STEP\nMOVR r1\nPAUSE",
+        fix: ".macro STEP\nMOVR r1\n.endm\nPAUSE",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0027",
+        title: "Unterminated .rept",
+        cause: "The lines after `.rept` up to `.endr` are repeated, so a \
+                missing `.endr` would repeat the rest of the file.",
+        example: ".rept 4\nMOVR r1\nPAUSE",
+        fix: ".rept 4\nMOVR r1\n.endr\nPAUSE",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0028",
+        title: "Unterminated conditional",
+        cause: "Every `.if`, `.ifdef` and `.ifndef` block ends with `.endif`, \
+                otherwise it's unclear where the conditional code stops.",
+        example: ".ifdef FAST\nADDI r0, 2\nPAUSE",
+        fix: ".ifdef FAST\nADDI r0, 2\n.endif\nPAUSE",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0029",
+        title: "Unmatched directive",
+        cause: "An `.else` or `.endif` must follow an `.if`, `.ifdef` or \
+                `.ifndef`, and a block has at most one `.else`.",
+        example: "ADDI r0, 1\n.endif",
+        fix: ".ifdef FAST\nADDI r0, 1\n.endif",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0030",
+        title: "Invalid condition",
+        cause: "An `.if` condition is a value, true unless it is 0, or two \
+                values compared with ==, !=, <, <=, > or >=. The operator \
+                needs spaces around it.",
+        example: ".if SPEED = 2",
+        fix: ".if SPEED == 2",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0031",
+        title: "Invalid expression",
+        cause: "An expression uses numbers, constants, +, -, *, /, %, << and \
+                >> with balanced parentheses and no spaces. It can't divide by \
+                zero or overflow a 32-bit integer.",
+        example: "ADDI r0, (MAX-1/2",
+        fix: "ADDI r0, (MAX-1)/2",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0032",
+        title: "Invalid register alias",
+        cause: "A `.def` alias must start with a letter or underscore, contain \
+                only letters, digits and underscores, and not be a register.",
+        example: ".def 2nd = r2",
+        fix: ".def second = r2",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0033",
+        title: "Invalid alignment",
+        cause: "`.align` pads to a multiple of its operand, which must be a power \
+                of two such as 1, 2, 4 or 8.",
+        example: ".align 6",
+        fix: ".align 8",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0034",
+        title: "Name defined twice",
+        cause: "Labels, constants and `.global`/`.extern` symbols share one set of \
+                names, so each can only be defined once. The error gives the line \
+                of the first definition.",
+        example: "loop: ADDI r0, 1\n      BR loop\nloop: PAUSE",
+        fix: "loop: ADDI r0, 1\n      BR loop\ndone: PAUSE",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0035",
+        title: "Past the end of memory",
+        cause: "A `.org`, `.align`, `.fill`, `.space` or `.rept` places more words \
+                than the memory holds. The depth is 256 words unless `--depth` \
+                gives another.",
+        example: ".org 300\nPAUSE",
+        fix: ".org 200\nPAUSE",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0036",
+        title: "Undefined label or constant",
+        cause: "An operand is a name, but no label or constant of that name is \
+                defined. Labels are defined with `name:` and constants with `EQU` \
+                or `.equ`. Names are case sensitive.",
+        example: "BRZ done\nADDI r0, 1",
+        fix: "BRZ done\nADDI r0, 1\ndone: PAUSE",
+        show_ranges: false,
+    },
+    Explanation {
+        code: "E0037",
+        title: "Unknown warning",
+        cause: "`.allow`, or `#allow(...)` in a comment, names a warning that \
+                doesn't exist, or none. The warnings are `deprecated` and \
+                `no_effect`.",
+        example: "SUBI r0, 0 ;#allow(noeffect)",
+        fix: "SUBI r0, 0 ;#allow(no_effect)",
+        show_ranges: false,
+    },
+];
+
+/// Valid range of each immediate type and the instructions that use it.
+fn imm_ranges() -> String {
+    let i5 = (<i8 as ImmType<5>>::MIN, <i8 as ImmType<5>>::MAX);
+    let u3 = (<u8 as ImmType<3>>::MIN, <u8 as ImmType<3>>::MAX);
+    let u4 = (<u8 as ImmType<4>>::MIN, <u8 as ImmType<4>>::MAX);
+
+    format!(
+        "Immediate ranges:\n\
+         \x20 I5  {:>3} to {:>2}  BR, BRZ\n\
+         \x20 U3  {:>3} to {:>2}  ADDI, SUBI\n\
+         \x20 U4  {:>3} to {:>2}  SR0, SRH0\n",
+        i5.0, i5.1, u3.0, u3.1, u4.0, u4.1
+    )
+}
+
+/// Long-form explanation of an error code, or `None` if the code is unknown.
+pub fn explain(code: &str) -> Option<String> {
+    let code = code.to_ascii_uppercase();
+    let e = EXPLANATIONS.iter().find(|e| e.code == code)?;
+
+    let mut s = format!("{}: {}\n\n{}\n\n", e.code, e.title, e.cause);
+    if e.show_ranges {
+        s.push_str(&imm_ranges());
+        s.push('\n');
+    }
+    s.push_str(&format!("Erroneous code:\n{}\n\n", indent(e.example)));
+    s.push_str(&format!("Fixed:\n{}\n", indent(e.fix)));
+    Some(s)
+}
+
+fn indent(s: &str) -> String {
+    s.lines()
+        .map(|l| format!("    {l}"))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explain() {
+        for e in EXPLANATIONS {
+            assert!(explain(e.code).is_some());
+        }
+        assert!(explain("e0002").unwrap().contains("-16 to 15"));
+        assert!(explain("E9999").is_none());
+    }
+}
@@ -0,0 +1,149 @@
+/// Why an expression couldn't be evaluated.
+#[derive(Debug, PartialEq)]
+pub(crate) enum ExprError {
+    /// A name that isn't a number or a known value.
+    Undefined(String),
+    /// Bad syntax, division by zero or overflow.
+    Invalid,
+}
+
+/// Evaluate an expression of numbers and names with `+`, `-`, `*`, `/`, `%`,
+/// `<<`, `>>` and parentheses, e.g. `(MAX - 1) / 2`. Operators have C's
+/// precedence. `lookup` gives the value of a number or name.
+pub(crate) fn eval(expr: &str, lookup: impl Fn(&str) -> Option<i32>) -> Result<i32, ExprError> {
+    let mut parser = Parser {
+        rest: expr,
+        lookup: &lookup,
+    };
+    let value = parser.shift()?;
+    match parser.rest.trim().is_empty() {
+        true => Ok(value),
+        false => Err(ExprError::Invalid),
+    }
+}
+
+/// Whether a token is an expression rather than a single number or name.
+pub(crate) fn is_expression(token: &str) -> bool {
+    let rest = token.get(1..).unwrap_or_default();
+    token.starts_with('(') || rest.contains(['+', '-', '*', '/', '%', '<', '>', '(', ')'])
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+    lookup: &'a dyn Fn(&str) -> Option<i32>,
+}
+
+impl Parser<'_> {
+    fn eat(&mut self, op: &str) -> bool {
+        self.rest = self.rest.trim_start();
+        match self.rest.strip_prefix(op) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn shift(&mut self) -> Result<i32, ExprError> {
+        let mut value = self.sum()?;
+        loop {
+            let shifted = if self.eat("<<") {
+                value.checked_shl(self.sum()? as u32)
+            } else if self.eat(">>") {
+                value.checked_shr(self.sum()? as u32)
+            } else {
+                return Ok(value);
+            };
+            value = shifted.ok_or(ExprError::Invalid)?;
+        }
+    }
+
+    fn sum(&mut self) -> Result<i32, ExprError> {
+        let mut value = self.product()?;
+        loop {
+            let sum = if self.eat("+") {
+                value.checked_add(self.product()?)
+            } else if self.eat("-") {
+                value.checked_sub(self.product()?)
+            } else {
+                return Ok(value);
+            };
+            value = sum.ok_or(ExprError::Invalid)?;
+        }
+    }
+
+    fn product(&mut self) -> Result<i32, ExprError> {
+        let mut value = self.unary()?;
+        loop {
+            let product = if self.eat("*") {
+                value.checked_mul(self.unary()?)
+            } else if self.eat("/") {
+                value.checked_div(self.unary()?)
+            } else if self.eat("%") {
+                value.checked_rem(self.unary()?)
+            } else {
+                return Ok(value);
+            };
+            value = product.ok_or(ExprError::Invalid)?;
+        }
+    }
+
+    fn unary(&mut self) -> Result<i32, ExprError> {
+        if self.eat("-") {
+            self.unary()?.checked_neg().ok_or(ExprError::Invalid)
+        } else if self.eat("+") {
+            self.unary()
+        } else if self.eat("(") {
+            let value = self.shift()?;
+            match self.eat(")") {
+                true => Ok(value),
+                false => Err(ExprError::Invalid),
+            }
+        } else {
+            // `$` is the current address where labels are known
+            self.rest = self.rest.trim_start();
+            let end = self
+                .rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '$')
+                .unwrap_or(self.rest.len());
+            let (atom, rest) = self.rest.split_at(end);
+            if atom.is_empty() {
+                return Err(ExprError::Invalid);
+            }
+            self.rest = rest;
+            (self.lookup)(atom).ok_or_else(|| ExprError::Undefined(atom.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval() {
+        let lookup = |t: &str| match t {
+            "MAX" => Some(7),
+            _ => t.parse().ok(),
+        };
+        assert_eq!(eval("(MAX-1)/2", lookup), Ok(3));
+        assert_eq!(eval(" ( MAX - 1 ) / 2", lookup), Ok(3));
+        assert_eq!(eval("1 2", lookup), Err(ExprError::Invalid));
+        assert_eq!(eval("1+2*3", lookup), Ok(7));
+        assert_eq!(eval("1<<2+1", lookup), Ok(8));
+        assert_eq!(eval("-(MAX%4)", lookup), Ok(-3));
+        assert_eq!(eval("MAX/0", lookup), Err(ExprError::Invalid));
+        assert_eq!(eval("(1+2", lookup), Err(ExprError::Invalid));
+        assert_eq!(eval("1+", lookup), Err(ExprError::Invalid));
+        assert_eq!(
+            eval("MIN+1", lookup),
+            Err(ExprError::Undefined("MIN".to_string()))
+        );
+
+        assert!(is_expression("(MAX-1)/2"));
+        assert!(is_expression("MAX<<1"));
+        assert!(!is_expression("-3"));
+        assert!(!is_expression("@12"));
+    }
+}
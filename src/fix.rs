@@ -0,0 +1,264 @@
+use crate::{
+    assembly::{comment_start, is_register, parse_number, Syntax},
+    instruction_set::InstructionSet,
+    isa::IsaSpec,
+    op::Isa,
+};
+
+/// A correction to one line of source that makes it assemble.
+#[derive(Debug, PartialEq)]
+pub struct Fix {
+    /// Line number, starting at 1.
+    pub line: usize,
+    pub before: String,
+    pub after: String,
+}
+
+/// Corrections for every line that doesn't assemble but can be fixed
+/// without guessing: a misspelled mnemonic with one closest match, a
+/// negative `ADDI` or `SUBI` written as the other, and, for
+/// [`Syntax::Strict`], immediates missing their `#`.
+///
+/// Directives, constant definitions, labelled lines and uses of `.pseudo`
+/// instructions are left alone.
+pub fn fixes(contents: &str, isa: &Isa, syntax: Syntax) -> Vec<Fix> {
+    let lines: Vec<&str> = contents.lines().collect();
+    let pseudos: Vec<&str> = lines
+        .iter()
+        .filter_map(|l| l.trim().strip_prefix(".pseudo"))
+        .filter_map(|d| d.split_whitespace().next())
+        .map(|name| name.trim_end_matches(':'))
+        .collect();
+
+    let mut fixes = vec![];
+    for (n, line) in lines.iter().enumerate() {
+        let cut = comment_start(line);
+        let code = line[..cut].trim();
+        let mut parts = code
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|t| !t.is_empty());
+        let Some(mnemonic) = parts.next() else {
+            continue;
+        };
+        if mnemonic.starts_with('.') || mnemonic.ends_with(':') || pseudos.contains(&mnemonic) {
+            continue;
+        }
+        let tokens: Vec<&str> = parts.collect();
+        if tokens
+            .first()
+            .is_some_and(|t| t.eq_ignore_ascii_case("EQU"))
+        {
+            continue;
+        }
+        if assembles(isa, syntax, mnemonic, &tokens) {
+            continue;
+        }
+        let Some(fixed) = fix_instruction(isa, syntax, mnemonic, &tokens) else {
+            continue;
+        };
+
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let comment = &line[line[..cut].trim_end().len()..];
+        fixes.push(Fix {
+            line: n + 1,
+            before: line.to_string(),
+            after: format!("{indent}{fixed}{comment}"),
+        });
+    }
+    fixes
+}
+
+/// Corrections replacing every deprecated mnemonic of `spec` with the one
+/// that replaced it.
+pub fn renames(contents: &str, spec: &IsaSpec) -> Vec<Fix> {
+    contents
+        .lines()
+        .enumerate()
+        .filter_map(|(n, line)| {
+            let cut = comment_start(line);
+            let mnemonic = line[..cut]
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .find(|t| !t.is_empty() && !t.ends_with(':'))?;
+            let instr = spec.renamed(mnemonic)?;
+            Some(Fix {
+                line: n + 1,
+                before: line.to_string(),
+                after: line.replacen(mnemonic, instr.mnemonic(), 1),
+            })
+        })
+        .collect()
+}
+
+/// `contents` with each fix applied, keeping its line endings.
+pub fn apply(contents: &str, fixes: &[Fix]) -> String {
+    contents
+        .split_inclusive('\n')
+        .enumerate()
+        .map(|(n, line)| match fixes.iter().find(|f| f.line == n + 1) {
+            Some(fix) => {
+                let ending = &line[line.trim_end_matches(['\r', '\n']).len()..];
+                format!("{}{ending}", fix.after)
+            }
+            None => line.to_string(),
+        })
+        .collect()
+}
+
+/// Does the instruction assemble as written?
+fn assembles(isa: &Isa, syntax: Syntax, mnemonic: &str, tokens: &[&str]) -> bool {
+    let hashes =
+        syntax == Syntax::Permissive || tokens.iter().all(|t| is_register(t) || t.starts_with('#'));
+    hashes && isa.parse(mnemonic, tokens).is_ok()
+}
+
+/// The instruction corrected and written in the usual style, `None` if the
+/// corrections don't make it assemble.
+fn fix_instruction(isa: &Isa, syntax: Syntax, mnemonic: &str, tokens: &[&str]) -> Option<String> {
+    let mut mnemonic = match isa.parse(mnemonic, tokens) {
+        Err(e) if e.iter().any(|e| e.code() == "E0001") => closest(isa, mnemonic)?.to_string(),
+        _ => mnemonic.to_string(),
+    };
+    let mut tokens: Vec<String> = tokens
+        .iter()
+        .map(|t| match is_register(t) {
+            true => t.to_ascii_lowercase(),
+            false => t.to_string(),
+        })
+        .collect();
+
+    // ADDI r0, -2 is SUBI r0, 2
+    if let ("ADDI" | "SUBI", [_, imm]) = (mnemonic.as_str(), &mut tokens[..]) {
+        let value = parse_number(imm.strip_prefix('#').unwrap_or(imm));
+        if let Some(value) = value.filter(|v| *v < 0) {
+            mnemonic = match mnemonic.as_str() {
+                "ADDI" => "SUBI".to_string(),
+                _ => "ADDI".to_string(),
+            };
+            *imm = (-value).to_string();
+        }
+    }
+
+    if syntax == Syntax::Strict {
+        for t in tokens
+            .iter_mut()
+            .filter(|t| !is_register(t) && !t.starts_with('#'))
+        {
+            t.insert(0, '#');
+        }
+    }
+
+    let refs: Vec<&str> = tokens.iter().map(String::as_str).collect();
+    if isa.parse(&mnemonic, &refs).is_err() {
+        return None;
+    }
+    Some(match tokens.is_empty() {
+        true => mnemonic,
+        false => format!("{mnemonic} {}", tokens.join(", ")),
+    })
+}
+
+/// The one mnemonic nearest to a misspelled one, ignoring case, if it is
+/// close enough to be what was meant.
+fn closest(isa: &Isa, mnemonic: &str) -> Option<&'static str> {
+    let upper = mnemonic.to_ascii_uppercase();
+    let mut scored: Vec<(usize, &str)> = isa
+        .mnemonics()
+        .into_iter()
+        .map(|m| (edit_distance(&upper, m), m))
+        .collect();
+    scored.sort();
+    match scored[..] {
+        [(d, m), (next, _), ..] if d <= 1 + upper.len() / 4 && d < next => Some(m),
+        _ => None,
+    }
+}
+
+/// Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = diagonal + (ca != *cb) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_renames() {
+        use crate::isa::InstrSpec;
+
+        let spec = IsaSpec::new(
+            "v2",
+            8,
+            vec![InstrSpec::new("SRH", "0101iiii", &["i:unsigned"])
+                .unwrap()
+                .with_deprecated(&["SRH0"])],
+        )
+        .unwrap();
+        let fixes = renames("SRH 1\n  SRH0 3 ; SRH0\n", &spec);
+        assert_eq!(
+            fixes,
+            vec![Fix {
+                line: 2,
+                before: "  SRH0 3 ; SRH0".to_string(),
+                after: "  SRH 3 ; SRH0".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_fixes() {
+        let isa = Isa::default();
+        let source = "ADDII r0, 1 ; typo\n\
+                      \tADDI r1, -3\n\
+                      .pseudo DLY n: ADDI r3, n\n\
+                      DLY 1\n\
+                      CLR r0\n\
+                      XYZ r0\n";
+        let fixes = fixes(source, &isa, Syntax::Permissive);
+        assert_eq!(
+            fixes,
+            vec![
+                Fix {
+                    line: 1,
+                    before: "ADDII r0, 1 ; typo".to_string(),
+                    after: "ADDI r0, 1 ; typo".to_string(),
+                },
+                Fix {
+                    line: 2,
+                    before: "\tADDI r1, -3".to_string(),
+                    after: "\tSUBI r1, 3".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            apply("ADDII r0, 1 ; typo\r\nCLR r0\r\n", &fixes[..1]),
+            "ADDI r0, 1 ; typo\r\nCLR r0\r\n"
+        );
+
+        let strict = super::fixes("ADDI r0 1\nSR0 #2\n", &isa, Syntax::Strict);
+        assert_eq!(strict.len(), 1);
+        assert_eq!(strict[0].after, "ADDI r0, #1");
+    }
+
+    #[test]
+    fn test_closest() {
+        let isa = Isa::default();
+        assert_eq!(closest(&isa, "pause"), Some("PAUSE"));
+        assert_eq!(closest(&isa, "PUASE"), Some("PAUSE"));
+        // as close to MOVR as to MOVRHS
+        assert_eq!(closest(&isa, "MOVRH"), None);
+        assert_eq!(closest(&isa, "HALT"), None);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+}
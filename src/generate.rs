@@ -0,0 +1,93 @@
+use crate::op::{Category, Isa, Op};
+
+/// A small seedable random number generator (SplitMix64), so generated
+/// programs are the same for a seed on every platform.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+/// Constraints on the programs [`random_program`] makes.
+#[derive(Debug, Clone, Copy)]
+pub struct Constraints {
+    /// Number of instructions.
+    pub len: usize,
+    /// Allow `BR` and `BRZ`. Branches always land inside the program.
+    pub branches: bool,
+    /// Only use instructions that just change registers.
+    pub alu_only: bool,
+    /// Make the last instruction `PAUSE`.
+    pub end_with_pause: bool,
+}
+
+/// A random program of instructions valid in `isa`.
+pub fn random_program(rng: &mut Rng, isa: &Isa, c: Constraints) -> Vec<Op> {
+    let mut ops = vec![];
+    while ops.len() < c.len {
+        let addr = ops.len() as i32;
+        if c.end_with_pause && addr + 1 == c.len as i32 {
+            ops.push(Op::PAUSE);
+            continue;
+        }
+
+        let Ok(op) = Op::decode(rng.next_u64() as u8, isa) else {
+            continue;
+        };
+        let allowed = match op.category() {
+            Category::Branch => c.branches && !c.alu_only,
+            Category::Alu => true,
+            _ => !c.alu_only,
+        };
+        let lands_inside = op
+            .branch_offset()
+            .is_none_or(|o| (0..c.len as i32).contains(&(addr + o)));
+        if allowed && lands_inside {
+            ops.push(op);
+        }
+    }
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_program() {
+        let isa = Isa::default();
+        let c = Constraints {
+            len: 20,
+            branches: true,
+            alu_only: false,
+            end_with_pause: true,
+        };
+        let a = random_program(&mut Rng::new(7), &isa, c);
+        assert_eq!(a.len(), 20);
+        assert_eq!(a[19], Op::PAUSE);
+        assert_eq!(a, random_program(&mut Rng::new(7), &isa, c));
+        for (n, op) in a.iter().enumerate() {
+            if let Some(o) = op.branch_offset() {
+                assert!((0..20).contains(&(n as i32 + o)));
+            }
+        }
+
+        let alu = Constraints {
+            alu_only: true,
+            end_with_pause: false,
+            ..c
+        };
+        let ops = random_program(&mut Rng::new(7), &isa, alu);
+        assert!(ops.iter().all(|o| o.category() == Category::Alu));
+    }
+}
@@ -0,0 +1,235 @@
+use std::fmt::Display;
+
+#[cfg(feature = "toml")]
+use serde::Deserialize;
+
+use crate::{assembly, instruction_set::InstructionSet, object, word::Word};
+
+/// What a submission must do to pass, loaded from TOML with
+/// [`GradeSpec::from_toml`]. Every submission must assemble; the limits are
+/// optional.
+///
+/// ```toml
+/// max_words = 64
+/// max_data_words = 16
+/// ```
+#[derive(Debug, Default, PartialEq)]
+#[cfg_attr(feature = "toml", derive(Deserialize), serde(deny_unknown_fields))]
+pub struct GradeSpec {
+    /// Most words of program memory the submission may use.
+    pub max_words: Option<usize>,
+    /// Most words of data memory the submission may use.
+    pub max_data_words: Option<usize>,
+}
+
+/// Why a submission failed.
+#[derive(Debug, PartialEq)]
+pub enum Failure {
+    /// The submission couldn't be read, and why.
+    Unreadable(String),
+    /// The submission has this many assembly or symbol errors.
+    Errors(usize),
+    TooLong(usize, usize),
+    TooMuchData(usize, usize),
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::Unreadable(e) => write!(f, "can't be read: {e}"),
+            Failure::Errors(n) => write!(f, "{n} assembly errors"),
+            Failure::TooLong(n, max) => write!(f, "{n} words of program, limit {max}"),
+            Failure::TooMuchData(n, max) => write!(f, "{n} words of data, limit {max}"),
+        }
+    }
+}
+
+/// The result of grading one submission.
+#[derive(Debug, PartialEq)]
+pub struct Report {
+    pub submission: String,
+    /// Words of program and data memory, if the submission assembled.
+    pub size: Option<(usize, usize)>,
+    pub failures: Vec<Failure>,
+}
+
+impl GradeSpec {
+    #[cfg(feature = "toml")]
+    pub fn from_toml(s: &str) -> Result<GradeSpec, String> {
+        toml::from_str(s).map_err(|e| e.message().to_string())
+    }
+
+    /// Assemble `contents` for `isa` and check it against the spec.
+    pub fn grade<W: Word, I: InstructionSet<W>>(
+        &self,
+        submission: &str,
+        contents: &[u8],
+        isa: &I,
+    ) -> Report {
+        let assembled = assembly::assemble(contents, isa)
+            .map_err(|errors| errors.len())
+            .and_then(|m| {
+                let words = object::resolve(isa, m.instrs, m.symbols).map_err(|e| e.len())?;
+                Ok((words.len(), m.data.len()))
+            });
+
+        let mut failures = vec![];
+        let size = match assembled {
+            Ok(size) => Some(size),
+            Err(n) => {
+                failures.push(Failure::Errors(n));
+                None
+            }
+        };
+        if let Some((words, data)) = size {
+            match self.max_words {
+                Some(max) if words > max => failures.push(Failure::TooLong(words, max)),
+                _ => {}
+            }
+            match self.max_data_words {
+                Some(max) if data > max => failures.push(Failure::TooMuchData(data, max)),
+                _ => {}
+            }
+        }
+
+        Report {
+            submission: submission.to_string(),
+            size,
+            failures,
+        }
+    }
+}
+
+impl Report {
+    pub const CSV_HEADER: &'static str = "submission,passed,words,data_words,failures";
+
+    /// The report for a submission that couldn't be read, so grading can go
+    /// on with the rest.
+    pub fn unreadable(submission: &str, error: &str) -> Report {
+        Report {
+            submission: submission.to_string(),
+            size: None,
+            failures: vec![Failure::Unreadable(error.to_string())],
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    fn failure_list(&self) -> String {
+        let failures: Vec<String> = self.failures.iter().map(|f| f.to_string()).collect();
+        failures.join("; ")
+    }
+
+    /// One row of a CSV report, matching [`Report::CSV_HEADER`].
+    pub fn to_csv(&self) -> String {
+        let (words, data) = match self.size {
+            Some((w, d)) => (w.to_string(), d.to_string()),
+            None => (String::new(), String::new()),
+        };
+        format!(
+            "{},{},{words},{data},{}",
+            csv_field(&self.submission),
+            self.passed(),
+            csv_field(&self.failure_list())
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        let (words, data) = match self.size {
+            Some((w, d)) => (w.to_string(), d.to_string()),
+            None => ("null".to_string(), "null".to_string()),
+        };
+        let failures: Vec<String> = self
+            .failures
+            .iter()
+            .map(|f| json_string(&f.to_string()))
+            .collect();
+        format!(
+            "{{\"submission\": {}, \"passed\": {}, \"words\": {words}, \
+             \"data_words\": {data}, \"failures\": [{}]}}",
+            json_string(&self.submission),
+            self.passed(),
+            failures.join(", ")
+        )
+    }
+}
+
+/// A quoted CSV field. Quotes are doubled, and line breaks are kept inside
+/// the quotes.
+fn csv_field(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+/// A quoted JSON string, with quotes, backslashes and control characters
+/// escaped.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op::Isa;
+
+    #[test]
+    fn test_grade() {
+        let isa = Isa::default();
+        let spec = GradeSpec {
+            max_words: Some(2),
+            max_data_words: None,
+        };
+
+        let report = spec.grade("alice.s", b"ADDI r0, 1\nPAUSE", &isa);
+        assert!(report.passed());
+        assert_eq!(report.size, Some((2, 0)));
+        assert_eq!(report.to_csv(), "\"alice.s\",true,2,0,\"\"");
+
+        let report = spec.grade("bob.s", b"PAUSE\nPAUSE\nPAUSE", &isa);
+        assert_eq!(report.failures, vec![Failure::TooLong(3, 2)]);
+        assert_eq!(
+            report.to_json(),
+            "{\"submission\": \"bob.s\", \"passed\": false, \"words\": 3, \
+             \"data_words\": 0, \"failures\": [\"3 words of program, limit 2\"]}"
+        );
+
+        let report = spec.grade("carol.s", b"ADDI r0\nFOO", &isa);
+        assert_eq!(report.failures, vec![Failure::Errors(2)]);
+        assert_eq!(report.to_csv(), "\"carol.s\",false,,,\"2 assembly errors\"");
+
+        let report = Report::unreadable("dave\t\"1\".s", "No such file");
+        assert!(!report.passed());
+        assert_eq!(
+            report.to_csv(),
+            "\"dave\t\"\"1\"\".s\",false,,,\"can't be read: No such file\""
+        );
+        assert_eq!(
+            report.to_json(),
+            "{\"submission\": \"dave\\t\\\"1\\\".s\", \"passed\": false, \"words\": null, \
+             \"data_words\": null, \"failures\": [\"can't be read: No such file\"]}"
+        );
+        assert_eq!(json_string("a\u{1}\nb"), "\"a\\u0001\\nb\"");
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_spec_fields() {
+        let spec = GradeSpec::from_toml("max_words = 64").unwrap();
+        assert_eq!(spec.max_words, Some(64));
+        assert!(GradeSpec::from_toml("max_word = 64").is_err());
+    }
+}
@@ -0,0 +1,76 @@
+/// How often each mnemonic appears in a program.
+#[derive(Debug, PartialEq)]
+pub struct Histogram {
+    /// Mnemonics and their counts, most common first.
+    pub counts: Vec<(String, usize)>,
+    pub total: usize,
+}
+
+impl Histogram {
+    /// Count the mnemonics of instructions given as assembly text.
+    pub fn new<'a>(instrs: impl IntoIterator<Item = &'a str>) -> Histogram {
+        let mut counts: Vec<(String, usize)> = vec![];
+        let mut total = 0;
+        for instr in instrs {
+            let Some(mnemonic) = instr.split_whitespace().next() else {
+                continue;
+            };
+            total += 1;
+            match counts.iter_mut().find(|(m, _)| m == mnemonic) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((mnemonic.to_string(), 1)),
+            }
+        }
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Histogram { counts, total }
+    }
+
+    fn percent(&self, count: usize) -> f64 {
+        match self.total {
+            0 => 0.0,
+            t => count as f64 * 100.0 / t as f64,
+        }
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut s = "mnemonic,count,percent\n".to_string();
+        for (mnemonic, count) in &self.counts {
+            s += &format!("{mnemonic},{count},{:.1}\n", self.percent(*count));
+        }
+        s
+    }
+
+    pub fn to_json(&self) -> String {
+        let rows: Vec<String> = self
+            .counts
+            .iter()
+            .map(|(mnemonic, count)| {
+                format!(
+                    "{{\"mnemonic\": \"{mnemonic}\", \"count\": {count}, \"percent\": {:.1}}}",
+                    self.percent(*count)
+                )
+            })
+            .collect();
+        format!("[{}]", rows.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let h = Histogram::new(["PAUSE", "CLR r0", "PAUSE", "BR -1"]);
+        assert_eq!(h.total, 4);
+        assert_eq!(
+            h.to_csv(),
+            "mnemonic,count,percent\nPAUSE,2,50.0\nBR,1,25.0\nCLR,1,25.0\n"
+        );
+        assert_eq!(
+            Histogram::new(["PAUSE"]).to_json(),
+            "[{\"mnemonic\": \"PAUSE\", \"count\": 1, \"percent\": 100.0}]"
+        );
+        assert_eq!(Histogram::new([]).to_json(), "[]");
+    }
+}
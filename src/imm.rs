@@ -1,3 +1,17 @@
+/// An immediate in an `N`-bit field of an instruction, held as `u8` for an
+/// unsigned field or `i8` for a two's complement one.
+///
+/// ```
+/// use asp::imm::{sign_extend, I5, U3};
+///
+/// assert_eq!(I5::WIDTH, 5);
+/// assert_eq!(U3::MASK, 0b111);
+/// assert_eq!(I5::new(-5).unwrap().bits(), 0b11011);
+/// assert_eq!(I5::from_field(0b11011).get(), -5);
+/// assert_eq!(sign_extend(0x800, 12), -2048);
+/// assert_eq!(I5::new_wrapping(16).get(), -16);
+/// assert_eq!(U3::new_saturating(9).get(), 7);
+/// ```
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Imm<T, const N: u8>
 where
@@ -22,11 +36,58 @@ impl<const N: u8> ImmType<N> for i8 {
 }
 
 impl<T: ImmType<N>, const N: u8> Imm<T, N> {
+    /// Bits in the field.
+    pub const WIDTH: u8 = N;
+    /// The field's bits, in the low bits of a byte.
+    pub const MASK: u8 = ((1u16 << N) - 1) as u8;
+
     pub fn get(self) -> T {
         self.value
     }
 }
 
+impl<const N: u8> Imm<u8, N> {
+    /// The value as its bit field.
+    pub fn bits(self) -> u8 {
+        self.value & Self::MASK
+    }
+
+    /// The immediate a bit field holds, ignoring bits above the field.
+    pub fn from_field(bits: u8) -> Self {
+        Imm {
+            value: bits & Self::MASK,
+        }
+    }
+}
+
+impl<const N: u8> Imm<i8, N> {
+    /// The value as its two's complement bit field.
+    pub fn bits(self) -> u8 {
+        self.value as u8 & Self::MASK
+    }
+
+    /// The immediate a two's complement bit field holds, ignoring bits above
+    /// the field.
+    pub fn from_field(bits: u8) -> Self {
+        Imm {
+            value: sign_extend((bits & Self::MASK) as u32, N) as i8,
+        }
+    }
+}
+
+/// The value of a `width`-bit two's complement field, given in the low bits
+/// of `bits`. A field of no bits holds 0, and one of 32 or more all of `bits`.
+pub fn sign_extend(bits: u32, width: u8) -> i32 {
+    match width {
+        0 => 0,
+        1..=31 => {
+            let shift = 32 - width as u32;
+            ((bits << shift) as i32) >> shift
+        }
+        _ => bits as i32,
+    }
+}
+
 impl<T: ImmType<N>, const N: u8> Imm<T, N> {
     pub fn new(value: T) -> Option<Self> {
         if value >= T::MIN && value <= T::MAX {
@@ -35,6 +96,25 @@ impl<T: ImmType<N>, const N: u8> Imm<T, N> {
             None
         }
     }
+
+    /// The immediate congruent to `value` modulo 2^N, as if only its low N
+    /// bits were kept.
+    pub fn new_wrapping(value: i32) -> Self {
+        // in i64, as `value - min` overflows i32 for values near its limits
+        let (min, max): (i32, i32) = (T::MIN.into(), T::MAX.into());
+        let (value, min, max) = (value as i64, min as i64, max as i64);
+        Self::in_range(((value - min).rem_euclid(max - min + 1) + min) as i32)
+    }
+
+    /// The immediate nearest to `value`.
+    pub fn new_saturating(value: i32) -> Self {
+        Self::in_range(value.clamp(T::MIN.into(), T::MAX.into()))
+    }
+
+    // callers keep `value` within MIN..=MAX, so it always converts
+    fn in_range(value: i32) -> Self {
+        T::try_from(value).map_or(Imm { value: T::MIN }, |value| Imm { value })
+    }
 }
 
 // is there a way to use generics here? getting issues with it
@@ -66,7 +146,7 @@ mod tests {
     fn test_imm_i() {
         for i in -128..=127 {
             let x = Imm::<i8, 3>::new(i);
-            if i >= -4 && i < 4 {
+            if (-4..4).contains(&i) {
                 assert!(x.is_some());
                 assert_eq!(x.unwrap().get(), i);
             } else {
@@ -75,6 +155,38 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_fields() {
+        assert_eq!(I5::MASK, 0b11111);
+        assert_eq!(U3::WIDTH, 3);
+        assert_eq!(I5::new(-5).unwrap().bits(), 0b11011);
+        assert_eq!(I5::from_field(0b11011).get(), -5);
+        assert_eq!(I5::from_field(0b01111).get(), 15);
+        assert_eq!(U4::from_field(0xff).get(), 15);
+        assert_eq!(U3::new(5).unwrap().bits(), 5);
+        assert_eq!(sign_extend(0x800, 12), -2048);
+        assert_eq!(sign_extend(0xffff_ffff, 0), 0);
+        assert_eq!(sign_extend(1, 1), -1);
+        assert_eq!(sign_extend(0x8000_0000, 32), i32::MIN);
+    }
+
+    #[test]
+    fn test_wrapping_saturating() {
+        assert_eq!(I5::new_wrapping(16).get(), -16);
+        assert_eq!(I5::new_wrapping(-17).get(), 15);
+        assert_eq!(I5::new_wrapping(-5).get(), -5);
+        assert_eq!(U3::new_wrapping(9).get(), 1);
+        assert_eq!(U3::new_wrapping(-1).get(), 7);
+        assert_eq!(I5::new_wrapping(i32::MAX).get(), -1);
+        assert_eq!(I5::new_wrapping(i32::MIN).get(), 0);
+        assert_eq!(U3::new_wrapping(i32::MAX).get(), 7);
+
+        assert_eq!(I5::new_saturating(100).get(), 15);
+        assert_eq!(I5::new_saturating(-100).get(), -16);
+        assert_eq!(U4::new_saturating(-3).get(), 0);
+        assert_eq!(U4::new_saturating(7).get(), 7);
+    }
+
     #[test]
     fn test_imm_u() {
         for i in 0..=255 {
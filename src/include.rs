@@ -0,0 +1,188 @@
+use crate::assembly::comment_start;
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A source file with its `.include "file"` directives replaced by the
+/// included files, remembering where each line came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    pub text: Vec<u8>,
+    /// Every file read, the root first.
+    files: Vec<PathBuf>,
+    /// The file index and line number of each line of `text`.
+    lines: Vec<(usize, usize)>,
+}
+
+impl Source {
+    /// Every file read, the root first.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// The included file and line a line of `text` came from, counting from
+    /// 1. The file is `None` for lines of the root file.
+    pub fn origin(&self, line: usize) -> (Option<&Path>, usize) {
+        match line.checked_sub(1).and_then(|n| self.lines.get(n)) {
+            Some(&(file, n)) if file != 0 => (Some(self.files[file].as_path()), n),
+            Some(&(_, n)) => (None, n),
+            None => (None, line),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum IncludeError {
+    /// A file couldn't be read, with the reason.
+    Read(PathBuf, String),
+    /// The file and line of an `.include` whose file wasn't found.
+    NotFound(PathBuf, usize, String),
+    /// The file and line of an `.include` without a quoted file name.
+    Invalid(PathBuf, usize),
+    /// A file that includes itself, directly or not.
+    Cycle(PathBuf),
+}
+
+impl Display for IncludeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IncludeError::Read(path, e) => write!(f, "Failed to read {}: {e}", path.display()),
+            IncludeError::NotFound(path, line, name) => write!(
+                f,
+                "{}: Line {line}: Can't find included file \"{name}\".",
+                path.display()
+            ),
+            IncludeError::Invalid(path, line) => write!(
+                f,
+                "{}: Line {line}: Expected a quoted file name after .include.",
+                path.display()
+            ),
+            IncludeError::Cycle(path) => write!(f, "{} includes itself.", path.display()),
+        }
+    }
+}
+
+/// Read `path`, replacing each `.include "file"` line with the contents of
+/// `file`. The file is looked for beside the file including it, then in
+/// each of `search` in order.
+pub fn expand(path: &Path, search: &[PathBuf]) -> Result<Source, IncludeError> {
+    let mut source = Source {
+        text: vec![],
+        files: vec![],
+        lines: vec![],
+    };
+    expand_into(&mut source, path, search, &mut vec![])?;
+    Ok(source)
+}
+
+fn expand_into(
+    source: &mut Source,
+    path: &Path,
+    search: &[PathBuf],
+    stack: &mut Vec<PathBuf>,
+) -> Result<(), IncludeError> {
+    let read = |e: std::io::Error| IncludeError::Read(path.to_path_buf(), e.to_string());
+    let canonical = fs::canonicalize(path).map_err(read)?;
+    if stack.contains(&canonical) {
+        return Err(IncludeError::Cycle(path.to_path_buf()));
+    }
+    let contents = fs::read(path).map_err(read)?;
+
+    let file = source.files.len();
+    source.files.push(path.to_path_buf());
+    stack.push(canonical);
+
+    for (n, line) in contents.split(|b| *b == b'\n').enumerate() {
+        let lineno = n + 1;
+        let Some(name) = included(line) else {
+            if !source.lines.is_empty() {
+                source.text.push(b'\n');
+            }
+            source.text.extend_from_slice(line);
+            source.lines.push((file, lineno));
+            continue;
+        };
+        let name = name.ok_or_else(|| IncludeError::Invalid(path.to_path_buf(), lineno))?;
+
+        let beside = path.parent().unwrap_or(Path::new(""));
+        let found = std::iter::once(beside)
+            .chain(search.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(name))
+            .find(|p| p.is_file())
+            .ok_or_else(|| IncludeError::NotFound(path.to_path_buf(), lineno, name.to_string()))?;
+        expand_into(source, &found, search, stack)?;
+    }
+
+    stack.pop();
+    Ok(())
+}
+
+/// The file name of an `.include` line, `Some(None)` if it isn't quoted.
+fn included(line: &[u8]) -> Option<Option<&str>> {
+    let line = std::str::from_utf8(line).ok()?;
+    let line = line[..comment_start(line)].trim();
+    let rest = line.strip_prefix(".include")?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let name = rest
+        .trim()
+        .strip_prefix('"')
+        .and_then(|r| r.strip_suffix('"'));
+    Some(name.filter(|n| !n.is_empty() && !n.contains('"')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let dir = std::env::temp_dir().join(format!("asp-include-{}", std::process::id()));
+        let lib = dir.join("lib");
+        fs::create_dir_all(&lib).unwrap();
+        fs::write(
+            dir.join("main.s"),
+            "CLR r0\n.include \"consts.s\" ; shared\nPAUSE",
+        )
+        .unwrap();
+        fs::write(lib.join("consts.s"), "SPEED EQU 5\n.include \"more.s\"").unwrap();
+        fs::write(lib.join("more.s"), "STEPS EQU 10").unwrap();
+
+        let source = expand(&dir.join("main.s"), std::slice::from_ref(&lib)).unwrap();
+        assert_eq!(
+            String::from_utf8(source.text.clone()).unwrap(),
+            "CLR r0\nSPEED EQU 5\nSTEPS EQU 10\nPAUSE"
+        );
+        assert_eq!(source.origin(1), (None, 1));
+        assert_eq!(source.origin(2), (Some(lib.join("consts.s").as_path()), 1));
+        assert_eq!(source.origin(3), (Some(lib.join("more.s").as_path()), 1));
+        assert_eq!(source.origin(4), (None, 3));
+        assert_eq!(source.files().len(), 3);
+
+        assert_eq!(
+            expand(&dir.join("main.s"), &[]),
+            Err(IncludeError::NotFound(
+                dir.join("main.s"),
+                2,
+                "consts.s".to_string()
+            ))
+        );
+
+        fs::write(lib.join("more.s"), ".include \"consts.s\"").unwrap();
+        assert_eq!(
+            expand(&dir.join("main.s"), std::slice::from_ref(&lib)),
+            Err(IncludeError::Cycle(lib.join("consts.s")))
+        );
+
+        fs::write(dir.join("bad.s"), ".include consts.s").unwrap();
+        assert_eq!(
+            expand(&dir.join("bad.s"), &[lib]),
+            Err(IncludeError::Invalid(dir.join("bad.s"), 1))
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
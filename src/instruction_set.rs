@@ -0,0 +1,313 @@
+use crate::{
+    assembly::{self, AsmError, AsmWarning},
+    binary::Decoded,
+    isa::{IsaSpec, OperandKind},
+    op::{InvalidOpcode, Isa, Op},
+    word::Word,
+};
+
+/// An instruction set the assembler and disassembler can target, stored in
+/// words of type `W`.
+///
+/// The built-in ISA and runtime-defined ISAs both implement this, and other
+/// crates can implement it for their own 8 or 16-bit designs to reuse the
+/// file parsing, error reporting and output formats.
+pub trait InstructionSet<W: Word> {
+    /// One parsed instruction.
+    type Instr;
+
+    /// Number of bits in an instruction word.
+    fn width(&self) -> u8;
+
+    /// Parse an instruction from its mnemonic and operand tokens, reporting
+    /// every problem found.
+    fn parse(&self, mnemonic: &str, operands: &[&str]) -> Result<Self::Instr, Vec<AsmError>>;
+
+    fn encode(&self, instr: &Self::Instr) -> W;
+
+    fn decode(&self, word: W) -> Result<Self::Instr, InvalidOpcode>;
+
+    /// Assembly text for an instruction.
+    fn format(&self, instr: &Self::Instr) -> String;
+
+    /// An unconditional branch `offset` instructions forward from itself,
+    /// used to reach a `.entry` point. `None` if the ISA has no such branch
+    /// or it can't reach that far.
+    fn jump(&self, _offset: i32) -> Option<Self::Instr> {
+        None
+    }
+
+    /// How far a branch jumps from its own address, `None` if the
+    /// instruction isn't a branch or the ISA doesn't say.
+    fn branch_offset(&self, _instr: &Self::Instr) -> Option<i32> {
+        None
+    }
+
+    /// The mnemonic replacing `mnemonic`, if it's a deprecated spelling the
+    /// ISA still accepts, so the assembler can warn about it.
+    fn replacement(&self, _mnemonic: &str) -> Option<String> {
+        None
+    }
+
+    /// What's suspicious about an instruction that is still legal, e.g. one
+    /// that changes nothing.
+    fn warning(&self, _instr: &Self::Instr) -> Option<AsmWarning> {
+        None
+    }
+
+    /// Whether execution can go on to the next instruction, false for an
+    /// unconditional branch.
+    fn falls_through(&self, _instr: &Self::Instr) -> bool {
+        true
+    }
+
+    /// The instruction with its registers renamed by `map`, see
+    /// [`Op::remap_registers`]. `None` if a register maps to one that
+    /// doesn't exist or the ISA can't rename registers.
+    fn remap_registers(&self, _instr: &Self::Instr, _map: &[(u8, u8)]) -> Option<Self::Instr> {
+        None
+    }
+}
+
+impl InstructionSet<u8> for Isa {
+    type Instr = Op;
+
+    fn width(&self) -> u8 {
+        8
+    }
+
+    fn parse(&self, mnemonic: &str, operands: &[&str]) -> Result<Op, Vec<AsmError>> {
+        assembly::parse_op(mnemonic, operands, self)
+    }
+
+    fn encode(&self, instr: &Op) -> u8 {
+        instr.to_binary()
+    }
+
+    fn decode(&self, word: u8) -> Result<Op, InvalidOpcode> {
+        Op::decode(word, self)
+    }
+
+    fn format(&self, instr: &Op) -> String {
+        instr.to_string()
+    }
+
+    fn jump(&self, offset: i32) -> Option<Op> {
+        let offset: i8 = offset.try_into().ok()?;
+        offset.try_into().ok().map(Op::BR)
+    }
+
+    fn branch_offset(&self, instr: &Op) -> Option<i32> {
+        instr.branch_offset()
+    }
+
+    fn falls_through(&self, instr: &Op) -> bool {
+        !matches!(instr, Op::BR(_))
+    }
+
+    fn warning(&self, instr: &Op) -> Option<AsmWarning> {
+        // `ADDI r0, 0` is left alone as it's the usual NOP
+        match instr {
+            Op::SUBI(_, imm) if imm.get() == 0 => Some(AsmWarning::NoEffect(instr.to_string())),
+            Op::MOV(d, s) if d == s => Some(AsmWarning::NoEffect(instr.to_string())),
+            _ => None,
+        }
+    }
+
+    fn remap_registers(&self, instr: &Op, map: &[(u8, u8)]) -> Option<Op> {
+        instr.remap_registers(map)
+    }
+}
+
+/// Instructions of a runtime-defined ISA are kept as their encoded words.
+impl<W: Word> InstructionSet<W> for IsaSpec {
+    type Instr = W;
+
+    fn width(&self) -> u8 {
+        IsaSpec::width(self)
+    }
+
+    fn parse(&self, mnemonic: &str, operands: &[&str]) -> Result<W, Vec<AsmError>> {
+        assembly::parse_spec(mnemonic, operands, self).map(W::truncate)
+    }
+
+    fn encode(&self, instr: &W) -> W {
+        *instr
+    }
+
+    fn decode(&self, word: W) -> Result<W, InvalidOpcode> {
+        match IsaSpec::decode(self, word.into()) {
+            Some(_) => Ok(word),
+            None => Err(InvalidOpcode(word.into())),
+        }
+    }
+
+    fn format(&self, instr: &W) -> String {
+        IsaSpec::format(self, (*instr).into()).unwrap_or_default()
+    }
+
+    fn jump(&self, offset: i32) -> Option<W> {
+        IsaSpec::jump(self, offset).map(W::truncate)
+    }
+
+    fn branch_offset(&self, instr: &W) -> Option<i32> {
+        IsaSpec::branch_offset(self, (*instr).into())
+    }
+
+    fn falls_through(&self, instr: &W) -> bool {
+        IsaSpec::falls_through(self, (*instr).into())
+    }
+
+    fn replacement(&self, mnemonic: &str) -> Option<String> {
+        let instr = self
+            .renamed(mnemonic)
+            .filter(|_| self.accepts_deprecated())?;
+        Some(instr.mnemonic().to_string())
+    }
+
+    fn remap_registers(&self, instr: &W, map: &[(u8, u8)]) -> Option<W> {
+        let (spec, values) = IsaSpec::decode(self, (*instr).into())?;
+        let values = spec
+            .operands()
+            .iter()
+            .zip(values)
+            .map(|(o, v)| {
+                let to = map.iter().find(|(from, _)| *from as i32 == v);
+                match (o.kind, to) {
+                    (OperandKind::Reg, Some((_, to))) => {
+                        let (min, max) = o.range();
+                        (min..=max).contains(&(*to as i32)).then_some(*to as i32)
+                    }
+                    _ => Some(v),
+                }
+            })
+            .collect::<Option<Vec<i32>>>()?;
+        Some(W::truncate(spec.encode(&values)))
+    }
+}
+
+/// An instruction set whose program memory may also hold data, e.g. a table
+/// placed with `.byte`. Words that don't decode as an instruction of the
+/// inner ISA are kept as they are rather than rejected.
+pub struct WithData<'a, I>(pub &'a I);
+
+impl<W: Word, I: InstructionSet<W>> InstructionSet<W> for WithData<'_, I> {
+    type Instr = Decoded<I::Instr, W>;
+
+    fn width(&self) -> u8 {
+        self.0.width()
+    }
+
+    fn parse(&self, mnemonic: &str, operands: &[&str]) -> Result<Self::Instr, Vec<AsmError>> {
+        self.0.parse(mnemonic, operands).map(Decoded::Instr)
+    }
+
+    fn encode(&self, instr: &Self::Instr) -> W {
+        match instr {
+            Decoded::Instr(i) => self.0.encode(i),
+            Decoded::Data(w) => *w,
+        }
+    }
+
+    fn decode(&self, word: W) -> Result<Self::Instr, InvalidOpcode> {
+        Ok(match self.0.decode(word) {
+            Ok(i) => Decoded::Instr(i),
+            Err(_) => Decoded::Data(word),
+        })
+    }
+
+    fn format(&self, instr: &Self::Instr) -> String {
+        match instr {
+            Decoded::Instr(i) => self.0.format(i),
+            Decoded::Data(w) => {
+                let digits = (self.0.width() as usize).div_ceil(4);
+                format!(".byte 0x{:0digits$x}", (*w).into())
+            }
+        }
+    }
+
+    fn jump(&self, offset: i32) -> Option<Self::Instr> {
+        self.0.jump(offset).map(Decoded::Instr)
+    }
+
+    fn branch_offset(&self, instr: &Self::Instr) -> Option<i32> {
+        match instr {
+            Decoded::Instr(i) => self.0.branch_offset(i),
+            Decoded::Data(_) => None,
+        }
+    }
+
+    fn replacement(&self, mnemonic: &str) -> Option<String> {
+        self.0.replacement(mnemonic)
+    }
+
+    fn warning(&self, instr: &Self::Instr) -> Option<AsmWarning> {
+        match instr {
+            Decoded::Instr(i) => self.0.warning(i),
+            Decoded::Data(_) => None,
+        }
+    }
+
+    fn falls_through(&self, instr: &Self::Instr) -> bool {
+        match instr {
+            Decoded::Instr(i) => self.0.falls_through(i),
+            Decoded::Data(_) => true,
+        }
+    }
+
+    fn remap_registers(&self, instr: &Self::Instr, map: &[(u8, u8)]) -> Option<Self::Instr> {
+        match instr {
+            Decoded::Instr(i) => self.0.remap_registers(i, map).map(Decoded::Instr),
+            Decoded::Data(w) => Some(Decoded::Data(*w)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{imm::I5, isa::InstrSpec};
+
+    /// Parse, encode, decode and format one line of assembly.
+    fn round_trip<W: Word, I: InstructionSet<W>>(isa: &I, line: &str) -> String {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let instr = isa.parse(tokens[0], &tokens[1..]).unwrap();
+        isa.format(&isa.decode(isa.encode(&instr)).unwrap())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(round_trip(&Isa::default(), "ADDI r1 5"), "ADDI r1, 5");
+
+        let spec = IsaSpec::new(
+            "wide",
+            12,
+            vec![InstrSpec::new("JMP", "0001_iiiiiiii", &["i:signed"]).unwrap()],
+        )
+        .unwrap();
+        assert_eq!(round_trip::<u16, _>(&spec, "JMP -100"), "JMP -100");
+        assert!(InstructionSet::<u16>::decode(&spec, 0).is_err());
+        assert_eq!(InstructionSet::<u16>::jump(&spec, 1), None);
+    }
+
+    #[test]
+    fn test_remap_registers() {
+        let spec = IsaSpec::new(
+            "wide",
+            12,
+            vec![InstrSpec::new("MOV", "0001_00rr_00ss", &["r:reg", "s:reg"]).unwrap()],
+        )
+        .unwrap();
+        let mov = InstructionSet::<u16>::parse(&spec, "MOV", &["r1", "r3"]).unwrap();
+        let swapped = spec.remap_registers(&mov, &[(1, 3), (3, 1)]).unwrap();
+        assert_eq!(InstructionSet::<u16>::format(&spec, &swapped), "MOV r3, r1");
+        assert_eq!(spec.remap_registers(&mov, &[(1, 4)]), None);
+    }
+
+    #[test]
+    fn test_jump() {
+        let isa = Isa::default();
+        assert_eq!(isa.jump(15), Some(Op::BR(I5::new(15).unwrap())));
+        assert_eq!(isa.jump(16), None);
+    }
+}
@@ -0,0 +1,698 @@
+use std::fmt::Display;
+
+#[cfg(feature = "toml")]
+use serde::Deserialize;
+
+use crate::encoding::{Encoding, EncodingError};
+use crate::imm::sign_extend;
+
+/// An instruction set defined at runtime rather than built into the crate,
+/// e.g. loaded from a TOML file with [`IsaSpec::from_toml`].
+///
+/// ```toml
+/// name = "my_cpu"
+/// width = 8
+/// registers = 4 # optional, defaults to what the register fields can address
+///
+/// [[instruction]]
+/// mnemonic = "ADDI"
+/// encoding = "000iiirr"
+/// operands = ["r:reg", "i:unsigned"]
+/// deprecated = ["ADDU"] # optional, older mnemonics still accepted
+///
+/// [[instruction]]
+/// mnemonic = "BR"
+/// encoding = "100iiiii"
+/// operands = ["i:signed"]
+/// branch = "always" # optional, or "conditional", by its signed operand
+/// ```
+#[derive(Debug)]
+pub struct IsaSpec {
+    pub name: String,
+    width: u8,
+    registers: u8,
+    instructions: Vec<InstrSpec>,
+    deny_deprecated: bool,
+    old_names: bool,
+}
+
+#[derive(Debug)]
+pub struct InstrSpec {
+    mnemonic: String,
+    encoding: Encoding,
+    operands: Vec<OperandSpec>,
+    deprecated: Vec<String>,
+    branch: Option<Branch>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OperandSpec {
+    pub field: char,
+    pub kind: OperandKind,
+    pub width: u8,
+    registers: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperandKind {
+    Reg,
+    Signed,
+    Unsigned,
+}
+
+/// How an instruction branches, by the offset from its own address in its
+/// one signed operand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "toml", derive(Deserialize), serde(rename_all = "lowercase"))]
+pub enum Branch {
+    /// Always taken, so execution never goes on to the next instruction.
+    Always,
+    /// Only taken sometimes, e.g. when a register is zero.
+    Conditional,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum IsaError {
+    Toml(String),
+    UnsupportedWidth(u8),
+    Encoding(String, EncodingError),
+    WidthMismatch(String, u8),
+    InvalidOperand(String, String),
+    UnknownField(String, char),
+    UnusedField(String, char),
+    DuplicateMnemonic(String),
+    Overlap(String, String),
+    NoRegisters,
+    RegisterFieldTooNarrow(String, char),
+    BranchOffset(String),
+}
+
+impl Display for IsaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IsaError::Toml(e) => write!(f, "Invalid ISA file: {e}"),
+            IsaError::UnsupportedWidth(w) => {
+                write!(
+                    f,
+                    "Instruction width {w} is not supported, must be 1 to 16."
+                )
+            }
+            IsaError::Encoding(m, e) => write!(f, "{m}: {e}"),
+            IsaError::WidthMismatch(m, w) => {
+                write!(f, "{m}: Encoding has {w} bits but the ISA width differs.")
+            }
+            IsaError::InvalidOperand(m, o) => write!(
+                f,
+                "{m}: Invalid operand \"{o}\", expected <field>:<reg|signed|unsigned>."
+            ),
+            IsaError::UnknownField(m, c) => write!(f, "{m}: Field '{c}' is not in the encoding."),
+            IsaError::UnusedField(m, c) => {
+                write!(f, "{m}: Encoding field '{c}' is not used by any operand.")
+            }
+            IsaError::DuplicateMnemonic(m) => write!(f, "{m}: Mnemonic is defined twice."),
+            IsaError::Overlap(a, b) => write!(f, "{a} and {b} have overlapping encodings."),
+            IsaError::NoRegisters => write!(f, "The register count must be at least 1."),
+            IsaError::RegisterFieldTooNarrow(m, c) => {
+                write!(f, "{m}: Register field '{c}' can't address every register.")
+            }
+            IsaError::BranchOffset(m) => {
+                write!(
+                    f,
+                    "{m}: A branch needs exactly one signed operand for its offset."
+                )
+            }
+        }
+    }
+}
+
+impl OperandSpec {
+    /// Smallest and largest value the operand can hold.
+    pub fn range(&self) -> (i32, i32) {
+        match self.kind {
+            OperandKind::Reg => (0, self.registers as i32 - 1),
+            OperandKind::Unsigned => (0, (1 << self.width) - 1),
+            OperandKind::Signed => (-(1 << (self.width - 1)), (1 << (self.width - 1)) - 1),
+        }
+    }
+
+    fn format(&self, value: i32) -> String {
+        match self.kind {
+            OperandKind::Reg => format!("r{value}"),
+            OperandKind::Signed | OperandKind::Unsigned => format!("{value}"),
+        }
+    }
+}
+
+impl InstrSpec {
+    /// `operands` are written `<field>:<kind>` in assembly order, where kind
+    /// is `reg`, `signed` or `unsigned`, e.g. `["r:reg", "i:unsigned"]`.
+    pub fn new(mnemonic: &str, encoding: &str, operands: &[&str]) -> Result<InstrSpec, IsaError> {
+        let m = || mnemonic.to_string();
+        let encoding = Encoding::parse(encoding).map_err(|e| IsaError::Encoding(m(), e))?;
+
+        let operands = operands
+            .iter()
+            .map(|o| {
+                let invalid = || IsaError::InvalidOperand(m(), o.to_string());
+                let (field, kind) = o.split_once(':').ok_or_else(invalid)?;
+
+                let mut chars = field.chars();
+                let field = match (chars.next(), chars.next()) {
+                    (Some(c), None) => c,
+                    _ => return Err(invalid()),
+                };
+                let kind = match kind {
+                    "reg" => OperandKind::Reg,
+                    "signed" => OperandKind::Signed,
+                    "unsigned" => OperandKind::Unsigned,
+                    _ => return Err(invalid()),
+                };
+                let width = encoding.field_width(field);
+                if width == 0 {
+                    return Err(IsaError::UnknownField(m(), field));
+                }
+                Ok(OperandSpec {
+                    field,
+                    kind,
+                    width,
+                    registers: 1 << width,
+                })
+            })
+            .collect::<Result<Vec<OperandSpec>, IsaError>>()?;
+
+        if let Some(c) = encoding
+            .fields()
+            .find(|c| !operands.iter().any(|o| o.field == *c))
+        {
+            return Err(IsaError::UnusedField(m(), c));
+        }
+
+        Ok(InstrSpec {
+            mnemonic: mnemonic.to_string(),
+            encoding,
+            operands,
+            deprecated: vec![],
+            branch: None,
+        })
+    }
+
+    /// Older mnemonics for the instruction, from before it was renamed. The
+    /// assembler accepts them with a warning.
+    pub fn with_deprecated(mut self, names: &[&str]) -> InstrSpec {
+        self.deprecated = names.iter().map(|n| n.to_string()).collect();
+        self
+    }
+
+    /// Mark the instruction as a branch by the offset in its one signed
+    /// operand, so `.entry` and following branches in disassembly can use it.
+    pub fn with_branch(mut self, branch: Branch) -> Result<InstrSpec, IsaError> {
+        let signed = self
+            .operands
+            .iter()
+            .filter(|o| o.kind == OperandKind::Signed);
+        if signed.count() != 1 {
+            return Err(IsaError::BranchOffset(self.mnemonic));
+        }
+        self.branch = Some(branch);
+        Ok(self)
+    }
+
+    pub fn mnemonic(&self) -> &str {
+        &self.mnemonic
+    }
+
+    pub fn branch(&self) -> Option<Branch> {
+        self.branch
+    }
+
+    pub fn deprecated(&self) -> &[String] {
+        &self.deprecated
+    }
+
+    pub fn operands(&self) -> &[OperandSpec] {
+        &self.operands
+    }
+
+    /// Encode operand values, which must already be within range.
+    pub fn encode(&self, values: &[i32]) -> u32 {
+        self.operands
+            .iter()
+            .zip(values)
+            .fold(self.encoding.opcode(), |word, (o, v)| {
+                word | self.encoding.insert(o.field, *v as u32)
+            })
+    }
+
+    /// Operand values of a word matching this instruction's encoding.
+    pub fn decode(&self, word: u32) -> Vec<i32> {
+        self.operands
+            .iter()
+            .map(|o| {
+                let value = self.encoding.extract(o.field, word);
+                match o.kind {
+                    OperandKind::Signed => sign_extend(value, o.width),
+                    _ => value as i32,
+                }
+            })
+            .collect()
+    }
+}
+
+impl IsaSpec {
+    pub fn new(name: &str, width: u8, instructions: Vec<InstrSpec>) -> Result<IsaSpec, IsaError> {
+        if !(1..=16).contains(&width) {
+            return Err(IsaError::UnsupportedWidth(width));
+        }
+
+        for (n, a) in instructions.iter().enumerate() {
+            if a.encoding.width() != width {
+                return Err(IsaError::WidthMismatch(
+                    a.mnemonic.clone(),
+                    a.encoding.width(),
+                ));
+            }
+            let names = |i: &InstrSpec| {
+                let mut names = vec![i.mnemonic.clone()];
+                names.extend(i.deprecated.iter().cloned());
+                names
+            };
+            for b in &instructions[n + 1..] {
+                if let Some(m) = names(a).into_iter().find(|m| names(b).contains(m)) {
+                    return Err(IsaError::DuplicateMnemonic(m));
+                }
+                if a.encoding.overlaps(&b.encoding) {
+                    return Err(IsaError::Overlap(a.mnemonic.clone(), b.mnemonic.clone()));
+                }
+            }
+        }
+
+        // by default, as many registers as the narrowest register field allows
+        let registers = instructions
+            .iter()
+            .flat_map(|i| &i.operands)
+            .filter(|o| o.kind == OperandKind::Reg)
+            .map(|o| o.registers)
+            .min()
+            .unwrap_or(0)
+            .min(u8::MAX as u32) as u8;
+
+        Ok(IsaSpec {
+            name: name.to_string(),
+            width,
+            registers,
+            instructions,
+            deny_deprecated: false,
+            old_names: false,
+        })
+    }
+
+    /// Reject deprecated mnemonics instead of accepting them with a warning.
+    pub fn deny_deprecated(mut self) -> IsaSpec {
+        self.deny_deprecated = true;
+        self
+    }
+
+    /// Disassemble renamed instructions with their first deprecated
+    /// mnemonic, for tools that only know the old names.
+    pub fn with_old_names(mut self) -> IsaSpec {
+        self.old_names = true;
+        self
+    }
+
+    pub fn accepts_deprecated(&self) -> bool {
+        !self.deny_deprecated
+    }
+
+    /// Set the size of the register file. Register operands outside of it are
+    /// rejected, and every register field must be wide enough to address it.
+    pub fn with_registers(mut self, registers: u8) -> Result<IsaSpec, IsaError> {
+        if registers == 0 {
+            return Err(IsaError::NoRegisters);
+        }
+        for i in &mut self.instructions {
+            for o in i.operands.iter_mut().filter(|o| o.kind == OperandKind::Reg) {
+                if registers as u32 > 1 << o.width {
+                    return Err(IsaError::RegisterFieldTooNarrow(
+                        i.mnemonic.clone(),
+                        o.field,
+                    ));
+                }
+                o.registers = registers as u32;
+            }
+        }
+        self.registers = registers;
+        Ok(self)
+    }
+
+    /// Number of registers in the register file.
+    pub fn registers(&self) -> u8 {
+        self.registers
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn from_toml(s: &str) -> Result<IsaSpec, IsaError> {
+        #[derive(Deserialize)]
+        struct RawIsa {
+            #[serde(default)]
+            name: String,
+            width: u8,
+            registers: Option<u8>,
+            #[serde(rename = "instruction", default)]
+            instructions: Vec<RawInstr>,
+        }
+
+        #[derive(Deserialize)]
+        struct RawInstr {
+            mnemonic: String,
+            encoding: String,
+            #[serde(default)]
+            operands: Vec<String>,
+            #[serde(default)]
+            deprecated: Vec<String>,
+            branch: Option<Branch>,
+        }
+
+        let raw: RawIsa = toml::from_str(s).map_err(|e| IsaError::Toml(e.message().to_string()))?;
+        let instructions = raw
+            .instructions
+            .iter()
+            .map(|i| {
+                let operands: Vec<&str> = i.operands.iter().map(String::as_str).collect();
+                let deprecated: Vec<&str> = i.deprecated.iter().map(String::as_str).collect();
+                let spec = InstrSpec::new(&i.mnemonic, &i.encoding, &operands)?
+                    .with_deprecated(&deprecated);
+                match i.branch {
+                    Some(branch) => spec.with_branch(branch),
+                    None => Ok(spec),
+                }
+            })
+            .collect::<Result<Vec<InstrSpec>, IsaError>>()?;
+
+        let spec = IsaSpec::new(&raw.name, raw.width, instructions)?;
+        match raw.registers {
+            Some(n) => spec.with_registers(n),
+            None => Ok(spec),
+        }
+    }
+
+    /// Number of bits in an instruction word.
+    pub fn width(&self) -> u8 {
+        self.width
+    }
+
+    pub fn find(&self, mnemonic: &str) -> Option<&InstrSpec> {
+        self.instructions.iter().find(|i| i.mnemonic == mnemonic)
+    }
+
+    /// The instruction that `old` is a deprecated mnemonic of.
+    pub fn renamed(&self, old: &str) -> Option<&InstrSpec> {
+        self.instructions
+            .iter()
+            .find(|i| i.deprecated.iter().any(|d| d == old))
+    }
+
+    /// The instruction a word encodes and its operand values.
+    pub fn decode(&self, word: u32) -> Option<(&InstrSpec, Vec<i32>)> {
+        self.instructions
+            .iter()
+            .find(|i| i.encoding.matches(word))
+            .map(|i| (i, i.decode(word)))
+    }
+
+    /// The word of an always-taken branch `offset` words forward from itself,
+    /// `None` if the ISA has no such branch or it can't reach that far.
+    pub fn jump(&self, offset: i32) -> Option<u32> {
+        self.instructions
+            .iter()
+            .filter(|i| i.branch == Some(Branch::Always) && i.operands.len() == 1)
+            .find(|i| {
+                let (min, max) = i.operands[0].range();
+                (min..=max).contains(&offset)
+            })
+            .map(|i| i.encode(&[offset]))
+    }
+
+    /// How far a word branches from its own address, `None` if it isn't a
+    /// branch.
+    pub fn branch_offset(&self, word: u32) -> Option<i32> {
+        let (instr, values) = self.decode(word)?;
+        instr.branch?;
+        instr
+            .operands
+            .iter()
+            .zip(values)
+            .find_map(|(o, v)| (o.kind == OperandKind::Signed).then_some(v))
+    }
+
+    /// Whether execution can go on to the word after this one.
+    pub fn falls_through(&self, word: u32) -> bool {
+        self.decode(word)
+            .is_none_or(|(i, _)| i.branch != Some(Branch::Always))
+    }
+
+    /// Disassemble a word, or `None` if it isn't a valid instruction.
+    pub fn format(&self, word: u32) -> Option<String> {
+        let (instr, values) = self.decode(word)?;
+        let operands: Vec<String> = instr
+            .operands
+            .iter()
+            .zip(values)
+            .map(|(o, v)| o.format(v))
+            .collect();
+
+        let mnemonic = match self.old_names {
+            true => instr.deprecated.first().unwrap_or(&instr.mnemonic),
+            false => &instr.mnemonic,
+        };
+        Some(match operands.is_empty() {
+            true => mnemonic.clone(),
+            false => format!("{mnemonic} {}", operands.join(", ")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> IsaSpec {
+        IsaSpec::new(
+            "test",
+            8,
+            vec![
+                InstrSpec::new("BR", "100iiiii", &["i:signed"]).unwrap(),
+                InstrSpec::new("ADDI", "000iiirr", &["r:reg", "i:unsigned"]).unwrap(),
+                InstrSpec::new("MOV", "0111ddss", &["d:reg", "s:reg"]).unwrap(),
+                InstrSpec::new("PAUSE", "11111111", &[]).unwrap(),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let spec = spec();
+        let br = spec.find("BR").unwrap();
+        assert_eq!(br.operands()[0].range(), (-16, 15));
+        assert_eq!(br.encode(&[-5]), 0b100_11011);
+        assert_eq!(spec.format(0b100_11011), Some("BR -5".to_string()));
+
+        let addi = spec.find("ADDI").unwrap();
+        assert_eq!(addi.encode(&[2, 5]), 0b000_101_10);
+        assert_eq!(spec.format(0b000_101_10), Some("ADDI r2, 5".to_string()));
+
+        assert_eq!(spec.format(0xff), Some("PAUSE".to_string()));
+        assert_eq!(spec.format(0b110_00000), None);
+    }
+
+    #[test]
+    fn test_deprecated() {
+        let spec = || {
+            IsaSpec::new(
+                "v2",
+                8,
+                vec![
+                    InstrSpec::new("SRH", "0101iiii", &["i:unsigned"])
+                        .unwrap()
+                        .with_deprecated(&["SRH0"]),
+                    InstrSpec::new("PAUSE", "11111111", &[]).unwrap(),
+                ],
+            )
+            .unwrap()
+        };
+        assert_eq!(spec().renamed("SRH0").unwrap().mnemonic(), "SRH");
+        assert!(spec().renamed("SRH").is_none());
+        assert!(spec().accepts_deprecated());
+        assert!(!spec().deny_deprecated().accepts_deprecated());
+        assert_eq!(spec().format(0x53), Some("SRH 3".to_string()));
+        assert_eq!(
+            spec().with_old_names().format(0x53),
+            Some("SRH0 3".to_string())
+        );
+        assert_eq!(
+            spec().with_old_names().format(0xff),
+            Some("PAUSE".to_string())
+        );
+
+        let clash = IsaSpec::new(
+            "v2",
+            8,
+            vec![
+                InstrSpec::new("SRH", "0101iiii", &["i:unsigned"])
+                    .unwrap()
+                    .with_deprecated(&["PAUSE"]),
+                InstrSpec::new("PAUSE", "11111111", &[]).unwrap(),
+            ],
+        );
+        assert_eq!(
+            clash.unwrap_err(),
+            IsaError::DuplicateMnemonic("PAUSE".to_string())
+        );
+    }
+
+    #[test]
+    fn test_registers() {
+        let spec = || {
+            IsaSpec::new(
+                "test",
+                8,
+                vec![
+                    InstrSpec::new("INC", "10000rrr", &["r:reg"]).unwrap(),
+                    InstrSpec::new("MOV", "11ddd_sss", &["d:reg", "s:reg"]).unwrap(),
+                    InstrSpec::new("CLR", "0100_00rr", &["r:reg"]).unwrap(),
+                ],
+            )
+            .unwrap()
+        };
+        assert_eq!(spec().registers(), 4);
+
+        let inc = spec().with_registers(3).unwrap();
+        assert_eq!(inc.find("INC").unwrap().operands()[0].range(), (0, 2));
+        assert_eq!(inc.find("CLR").unwrap().operands()[0].range(), (0, 2));
+
+        assert_eq!(
+            spec().with_registers(8).unwrap_err(),
+            IsaError::RegisterFieldTooNarrow("CLR".to_string(), 'r')
+        );
+        assert_eq!(spec().with_registers(0).unwrap_err(), IsaError::NoRegisters);
+    }
+
+    #[test]
+    fn test_invalid_spec() {
+        let err = |m, e, o: &[&str]| InstrSpec::new(m, e, o).unwrap_err();
+        assert_eq!(
+            err("CLR", "011000rr", &["x:reg"]),
+            IsaError::UnknownField("CLR".to_string(), 'x')
+        );
+        assert_eq!(
+            err("CLR", "011000rr", &[]),
+            IsaError::UnusedField("CLR".to_string(), 'r')
+        );
+        assert_eq!(
+            err("CLR", "011000rr", &["r:register"]),
+            IsaError::InvalidOperand("CLR".to_string(), "r:register".to_string())
+        );
+
+        let overlap = IsaSpec::new(
+            "test",
+            8,
+            vec![
+                InstrSpec::new("A", "1111xxxx", &["x:unsigned"]).unwrap(),
+                InstrSpec::new("B", "11111111", &[]).unwrap(),
+            ],
+        );
+        assert_eq!(
+            overlap.unwrap_err(),
+            IsaError::Overlap("A".to_string(), "B".to_string())
+        );
+
+        let mismatch = IsaSpec::new("test", 8, vec![InstrSpec::new("A", "1111", &[]).unwrap()]);
+        assert_eq!(
+            mismatch.unwrap_err(),
+            IsaError::WidthMismatch("A".to_string(), 4)
+        );
+
+        let branch = InstrSpec::new("B", "1111xxxx", &["x:unsigned"]).unwrap();
+        assert_eq!(
+            branch.with_branch(Branch::Always).unwrap_err(),
+            IsaError::BranchOffset("B".to_string())
+        );
+    }
+
+    #[test]
+    fn test_branches() {
+        let spec = IsaSpec::new(
+            "test",
+            8,
+            vec![
+                InstrSpec::new("BR", "100iiiii", &["i:signed"])
+                    .unwrap()
+                    .with_branch(Branch::Always)
+                    .unwrap(),
+                InstrSpec::new("BRZ", "101rriii", &["r:reg", "i:signed"])
+                    .unwrap()
+                    .with_branch(Branch::Conditional)
+                    .unwrap(),
+                InstrSpec::new("PAUSE", "11111111", &[]).unwrap(),
+            ],
+        )
+        .unwrap();
+        assert_eq!(spec.jump(-3), Some(0b100_11101));
+        assert_eq!(spec.jump(16), None);
+        assert_eq!(spec.branch_offset(0b100_11101), Some(-3));
+        assert_eq!(spec.branch_offset(0b101_10_010), Some(2));
+        assert_eq!(spec.branch_offset(0xff), None);
+        assert!(!spec.falls_through(0b100_00001));
+        assert!(spec.falls_through(0b101_00_001));
+        assert!(spec.falls_through(0xff));
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_toml() {
+        let spec = IsaSpec::from_toml(
+            r#"
+            name = "test"
+            width = 8
+
+            [[instruction]]
+            mnemonic = "BRZ"
+            encoding = "101_iiiii"
+            operands = ["i:signed"]
+
+            [[instruction]]
+            mnemonic = "PAUSE"
+            encoding = "1111_1111"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(spec.name, "test");
+        assert_eq!(spec.format(0b101_00010), Some("BRZ 2".to_string()));
+        assert_eq!(spec.format(0xff), Some("PAUSE".to_string()));
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_toml_example_matches_builtin() {
+        use crate::op::Op;
+
+        let spec = IsaSpec::from_toml(include_str!("../examples/isa.toml")).unwrap();
+        for byte in 0..=255u8 {
+            let builtin = Op::try_from(byte).ok();
+            let text = builtin.as_ref().map(|op| op.to_string());
+            assert_eq!(spec.format(byte as u32), text, "{byte:08b}");
+            let offset = builtin.and_then(|op| op.branch_offset());
+            assert_eq!(spec.branch_offset(byte as u32), offset, "{byte:08b}");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "toml")]
+    fn test_toml_example_16_bit() {
+        let spec = IsaSpec::from_toml(include_str!("../examples/isa16.toml")).unwrap();
+        assert_eq!(spec.width(), 16);
+        assert_eq!(spec.find("ADDI").unwrap().encode(&[1, 200]), 0x01c8);
+        assert_eq!(spec.format(0x8fff), Some("BR -1".to_string()));
+        assert_eq!(spec.format(0xffff), Some("PAUSE".to_string()));
+    }
+}
@@ -1,8 +1,33 @@
+// binary literals are grouped by instruction field, e.g. `0b011000_00`
+#![allow(clippy::unusual_byte_groupings)]
+
 pub mod assembly;
 pub mod binary;
-mod imm;
-mod op;
-mod program;
-mod reg;
+pub mod checksum;
+pub mod encoding;
+pub mod explain;
+mod expr;
+pub mod fix;
+pub mod generate;
+pub mod grade;
+pub mod histogram;
+pub mod imm;
+pub mod include;
+pub mod instruction_set;
+pub mod isa;
+pub mod messages;
+pub mod metrics;
+pub mod object;
+pub mod op;
+pub mod pattern;
+pub mod program;
+pub mod reg;
+pub mod scaffold;
+pub mod session;
+pub mod similarity;
+pub mod size;
+pub mod testbench;
+pub mod testing;
+pub mod word;
 
 use program::Program;
@@ -1,14 +1,47 @@
 use std::process::ExitCode;
-use std::{fs::File, io::Write};
+use std::{
+    fmt::Display,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use asp::{assembly, binary};
-use clap::{Parser, ValueEnum};
+use asp::{
+    assembly::{self, AssembleOptions, Module, Syntax},
+    binary::{self, Decoded},
+    checksum, explain, fix,
+    generate::{self, Constraints, Rng},
+    grade::{GradeSpec, Report},
+    histogram::Histogram,
+    include,
+    instruction_set::{InstructionSet, WithData},
+    isa::IsaSpec,
+    messages::Lang,
+    object::{self, Object, Symbols},
+    op::{Extension, Isa, Op},
+    pattern::Pattern,
+    program::{self, segments_as_mif, words_as_mif, Equivalence, Program},
+    scaffold,
+    similarity::{self, Fingerprint},
+    size::Usage,
+    testbench,
+    word::{self, Word},
+};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
+// the names are the usual spelling of each format
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, ValueEnum)]
 enum OutputFmt {
     ASM,
     HEX,
     MIF,
+    /// Object file for `asp link`.
+    OBJ,
+    /// Self-checking Verilog testbench, with the program in out.memh.
+    TESTBENCH,
 }
 impl OutputFmt {
     fn ext(&self) -> &str {
@@ -16,73 +49,2104 @@ impl OutputFmt {
             OutputFmt::ASM => "s",
             OutputFmt::HEX => "hex",
             OutputFmt::MIF => "mif",
+            OutputFmt::OBJ => "o",
+            OutputFmt::TESTBENCH => "v",
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum IsaArg {
+    /// The base instruction set only.
+    Base,
+    /// Logic operations AND, OR, XOR and NOT.
+    #[value(alias = "logic")]
+    Ext,
+    /// Data memory access with LD and ST.
+    Mem,
+    /// Switch input and LED/7-segment output with IN and OUT.
+    Io,
+    /// Shifts and register addition with SHL, SHR and ADD.
+    Shift,
+}
+
+impl IsaArg {
+    fn extension(&self) -> Option<Extension> {
+        match self {
+            IsaArg::Base => None,
+            IsaArg::Ext => Some(Extension::Logic),
+            IsaArg::Mem => Some(Extension::Mem),
+            IsaArg::Io => Some(Extension::Io),
+            IsaArg::Shift => Some(Extension::Shift),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum SyntaxArg {
+    /// Require `#` on immediates, commas between operands and upper case
+    /// mnemonics.
+    Strict,
+    /// Make `#` and commas optional and ignore case.
+    Permissive,
+}
+
+impl SyntaxArg {
+    fn syntax(&self) -> Syntax {
+        match self {
+            SyntaxArg::Strict => Syntax::Strict,
+            SyntaxArg::Permissive => Syntax::Permissive,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum LangArg {
+    /// English.
+    En,
+    /// French.
+    Fr,
+}
+
+impl LangArg {
+    fn lang(&self) -> Lang {
+        match self {
+            LangArg::En => Lang::En,
+            LangArg::Fr => Lang::Fr,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum HistogramFmt {
+    Json,
+    Csv,
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about=None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    file: String,
+    #[command(subcommand)]
+    command: Option<Command>,
 
+    #[command(flatten)]
+    assemble: AssembleArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Explain an error code in detail, e.g. `asp explain-error E0002`.
+    ExplainError { code: String },
+    /// Link object files into one program, e.g. `asp link a.o b.o -f mif`.
+    Link(LinkArgs),
+    /// Report how much of the program and data memories a program uses.
+    Size(SizeArgs),
+    /// Report structural metrics of a program, e.g. basic blocks and
+    /// register use.
+    Stats(StatsArgs),
+    /// Compare two programs, e.g. `asp diff a.s b.s --semantic`.
+    Diff(DiffArgs),
+    /// Grade submissions against a spec, e.g. `asp grade --spec lab1.toml *.s`.
+    Grade(GradeArgs),
+    /// Report pairs of programs that share much of their code.
+    Similarity(SimilarityArgs),
+    /// Search a program for instructions, e.g. `asp grep 'MOVA *' dump.hex -H`.
+    Grep(GrepArgs),
+    /// Generate random programs, e.g. `asp gen --count 10 --seed 1`.
+    Gen(GenArgs),
+    /// Insert or remove instructions, fixing up branches around them.
+    Patch(PatchArgs),
+    /// Correct mistakes in a source file that have an unambiguous fix.
+    Fix(FixArgs),
+    /// Check a memory dump holds a program, e.g. `asp verify prog.s dump.hex`.
+    Verify(VerifyArgs),
+    /// Read the program memory from the board over a serial port.
+    Dump(DumpArgs),
+    /// Create a starter project with an example program and a Makefile.
+    Init(InitArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+struct OutputArgs {
     #[arg(short, long="fmt", value_enum, default_value_t=OutputFmt::MIF, help="Output format.")]
     format: OutputFmt,
 
     #[arg(short, long, help = "Output filename, by default out.<fmt>")]
     output: Option<String>,
 
+    #[arg(short, long)]
+    verbose: bool,
+
+    #[arg(long, default_value_t = 256, help = "Words of program memory.")]
+    depth: usize,
+
+    #[arg(long, default_value_t = 256, help = "Words of data memory.")]
+    data_depth: usize,
+
+    #[arg(
+        long,
+        default_value_t = 8,
+        value_parser = clap::value_parser!(u8).range(8..=16),
+        help = "Bits per word of data memory."
+    )]
+    data_width: u8,
+
+    #[arg(
+        long,
+        value_name = "ADDR",
+        default_value = "0",
+        value_parser = parse_address,
+        help = "Place the program from ADDR, filling below it with zeros."
+    )]
+    start_address: usize,
+
+    #[arg(
+        long,
+        value_name = "WORDS",
+        value_parser = parse_nonzero,
+        help = "Split HEX and MIF outputs into out.bank<n> images of this many words."
+    )]
+    bank_size: Option<usize>,
+
+    #[arg(long, help = "Allow branches from one bank into another.")]
+    allow_bank_crossing: bool,
+
+    #[arg(long, help = "Print the CRC-32 of the machine code.")]
+    crc: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write the CRC-32 of the machine code to a file."
+    )]
+    crc_file: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "ADDR",
+        help = "Store the CRC-32 of the machine code before ADDR at ADDR."
+    )]
+    crc_at: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Write the tool version, input hashes, ISA and options to out.meta."
+    )]
+    emit_metadata: bool,
+
+    #[arg(
+        long,
+        default_value_t = 1000,
+        help = "Clock cycles the testbench or DO script runs the program for."
+    )]
+    cycles: usize,
+
+    #[arg(
+        long,
+        value_name = "REG=VALUE",
+        value_parser = parse_expect,
+        help = "Register value the testbench checks at the end, e.g. r1=5."
+    )]
+    expect: Vec<(u8, u32)>,
+
+    #[arg(
+        long,
+        help = "Write a ModelSim/Questa script, out.do, that simulates the program."
+    )]
+    do_script: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Verilog source of the CPU for the DO script to compile."
+    )]
+    cpu_src: Vec<String>,
+}
+
+/// What an output was built from, for `--emit-metadata`.
+#[derive(Clone)]
+struct Provenance {
+    inputs: Vec<String>,
+    isa: String,
+}
+
+impl Display for Provenance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "tool: asp {}", env!("CARGO_PKG_VERSION"))?;
+        for path in &self.inputs {
+            match fs::read(path) {
+                Ok(bytes) => writeln!(f, "input: {path} crc32=0x{:08x}", checksum::crc32(&bytes))?,
+                Err(_) => writeln!(f, "input: {path} unreadable")?,
+            }
+        }
+        writeln!(f, "isa: {}", self.isa)?;
+
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        writeln!(f, "options: {}", args.join(" "))
+    }
+}
+
+/// A parsed program ready to save.
+struct Output<W> {
+    text: String,
+    words: Vec<W>,
+    /// How far each instruction branches, `None` if it doesn't.
+    branches: Vec<Option<i32>>,
+    data: Vec<u16>,
+    symbols: Symbols,
+    /// Words that only fill memory skipped by `.org` or `.align`.
+    gaps: Vec<Range<usize>>,
+}
+
+#[derive(Args, Debug)]
+struct LinkArgs {
+    #[arg(required = true, help = "Object files, placed in the order given.")]
+    files: Vec<String>,
+
+    #[command(flatten)]
+    out: OutputArgs,
+
+    #[command(flatten)]
+    isa: IsaArgs,
+}
+
+#[derive(Args, Debug)]
+struct AssembleArgs {
+    #[arg(required = true)]
+    file: Option<String>,
+
+    #[command(flatten)]
+    out: OutputArgs,
+
     #[arg(short = 'H', long, help = "Input file is machine code in a hex file.")]
     hex: bool,
 
-    #[arg(short, long)]
-    verbose: bool,
+    #[arg(
+        long,
+        value_enum,
+        default_value = "permissive",
+        help = "How closely the source must follow the textbook syntax."
+    )]
+    syntax: SyntaxArg,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "en",
+        help = "Language of assembly errors."
+    )]
+    lang: LangArg,
+
+    #[arg(
+        long,
+        value_name = "START..END",
+        value_parser = parse_range,
+        help = "Only output the instructions from START up to END, e.g. 0x10..0x30."
+    )]
+    extract: Option<Range<usize>>,
+
+    #[arg(
+        long,
+        value_name = "ADDR",
+        default_value = "0",
+        value_parser = parse_address,
+        help = "Address in memory of the first instruction, for reported addresses."
+    )]
+    base: usize,
+
+    #[arg(long, value_enum, help = "Print how often each mnemonic is used.")]
+    histogram: Option<HistogramFmt>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "hex",
+        help = "Name addresses in the disassembly from a file of `<name> <address>` lines."
+    )]
+    symbols: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        requires = "hex",
+        help = "Skip a header of this many bytes of machine code."
+    )]
+    skip: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        requires = "hex",
+        help = "Only decode this many bytes of machine code."
+    )]
+    take: Option<u64>,
+
+    #[arg(
+        long,
+        requires = "hex",
+        conflicts_with_all = ["extract", "remap", "histogram", "symbols"],
+        help = "Only decode instructions reached by branches from the first, leaving the rest as data."
+    )]
+    follow_flow: bool,
+
+    #[arg(
+        long,
+        value_name = "FROM=TO",
+        value_delimiter = ',',
+        value_parser = parse_remap,
+        help = "Rename registers, e.g. r1=r3,r3=r1 to swap r1 and r3."
+    )]
+    remap: Vec<(u8, u8)>,
+
+    #[arg(
+        short = 'I',
+        value_name = "DIR",
+        help = "Also look for .include files in this directory."
+    )]
+    include: Vec<PathBuf>,
+
+    #[arg(
+        short = 'D',
+        value_name = "NAME[=VALUE]",
+        value_parser = parse_define,
+        help = "Define a constant for .if conditions and operands, 1 if no value is given."
+    )]
+    define: Vec<(String, i32)>,
+
+    #[arg(
+        long,
+        help = "Treat warnings as errors, producing no output if there are any."
+    )]
+    deny_warnings: bool,
+
+    #[command(flatten)]
+    isa: IsaArgs,
+}
+
+#[derive(Args, Debug)]
+struct IsaArgs {
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "base",
+        help = "Instruction set extensions to enable."
+    )]
+    isa: Vec<IsaArg>,
+
+    #[arg(long, help = "TOML file defining the instruction set to use.")]
+    isa_file: Option<String>,
+
+    #[arg(
+        long,
+        requires = "isa_file",
+        help = "Reject deprecated mnemonics instead of warning about them."
+    )]
+    deny_deprecated: bool,
+
+    #[arg(
+        long,
+        requires = "isa_file",
+        help = "Disassemble renamed instructions with their deprecated mnemonics."
+    )]
+    old_names: bool,
+}
+
+#[derive(Args, Debug)]
+struct SizeArgs {
+    file: String,
+
+    #[command(flatten)]
+    isa: IsaArgs,
+
+    #[arg(long, default_value_t = 256, help = "Words of program memory.")]
+    depth: usize,
+
+    #[arg(long, default_value_t = 256, help = "Words of data memory.")]
+    data_depth: usize,
+
+    #[arg(long, help = "Print the report as JSON.")]
+    json: bool,
+
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        help = "Fail if more than this percentage of program memory is used."
+    )]
+    fail_above: Option<f64>,
+}
+
+#[derive(Args, Debug)]
+struct StatsArgs {
+    file: String,
+
+    #[arg(short = 'H', long, help = "Input file is machine code in a hex file.")]
+    hex: bool,
+
+    #[arg(long, help = "Print the report as JSON.")]
+    json: bool,
+
+    #[command(flatten)]
+    isa: IsaArgs,
+}
+
+#[derive(Args, Debug)]
+struct DiffArgs {
+    a: String,
+    b: String,
+
+    #[arg(short = 'H', long, help = "Input files are machine code in hex files.")]
+    hex: bool,
+
+    #[arg(
+        long,
+        help = "Ignore trailing padding and which no-op instruction is used."
+    )]
+    semantic: bool,
+
+    #[command(flatten)]
+    isa: IsaArgs,
+}
+
+#[derive(Args, Debug)]
+struct GradeArgs {
+    #[arg(required = true, help = "Assembly files to grade.")]
+    files: Vec<String>,
+
+    #[arg(long, help = "TOML file with the requirements to check.")]
+    spec: String,
+
+    #[command(flatten)]
+    isa: IsaArgs,
+
+    #[arg(long, help = "Write the report as JSON instead of CSV.")]
+    json: bool,
+
+    #[arg(short, long, help = "Report filename, by default printed.")]
+    output: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct SimilarityArgs {
+    #[arg(required = true, help = "Assembly files to compare.")]
+    files: Vec<String>,
+
+    #[command(flatten)]
+    isa: IsaArgs,
+
+    #[arg(long, default_value_t = 4, help = "Instructions per n-gram.")]
+    ngram: usize,
+
+    #[arg(
+        long,
+        default_value_t = 0.5,
+        help = "Report pairs at least this similar, from 0 to 1."
+    )]
+    threshold: f64,
+}
+
+#[derive(Args, Debug)]
+struct GrepArgs {
+    #[arg(help = "Instruction to find, with * matching any operand.")]
+    pattern: String,
+
+    file: String,
+
+    #[arg(short = 'H', long, help = "Input file is machine code in a hex file.")]
+    hex: bool,
+
+    #[arg(
+        short = 'C',
+        long,
+        default_value_t = 1,
+        help = "Instructions to show before and after each match."
+    )]
+    context: usize,
+
+    #[command(flatten)]
+    isa: IsaArgs,
+}
+
+#[derive(Args, Debug)]
+struct GenArgs {
+    #[arg(long, default_value_t = 1, help = "Number of programs to generate.")]
+    count: usize,
+
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    #[arg(long, default_value_t = 32, help = "Instructions per program.")]
+    len: usize,
+
+    #[arg(long, help = "Don't use BR or BRZ.")]
+    no_branches: bool,
+
+    #[arg(long, help = "Only use instructions that just change registers.")]
+    alu_only: bool,
+
+    #[arg(long, help = "End every program with PAUSE.")]
+    pause: bool,
+
+    #[arg(
+        short,
+        long = "fmt",
+        value_enum,
+        default_value_t = OutputFmt::ASM,
+        help = "Output format, asm or hex."
+    )]
+    format: OutputFmt,
+
+    #[arg(
+        short,
+        long,
+        default_value = "gen",
+        help = "Filename prefix, numbered from 0."
+    )]
+    output: String,
+
+    #[command(flatten)]
+    isa: IsaArgs,
+}
+
+#[derive(Args, Debug)]
+struct PatchArgs {
+    file: String,
+
+    #[arg(short = 'H', long, help = "Input file is machine code in a hex file.")]
+    hex: bool,
+
+    #[arg(
+        long,
+        value_name = "ADDR",
+        value_parser = parse_address,
+        requires = "code",
+        conflicts_with = "delete",
+        help = "Insert instructions before ADDR."
+    )]
+    insert: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Assembly file of the instructions to insert."
+    )]
+    code: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "START..END",
+        value_parser = parse_range,
+        required_unless_present = "insert",
+        help = "Remove the instructions from START up to END."
+    )]
+    delete: Option<Range<usize>>,
+
+    #[arg(
+        short,
+        long = "fmt",
+        value_enum,
+        default_value_t = OutputFmt::HEX,
+        help = "Output format, asm or hex."
+    )]
+    format: OutputFmt,
+
+    #[arg(short, long, help = "Output filename, by default out.<fmt>")]
+    output: Option<String>,
+
+    #[command(flatten)]
+    isa: IsaArgs,
+}
+
+#[derive(Args, Debug)]
+struct FixArgs {
+    file: String,
+
+    #[arg(long, help = "Print the fixes without changing the file.")]
+    dry_run: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = SyntaxArg::Permissive,
+        help = "Syntax the fixed source must follow."
+    )]
+    syntax: SyntaxArg,
+
+    #[command(flatten)]
+    isa: IsaArgs,
+}
+
+#[derive(Args, Debug)]
+struct VerifyArgs {
+    #[arg(help = "Assembly source of the program.")]
+    source: String,
+
+    #[arg(help = "Machine code read back from the board.")]
+    dump: String,
+
+    #[command(flatten)]
+    isa: IsaArgs,
+}
+
+#[derive(Args, Debug)]
+struct DumpArgs {
+    #[arg(
+        long,
+        value_name = "PORT",
+        help = "Serial port, e.g. /dev/ttyUSB0 or COM3."
+    )]
+    serial: String,
+
+    #[arg(long, default_value_t = 115200)]
+    baud: u32,
+
+    #[arg(long, default_value_t = 256, help = "Bytes the loader sends.")]
+    bytes: usize,
+
+    #[arg(long, default_value_t = 5, help = "Seconds to wait for data.")]
+    timeout: u64,
+
+    #[arg(short, long, help = "Save the machine code to a hex file.")]
+    output: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "SOURCE",
+        help = "Check the dump against an assembly file instead of listing it."
+    )]
+    verify: Option<String>,
+
+    #[command(flatten)]
+    isa: IsaArgs,
+}
+
+#[derive(Args, Debug)]
+struct InitArgs {
+    #[arg(default_value = ".", help = "Directory to create the project in.")]
+    dir: PathBuf,
+
+    #[arg(long, default_value_t = 256, help = "Words of program memory.")]
+    depth: usize,
+
+    #[arg(short, long="fmt", value_enum, default_value_t=OutputFmt::MIF, help="Output format.")]
+    format: OutputFmt,
+
+    #[arg(long, help = "Overwrite files that already exist.")]
+    force: bool,
+}
+
+/// The instruction set to assemble for.
+enum Target {
+    Builtin(Isa),
+    Spec(IsaSpec),
 }
 
 fn main() -> ExitCode {
     let cli = Cli::parse();
 
-    let Ok(file) = File::open(&cli.file) else {
-        println!("Failed to open {}", cli.file);
+    match cli.command {
+        Some(Command::ExplainError { code }) => explain_error(&code),
+        Some(Command::Link(args)) => link(args),
+        Some(Command::Size(args)) => size(args),
+        Some(Command::Stats(args)) => stats(args),
+        Some(Command::Diff(args)) => diff(args),
+        Some(Command::Grade(args)) => grade(args),
+        Some(Command::Similarity(args)) => similarity(args),
+        Some(Command::Grep(args)) => grep(args),
+        Some(Command::Gen(args)) => gen(args),
+        Some(Command::Patch(args)) => patch(args),
+        Some(Command::Fix(args)) => fix(args),
+        Some(Command::Verify(args)) => verify(args),
+        Some(Command::Dump(args)) => dump(args),
+        Some(Command::Init(args)) => init(args),
+        None => assemble(cli.assemble),
+    }
+}
+
+fn explain_error(code: &str) -> ExitCode {
+    match explain::explain(code) {
+        Some(text) => {
+            print!("{text}");
+            ExitCode::from(0)
+        }
+        None => {
+            eprintln!("Unknown error code {code}.");
+            ExitCode::from(1)
+        }
+    }
+}
+
+fn assemble(cli: AssembleArgs) -> ExitCode {
+    let Some(filename) = cli.file.clone() else {
+        eprintln!("No input file given.");
         return ExitCode::from(2);
     };
 
-    let program = match cli.hex {
-        false => assembly::parse_file(file),
-        true => binary::parse_file(file),
+    let Ok(file) = File::open(&filename) else {
+        eprintln!("Failed to open {}", filename);
+        return ExitCode::from(2);
+    };
+
+    let Some(target) = load_target(&cli.isa) else {
+        return ExitCode::from(2);
+    };
+
+    let mut provenance = Provenance {
+        inputs: vec![filename],
+        isa: String::new(),
+    };
+    match &target {
+        Target::Builtin(isa) => provenance.isa = isa.to_string(),
+        Target::Spec(spec) => {
+            provenance.isa = spec.name.clone();
+            provenance.inputs.extend(cli.isa.isa_file.clone());
+        }
+    }
+
+    match target {
+        Target::Builtin(isa) => run(&cli, file, &isa, isa.has(Extension::Mem), provenance),
+        Target::Spec(spec) if spec.width() <= 8 => {
+            run::<u8, _>(&cli, file, &spec, false, provenance)
+        }
+        Target::Spec(spec) => run::<u16, _>(&cli, file, &spec, false, provenance),
+    }
+}
+
+fn load_target(args: &IsaArgs) -> Option<Target> {
+    match &args.isa_file {
+        Some(path) => load_spec(path)
+            .map(|spec| match args.deny_deprecated {
+                true => spec.deny_deprecated(),
+                false => spec,
+            })
+            .map(|spec| match args.old_names {
+                true => spec.with_old_names(),
+                false => spec,
+            })
+            .map(Target::Spec),
+        None => build_isa(&args.isa).map(Target::Builtin),
+    }
+}
+
+fn size(cli: SizeArgs) -> ExitCode {
+    let file = Path::new(&cli.file);
+    let lengths = match load_target(&cli.isa) {
+        None => return ExitCode::from(2),
+        Some(Target::Builtin(isa)) => measure(file, &isa),
+        Some(Target::Spec(spec)) if spec.width() <= 8 => measure::<u8, _>(file, &spec),
+        Some(Target::Spec(spec)) => measure::<u16, _>(file, &spec),
+    };
+    let Some((program, data)) = lengths else {
+        eprintln!("Exiting due to errors.");
+        return ExitCode::from(1);
     };
 
+    let program = Usage::new(&program, cli.depth);
+    let data = Usage::contiguous(data, cli.data_depth);
+    if cli.json {
+        println!(
+            "{{\"program\": {}, \"data\": {}}}",
+            program.to_json(),
+            data.to_json()
+        );
+    } else {
+        println!("Program memory: {program}");
+        println!("Data memory: {data}");
+    }
+
+    match cli.fail_above {
+        Some(max) if program.percent() > max => {
+            eprintln!("Program memory use is above {max}%.");
+            ExitCode::from(1)
+        }
+        _ => ExitCode::from(0),
+    }
+}
+
+fn stats(cli: StatsArgs) -> ExitCode {
+    let Some(isa) = builtin_isa(&cli.isa) else {
+        return ExitCode::from(2);
+    };
+    let Ok(file) = File::open(&cli.file) else {
+        eprintln!("Failed to open {}", cli.file);
+        return ExitCode::from(2);
+    };
+    let program = match cli.hex {
+        true => binary::parse_file(file, &isa),
+        false => assembly::parse_file(Path::new(&cli.file), &isa),
+    };
     let Some(program) = program else {
-        println!("Exiting due to errors.");
+        eprintln!("Exiting due to errors.");
         return ExitCode::from(1);
     };
 
-    if cli.verbose {
-        println!("---- Assembly ----");
-        println!("{}", program.as_text());
+    let metrics = program.metrics();
+    match cli.json {
+        true => println!("{}", metrics.to_json()),
+        false => print!("{metrics}"),
+    }
+    ExitCode::from(0)
+}
 
-        println!("---- Machine Code ----");
-        for op in program.as_binary() {
-            println!("{:08b}", op)
+fn diff(cli: DiffArgs) -> ExitCode {
+    let Some(isa) = builtin_isa(&cli.isa) else {
+        return ExitCode::from(2);
+    };
+
+    let mut programs = vec![];
+    for path in [&cli.a, &cli.b] {
+        let Ok(file) = File::open(path) else {
+            eprintln!("Failed to open {}", path);
+            return ExitCode::from(2);
+        };
+        let program = match cli.hex {
+            true => binary::parse_file(file, &isa),
+            false => assembly::parse_file(Path::new(path), &isa),
+        };
+        match program {
+            Some(p) => programs.push(p),
+            None => {
+                eprintln!("Exiting due to errors in {path}.");
+                return ExitCode::from(2);
+            }
         }
     }
 
-    let outfilename = cli.output.unwrap_or(format!("out.{}", cli.format.ext()));
+    let options = Equivalence {
+        ignore_padding: cli.semantic,
+        any_nop: cli.semantic,
+    };
+    let (a, b) = (&programs[0], &programs[1]);
+    if a.equivalent(b, options) {
+        println!("Programs are equivalent.");
+        return ExitCode::from(0);
+    }
+
+    match a.first_difference(b, options) {
+        Some(n) => print_difference(n, (&cli.a, a), (&cli.b, b)),
+        None => println!("Programs differ in data memory."),
+    }
+    ExitCode::from(1)
+}
+
+/// Show the instruction at address `n` of two named programs.
+fn print_difference(n: usize, (a_name, a): (&str, &Program), (b_name, b): (&str, &Program)) {
+    let show = |ops: &[Op]| ops.get(n).map_or("(end)".to_string(), |o| o.to_string());
+    println!("Programs differ at 0x{n:04x}:");
+    println!("  {a_name}: {}", show(&a.ops));
+    println!("  {b_name}: {}", show(&b.ops));
+}
 
-    let mut outfile = File::create(&outfilename).expect("Failed to create output file.");
-    let contents = match cli.format {
-        OutputFmt::ASM => program.as_text().as_bytes().to_vec(),
-        OutputFmt::HEX => program.as_binary().to_vec(),
-        OutputFmt::MIF => program.as_mif().unwrap().as_bytes().to_vec(),
+fn verify(cli: VerifyArgs) -> ExitCode {
+    let Some(isa) = builtin_isa(&cli.isa) else {
+        return ExitCode::from(2);
     };
 
-    return match outfile.write(&contents) {
-        Ok(_) => {
-            println!("Output saved to {outfilename}");
+    let mut programs = vec![];
+    for (path, hex) in [(&cli.source, false), (&cli.dump, true)] {
+        let Ok(file) = File::open(path) else {
+            eprintln!("Failed to open {}", path);
+            return ExitCode::from(2);
+        };
+        let program = match hex {
+            true => binary::parse_file(file, &isa),
+            false => assembly::parse_file(Path::new(path), &isa),
+        };
+        match program {
+            Some(p) => programs.push(p),
+            None => {
+                eprintln!("Exiting due to errors in {path}.");
+                return ExitCode::from(2);
+            }
+        }
+    }
+
+    compare_dump((&cli.source, &programs[0]), (&cli.dump, &programs[1]))
+}
+
+/// Does the dump hold the program assembled from the source?
+fn compare_dump(source: (&str, &Program), dump: (&str, &Program)) -> ExitCode {
+    // The dump is usually the whole memory, zero past the program.
+    let options = Equivalence {
+        ignore_padding: true,
+        any_nop: false,
+    };
+    match source.1.first_difference(dump.1, options) {
+        None => {
+            println!("{} matches {}.", dump.0, source.0);
             ExitCode::from(0)
         }
-        Err(_) => {
-            println!("Failed to save output.");
+        Some(n) => {
+            print_difference(n, source, dump);
             ExitCode::from(1)
         }
+    }
+}
+
+fn dump(cli: DumpArgs) -> ExitCode {
+    let Some(isa) = builtin_isa(&cli.isa) else {
+        return ExitCode::from(2);
     };
+
+    let port = serialport::new(&cli.serial, cli.baud)
+        .timeout(Duration::from_secs(cli.timeout))
+        .open();
+    let mut port = match port {
+        Ok(port) => port,
+        Err(e) => {
+            eprintln!("Failed to open {}: {e}", cli.serial);
+            return ExitCode::from(2);
+        }
+    };
+    let mut contents = vec![0; cli.bytes];
+    if let Err(e) = port.read_exact(&mut contents) {
+        eprintln!(
+            "Failed to read {} bytes from {}: {e}",
+            cli.bytes, cli.serial
+        );
+        return ExitCode::from(1);
+    }
+
+    if let Some(path) = &cli.output {
+        if let Err(code) = write_output(Path::new(path), &contents) {
+            return code;
+        }
+    }
+
+    let Some(ops) = binary::parse_file_at(&contents[..], &isa, 0) else {
+        eprintln!("Exiting due to errors.");
+        return ExitCode::from(1);
+    };
+    let dump = Program { ops, data: vec![] };
+
+    match &cli.verify {
+        Some(path) => {
+            let Some(source) = assembly::parse_file(Path::new(path), &isa) else {
+                eprintln!("Exiting due to errors in {path}.");
+                return ExitCode::from(2);
+            };
+            compare_dump((path, &source), (&cli.serial, &dump))
+        }
+        None => {
+            println!("{}", dump.display().with_addresses());
+            ExitCode::from(0)
+        }
+    }
+}
+
+fn init(cli: InitArgs) -> ExitCode {
+    let files = scaffold::files(cli.depth, cli.format.ext());
+    if !cli.force {
+        let existing: Vec<_> = files
+            .iter()
+            .map(|(name, _)| cli.dir.join(name))
+            .filter(|path| path.exists())
+            .collect();
+        if !existing.is_empty() {
+            for path in existing {
+                eprintln!(
+                    "{} already exists, use --force to overwrite it.",
+                    path.display()
+                );
+            }
+            return ExitCode::from(2);
+        }
+    }
+
+    if fs::create_dir_all(&cli.dir).is_err() {
+        eprintln!("Failed to create {}.", cli.dir.display());
+        return ExitCode::from(2);
+    }
+    for (name, contents) in files {
+        if let Err(code) = write_output(&cli.dir.join(name), contents.as_bytes()) {
+            return code;
+        }
+    }
+    ExitCode::from(0)
+}
+
+fn grade(cli: GradeArgs) -> ExitCode {
+    let Ok(contents) = fs::read_to_string(&cli.spec) else {
+        eprintln!("Failed to open {}", cli.spec);
+        return ExitCode::from(2);
+    };
+    let spec = match GradeSpec::from_toml(&contents) {
+        Ok(spec) => spec,
+        Err(e) => {
+            eprintln!("{}: {e}", cli.spec);
+            return ExitCode::from(2);
+        }
+    };
+    let Some(target) = load_target(&cli.isa) else {
+        return ExitCode::from(2);
+    };
+
+    let mut reports = vec![];
+    for path in &cli.files {
+        // an unreadable submission fails rather than stopping the rest
+        let contents = match include::expand(Path::new(path), &[]) {
+            Ok(source) => source.text,
+            Err(e) => {
+                reports.push(Report::unreadable(path, &e.to_string()));
+                continue;
+            }
+        };
+        reports.push(match &target {
+            Target::Builtin(isa) => spec.grade(path, &contents, isa),
+            Target::Spec(s) if s.width() <= 8 => spec.grade::<u8, _>(path, &contents, s),
+            Target::Spec(s) => spec.grade::<u16, _>(path, &contents, s),
+        });
+    }
+
+    let report = match cli.json {
+        true => {
+            let rows: Vec<String> = reports.iter().map(|r| r.to_json()).collect();
+            format!("[\n  {}\n]\n", rows.join(",\n  "))
+        }
+        false => {
+            let rows: Vec<String> = reports.iter().map(|r| r.to_csv()).collect();
+            format!("{}\n{}\n", Report::CSV_HEADER, rows.join("\n"))
+        }
+    };
+    let Some(path) = &cli.output else {
+        print!("{report}");
+        return ExitCode::from(0);
+    };
+    if let Err(code) = write_output(Path::new(path), report.as_bytes()) {
+        return code;
+    }
+    let passed = reports.iter().filter(|r| r.passed()).count();
+    println!("{passed} of {} submissions passed.", reports.len());
+    ExitCode::from(0)
+}
+
+fn similarity(cli: SimilarityArgs) -> ExitCode {
+    let Some(target) = load_target(&cli.isa) else {
+        return ExitCode::from(2);
+    };
+
+    let mut names = vec![];
+    let mut fingerprints = vec![];
+    for path in &cli.files {
+        let file = Path::new(path);
+        let fingerprint = match &target {
+            Target::Builtin(isa) => fingerprint(file, isa, cli.ngram),
+            Target::Spec(s) if s.width() <= 8 => fingerprint::<u8, _>(file, s, cli.ngram),
+            Target::Spec(s) => fingerprint::<u16, _>(file, s, cli.ngram),
+        };
+        match fingerprint {
+            Some(f) => {
+                names.push(path);
+                fingerprints.push(f);
+            }
+            None => eprintln!("Skipping {path} due to errors."),
+        }
+    }
+
+    for (a, b, score) in similarity::similar_pairs(&fingerprints, cli.threshold) {
+        println!("{score:.2}\t{}\t{}", names[a], names[b]);
+    }
+    ExitCode::from(0)
+}
+
+fn grep(cli: GrepArgs) -> ExitCode {
+    let Some(pattern) = Pattern::parse(&cli.pattern) else {
+        eprintln!("The pattern is empty.");
+        return ExitCode::from(2);
+    };
+    let file = Path::new(&cli.file);
+    let lines = match load_target(&cli.isa) {
+        None => return ExitCode::from(2),
+        Some(Target::Builtin(isa)) => listing(file, &isa, cli.hex),
+        Some(Target::Spec(spec)) if spec.width() <= 8 => listing::<u8, _>(file, &spec, cli.hex),
+        Some(Target::Spec(spec)) => listing::<u16, _>(file, &spec, cli.hex),
+    };
+    let Some(lines) = lines else {
+        eprintln!("Exiting due to errors.");
+        return ExitCode::from(2);
+    };
+
+    let matches: Vec<bool> = lines.iter().map(|l| pattern.matches(l)).collect();
+    let shown: Vec<bool> = (0..lines.len())
+        .map(|n| {
+            let start = n.saturating_sub(cli.context);
+            let end = (n + cli.context + 1).min(lines.len());
+            matches[start..end].contains(&true)
+        })
+        .collect();
+    for (addr, line) in lines.iter().enumerate().filter(|(n, _)| shown[*n]) {
+        if addr > 0 && !shown[addr - 1] && shown[..addr].contains(&true) {
+            println!("--");
+        }
+        let marker = if matches[addr] { '>' } else { ' ' };
+        println!("{marker} 0x{addr:04x}: {line}");
+    }
+
+    match matches.contains(&true) {
+        true => ExitCode::from(0),
+        false => ExitCode::from(1),
+    }
+}
+
+fn gen(cli: GenArgs) -> ExitCode {
+    let Some(isa) = builtin_isa(&cli.isa) else {
+        return ExitCode::from(2);
+    };
+    if !matches!(cli.format, OutputFmt::ASM | OutputFmt::HEX) {
+        eprintln!("Programs can only be generated as asm or hex.");
+        return ExitCode::from(2);
+    }
+
+    let constraints = Constraints {
+        len: cli.len,
+        branches: !cli.no_branches,
+        alu_only: cli.alu_only,
+        end_with_pause: cli.pause,
+    };
+    let mut rng = Rng::new(cli.seed);
+    for n in 0..cli.count {
+        let program = Program {
+            ops: generate::random_program(&mut rng, &isa, constraints),
+            data: vec![],
+        };
+        let contents = match cli.format {
+            OutputFmt::HEX => program.as_binary(),
+            _ => (program.as_text() + "\n").into_bytes(),
+        };
+        let path = format!("{}{n}.{}", cli.output, cli.format.ext());
+        if let Err(code) = write_output(Path::new(&path), &contents) {
+            return code;
+        }
+    }
+    ExitCode::from(0)
+}
+
+fn patch(cli: PatchArgs) -> ExitCode {
+    let Some(isa) = builtin_isa(&cli.isa) else {
+        return ExitCode::from(2);
+    };
+    if !matches!(cli.format, OutputFmt::ASM | OutputFmt::HEX) {
+        eprintln!("Patched programs can only be saved as asm or hex.");
+        return ExitCode::from(2);
+    }
+
+    let mut paths = vec![&cli.file];
+    paths.extend(&cli.code);
+    let mut programs = vec![];
+    for (n, path) in paths.into_iter().enumerate() {
+        let Ok(file) = File::open(path) else {
+            eprintln!("Failed to open {}", path);
+            return ExitCode::from(2);
+        };
+        let program = match cli.hex && n == 0 {
+            true => binary::parse_file(file, &isa),
+            false => assembly::parse_file(Path::new(path), &isa),
+        };
+        match program {
+            Some(p) => programs.push(p),
+            None => {
+                eprintln!("Exiting due to errors in {path}.");
+                return ExitCode::from(2);
+            }
+        }
+    }
+
+    let mut program = programs.remove(0);
+    // clap requires --code with --insert, and --delete otherwise
+    let result = match (cli.insert, programs.pop()) {
+        (Some(addr), Some(code)) => program.insert(addr, code.ops),
+        _ => program.remove(cli.delete.unwrap_or_default()),
+    };
+    if let Err(e) = result {
+        eprintln!("{e}");
+        return ExitCode::from(1);
+    }
+
+    let contents = match cli.format {
+        OutputFmt::HEX => program.as_binary(),
+        _ => (program.as_text() + "\n").into_bytes(),
+    };
+    let path = cli.output.unwrap_or(format!("out.{}", cli.format.ext()));
+    match write_output(Path::new(&path), &contents) {
+        Ok(()) => ExitCode::from(0),
+        Err(code) => code,
+    }
+}
+
+fn fix(cli: FixArgs) -> ExitCode {
+    let Ok(contents) = fs::read_to_string(&cli.file) else {
+        eprintln!("Failed to open {}", cli.file);
+        return ExitCode::from(2);
+    };
+
+    // With an ISA file, the fixes are renames of its deprecated mnemonics.
+    let fixes = match load_target(&cli.isa) {
+        None => return ExitCode::from(2),
+        Some(Target::Spec(spec)) => fix::renames(&contents, &spec),
+        Some(Target::Builtin(isa)) => fix::fixes(&contents, &isa, cli.syntax.syntax()),
+    };
+    for f in &fixes {
+        println!("Line {}: {} -> {}", f.line, f.before.trim(), f.after.trim());
+    }
+    if fixes.is_empty() {
+        eprintln!("Nothing to fix.");
+        return ExitCode::from(0);
+    }
+    if cli.dry_run {
+        return ExitCode::from(0);
+    }
+
+    let backup = format!("{}.bak", cli.file);
+    if fs::copy(&cli.file, &backup).is_err() {
+        eprintln!("Failed to back up {} to {backup}.", cli.file);
+        return ExitCode::from(1);
+    }
+    let fixed = fix::apply(&contents, &fixes);
+    match write_atomic(Path::new(&cli.file), fixed.as_bytes()) {
+        Ok(_) => {
+            eprintln!("Fixed {} lines, original saved to {backup}", fixes.len());
+            ExitCode::from(0)
+        }
+        Err(_) => {
+            eprintln!("Failed to save {}.", cli.file);
+            ExitCode::from(1)
+        }
+    }
+}
+
+/// The assembly text of each instruction in an assembly or machine code file.
+fn listing<W: Word, I: InstructionSet<W>>(path: &Path, isa: &I, hex: bool) -> Option<Vec<String>> {
+    if hex {
+        let Ok(file) = File::open(path) else {
+            eprintln!("Failed to open {}", path.display());
+            return None;
+        };
+        let instrs = binary::parse_file_with(file, isa)?;
+        return Some(instrs.iter().map(|i| isa.format(i)).collect());
+    }
+    let isa = &WithData(isa);
+    let module = assembly::parse_file_with(path, isa)?;
+    let instrs = object::resolve(isa, module.instrs, module.symbols).ok()?;
+    Some(instrs.iter().map(|i| isa.format(i)).collect())
+}
+
+/// Assemble a file and fingerprint its instructions.
+fn fingerprint<W: Word, I: InstructionSet<W>>(
+    path: &Path,
+    isa: &I,
+    ngram: usize,
+) -> Option<Fingerprint> {
+    let isa = &WithData(isa);
+    let module = assembly::parse_file_with(path, isa)?;
+    let instrs = object::resolve(isa, module.instrs, module.symbols).ok()?;
+    let lines: Vec<String> = instrs.iter().map(|i| isa.format(i)).collect();
+    Some(Fingerprint::new(&lines, ngram))
+}
+
+/// Which words of program memory a source file places something in, leaving
+/// out the memory `.org` skips, and the words of data memory it fills.
+fn measure<W: Word, I: InstructionSet<W>>(path: &Path, isa: &I) -> Option<(Vec<bool>, usize)> {
+    let module = assembly::parse_file_with(path, &WithData(isa))?;
+    let mut occupied = vec![true; module.instrs.len()];
+    for gap in module.gaps {
+        occupied[gap].fill(false);
+    }
+    Some((occupied, module.data.len()))
+}
+
+/// The built-in instruction set, for commands that can't use an ISA file.
+fn builtin_isa(args: &IsaArgs) -> Option<Isa> {
+    match &args.isa_file {
+        Some(path) => {
+            eprintln!("This command only supports the built-in instruction set, not {path}.");
+            None
+        }
+        None => build_isa(&args.isa),
+    }
+}
+
+/// The built-in instruction set with the chosen extensions.
+fn build_isa(args: &[IsaArg]) -> Option<Isa> {
+    let isa = args
+        .iter()
+        .filter_map(IsaArg::extension)
+        .fold(Isa::default(), Isa::with);
+    match isa.conflict() {
+        Some((a, b)) => {
+            eprintln!("The {a} and {b} extensions can't be enabled together.");
+            None
+        }
+        None => Some(isa),
+    }
+}
+
+fn link(cli: LinkArgs) -> ExitCode {
+    let Some(isa) = builtin_isa(&cli.isa) else {
+        return ExitCode::from(2);
+    };
+
+    let mut objects = vec![];
+    for path in &cli.files {
+        let Ok(contents) = fs::read_to_string(path) else {
+            eprintln!("Failed to open {}", path);
+            return ExitCode::from(2);
+        };
+        match Object::parse(&contents) {
+            Ok(obj) => objects.push(obj),
+            Err(e) => {
+                eprintln!("{path}: {e}");
+                return ExitCode::from(2);
+            }
+        }
+    }
+
+    let parsed = match object::link(&objects) {
+        Ok(words) => disassemble(&isa, words),
+        Err(errors) => {
+            for e in errors {
+                eprintln!("{e}");
+            }
+            None
+        }
+    };
+    let provenance = Provenance {
+        inputs: cli.files.clone(),
+        isa: isa.to_string(),
+    };
+    save(&cli.out, parsed, 8, false, provenance)
+}
+
+/// Pair linked machine code with its disassembly.
+fn disassemble(isa: &Isa, words: Vec<u8>) -> Option<Output<u8>> {
+    let mut lines = vec![];
+    let mut branches = vec![];
+    for (n, w) in words.iter().enumerate() {
+        match isa.decode(*w) {
+            Ok(op) => {
+                lines.push(op.to_string());
+                branches.push(op.branch_offset());
+            }
+            Err(e) => {
+                eprintln!("Error[{}] at 0x{n:04x}: {e}", e.code());
+                return None;
+            }
+        }
+    }
+    Some(Output {
+        text: lines.join("\n"),
+        words,
+        branches,
+        data: vec![],
+        symbols: Symbols::default(),
+        gaps: vec![],
+    })
+}
+
+/// Assemble or disassemble `file` for any instruction set and save the result.
+fn run<W: Word, I: InstructionSet<W>>(
+    cli: &AssembleArgs,
+    file: File,
+    isa: &I,
+    data_memory: bool,
+    mut provenance: Provenance,
+) -> ExitCode {
+    if cli.hex && cli.follow_flow {
+        let decoded = slice_file(file, cli.skip, cli.take)
+            .and_then(|part| binary::parse_flow_at(part, isa, cli.base));
+        let output = decoded.map(|d| flow_output(isa, d, cli.base));
+        return save(&cli.out, output, isa.width(), data_memory, provenance);
+    }
+    if cli.hex {
+        let module = slice_file(file, cli.skip, cli.take)
+            .and_then(|part| binary::parse_file_at(part, isa, cli.base))
+            .map(|instrs| Module {
+                instrs,
+                data: vec![],
+                symbols: Symbols::default(),
+                aliases: vec![],
+                gaps: vec![],
+                notes: vec![],
+            });
+        return finish(cli, &cli.out, module, isa, data_memory, provenance);
+    }
+
+    // tables placed with `.byte` needn't be valid instructions
+    let isa = &WithData(isa);
+    let filename = cli.file.as_deref().unwrap_or_default();
+    let opts = AssembleOptions {
+        syntax: cli.syntax.syntax(),
+        defines: cli.define.clone(),
+        lang: cli.lang.lang(),
+        include_paths: cli.include.clone(),
+        deny_warnings: cli.deny_warnings,
+        base: cli.out.start_address,
+        depth: cli.out.depth,
+        data_depth: cli.out.data_depth,
+        data_width: cli.out.data_width,
+    };
+    let source = match include::expand(Path::new(filename), &opts.include_paths) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::from(2);
+        }
+    };
+    let included = source.files().iter().skip(1);
+    provenance
+        .inputs
+        .extend(included.map(|f| f.display().to_string()));
+    let Some(programs) = assembly::split_programs(&source.text) else {
+        return ExitCode::from(1);
+    };
+    if programs.is_empty() {
+        let module = assembly::parse_included(&source.text, &source, isa, &opts);
+        return finish(cli, &cli.out, module, isa, data_memory, provenance);
+    }
+
+    // Each `.program` is saved as <name>.<fmt>, beside the -o file if given.
+    let dir = cli
+        .out
+        .output
+        .as_ref()
+        .and_then(|o| Path::new(o).parent())
+        .unwrap_or(Path::new(""));
+    let mut result = ExitCode::from(0);
+    for (name, program) in programs {
+        let mut out = cli.out.clone();
+        let path = dir.join(format!("{name}.{}", out.format.ext()));
+        out.output = Some(path.display().to_string());
+        let module = assembly::parse_included(&program, &source, isa, &opts);
+        let code = finish(cli, &out, module, isa, data_memory, provenance.clone());
+        if code != ExitCode::from(0) {
+            result = code;
+        }
+    }
+    result
+}
+
+/// Resolve, transform and save one assembled or disassembled module.
+fn finish<W: Word, I: InstructionSet<W>>(
+    cli: &AssembleArgs,
+    out: &OutputArgs,
+    module: Option<Module<I::Instr>>,
+    isa: &I,
+    data_memory: bool,
+    provenance: Provenance,
+) -> ExitCode {
+    // Objects keep their symbols for the linker, other outputs need them
+    // resolved now.
+    let module = module.and_then(|m| match out.format {
+        OutputFmt::OBJ => Some(m),
+        _ => match object::resolve(isa, m.instrs, m.symbols) {
+            Ok(instrs) => Some(Module {
+                instrs,
+                data: m.data,
+                symbols: Symbols::default(),
+                aliases: m.aliases,
+                gaps: m.gaps,
+                notes: m.notes,
+            }),
+            Err(errors) => {
+                for e in errors {
+                    eprintln!("{e}");
+                }
+                None
+            }
+        },
+    });
+
+    if cli.extract.is_some() && matches!(out.format, OutputFmt::OBJ) {
+        eprintln!("Can't extract part of an object file.");
+        return ExitCode::from(2);
+    }
+    let module = match &cli.extract {
+        Some(range) => module.map(|m| extract(isa, m, range, cli.base)),
+        None => module,
+    };
+
+    for (n, (from, _)) in cli.remap.iter().enumerate() {
+        if cli.remap[..n].iter().any(|(f, _)| f == from) {
+            eprintln!("r{from} is renamed twice.");
+            return ExitCode::from(2);
+        }
+    }
+    let module = match cli.remap.is_empty() {
+        true => module,
+        false => module.and_then(|m| remap(isa, m, &cli.remap)),
+    };
+
+    if let (Some(fmt), Some(m)) = (cli.histogram, &module) {
+        let text: Vec<String> = m.instrs.iter().map(|i| isa.format(i)).collect();
+        let histogram = Histogram::new(text.iter().map(String::as_str));
+        match fmt {
+            HistogramFmt::Json => println!("{}", histogram.to_json()),
+            HistogramFmt::Csv => print!("{}", histogram.to_csv()),
+        }
+    }
+
+    let names = match &cli.symbols {
+        Some(path) => match load_symbols(path) {
+            Some(names) => names,
+            None => return ExitCode::from(2),
+        },
+        None => vec![],
+    };
+    let name_of = |addr: usize| names.iter().find(|(_, a)| *a == addr).map(|(s, _)| s);
+
+    let parsed = module.map(|m| {
+        let mut lines: Vec<String> = vec![];
+        for (name, reg) in &m.aliases {
+            lines.push(format!(".def {name} = {reg}"));
+        }
+        for (n, i) in m.instrs.iter().enumerate() {
+            // memory skipped by `.org` or `.align` is listed as an `.org`
+            if let Some(gap) = m.gaps.iter().find(|g| g.contains(&n)) {
+                let fill: u32 = isa.encode(i).into();
+                let org = out.start_address + gap.end;
+                match (gap.start == n, fill) {
+                    (false, _) => {}
+                    (true, 0) => lines.push(format!(".org 0x{org:04x}")),
+                    (true, _) => lines.push(format!(".org 0x{org:04x}, 0x{fill:x}")),
+                }
+                continue;
+            }
+            let addr = cli.base + n;
+            if let Some(name) = name_of(addr) {
+                lines.push(format!("; {name}:"));
+            }
+            // Show where disassembled branches go, as offsets are hard to follow.
+            let target = isa.branch_offset(i).map(|o| addr as i64 + o as i64);
+            lines.push(match target {
+                Some(t) if cli.hex && t >= 0 => match name_of(t as usize) {
+                    Some(name) => format!("{} ; {name}", isa.format(i)),
+                    None => format!("{} ; 0x{t:04x}", isa.format(i)),
+                },
+                _ => match m.notes.iter().find(|(at, _)| *at == n) {
+                    Some((_, note)) => format!("{} ; {note}", m.show_aliases(&isa.format(i))),
+                    None => m.show_aliases(&isa.format(i)),
+                },
+            });
+        }
+        if !m.data.is_empty() {
+            lines.push(".data".to_string());
+            for chunk in m.data.chunks(8) {
+                let values: Vec<String> = chunk.iter().map(|b| b.to_string()).collect();
+                lines.push(format!(".byte {}", values.join(", ")));
+            }
+        }
+        Output {
+            text: lines.join("\n"),
+            words: m.instrs.iter().map(|i| isa.encode(i)).collect(),
+            branches: m.instrs.iter().map(|i| isa.branch_offset(i)).collect(),
+            data: m.data,
+            symbols: m.symbols,
+            gaps: m.gaps,
+        }
+    });
+    save(out, parsed, isa.width(), data_memory, provenance)
+}
+
+/// Disassembly of machine code decoded by following branches. Words that
+/// aren't reached are listed as `.byte` values, 8 to a line.
+fn flow_output<W: Word, I: InstructionSet<W>>(
+    isa: &I,
+    decoded: Vec<Decoded<I::Instr, W>>,
+    base: usize,
+) -> Output<W> {
+    let digits = (isa.width() as usize).div_ceil(4);
+    let flush = |lines: &mut Vec<String>, table: &mut Vec<String>| {
+        if !table.is_empty() {
+            lines.push(format!(".byte {}", table.join(", ")));
+            table.clear();
+        }
+    };
+    let mut lines: Vec<String> = vec![];
+    let mut table: Vec<String> = vec![];
+    for (n, d) in decoded.iter().enumerate() {
+        match d {
+            Decoded::Data(w) => table.push(format!("0x{:0digits$x}", (*w).into())),
+            Decoded::Instr(i) => {
+                flush(&mut lines, &mut table);
+                let target = isa.branch_offset(i).map(|o| (base + n) as i64 + o as i64);
+                lines.push(match target {
+                    Some(t) if t >= 0 => format!("{} ; 0x{t:04x}", isa.format(i)),
+                    _ => isa.format(i),
+                });
+            }
+        }
+        if table.len() == 8 {
+            flush(&mut lines, &mut table);
+        }
+    }
+    flush(&mut lines, &mut table);
+
+    Output {
+        text: lines.join("\n"),
+        words: decoded
+            .iter()
+            .map(|d| match d {
+                Decoded::Instr(i) => isa.encode(i),
+                Decoded::Data(w) => *w,
+            })
+            .collect(),
+        branches: decoded
+            .iter()
+            .map(|d| match d {
+                Decoded::Instr(i) => isa.branch_offset(i),
+                Decoded::Data(_) => None,
+            })
+            .collect(),
+        data: vec![],
+        symbols: Symbols::default(),
+        gaps: vec![],
+    }
+}
+
+/// The part of `file` after `skip` bytes, at most `take` bytes long.
+fn slice_file(mut file: File, skip: Option<u64>, take: Option<u64>) -> Option<io::Take<File>> {
+    if let Some(skip) = skip {
+        if file.seek(SeekFrom::Start(skip)).is_err() {
+            eprintln!("Failed to skip {skip} bytes.");
+            return None;
+        }
+    }
+    Some(file.take(take.unwrap_or(u64::MAX)))
+}
+
+/// Keep only the instructions at the addresses in `range`, warning about
+/// branches into or out of it. The first instruction is at `base`.
+fn extract<W: Word, I: InstructionSet<W>>(
+    isa: &I,
+    mut module: Module<I::Instr>,
+    range: &Range<usize>,
+    base: usize,
+) -> Module<I::Instr> {
+    let range = range.start.saturating_sub(base)..range.end.saturating_sub(base);
+    let offsets: Vec<Option<i32>> = module.instrs.iter().map(|i| isa.branch_offset(i)).collect();
+    let (exits, entries) = program::crossings(&offsets, &range);
+    for addr in exits {
+        let addr = base + addr;
+        eprintln!("Warning: branch at 0x{addr:04x} leaves the extracted region.");
+    }
+    for addr in entries {
+        let addr = base + addr;
+        eprintln!("Warning: branch at 0x{addr:04x} enters the extracted region.");
+    }
+
+    let end = range.end.min(module.instrs.len());
+    let start = range.start.min(end);
+    module.instrs.truncate(end);
+    module.instrs.drain(..start);
+    let clip = |n: usize| n.clamp(start, end) - start;
+    module.gaps = module
+        .gaps
+        .iter()
+        .map(|g| clip(g.start)..clip(g.end))
+        .filter(|g| !g.is_empty())
+        .collect();
+    module.notes.retain(|(n, _)| (start..end).contains(n));
+    module.notes.iter_mut().for_each(|(n, _)| *n -= start);
+    module
+}
+
+/// Rename the registers of every instruction, printing the first that can't
+/// be renamed.
+fn remap<W: Word, I: InstructionSet<W>>(
+    isa: &I,
+    mut module: Module<I::Instr>,
+    map: &[(u8, u8)],
+) -> Option<Module<I::Instr>> {
+    let mut instrs = vec![];
+    for (n, instr) in module.instrs.iter().enumerate() {
+        match isa.remap_registers(instr, map) {
+            Some(instr) => instrs.push(instr),
+            None => {
+                eprintln!(
+                    "Can't rename the registers of \"{}\" at 0x{n:04x}.",
+                    isa.format(instr)
+                );
+                return None;
+            }
+        }
+    }
+    module.instrs = instrs;
+    for (_, reg) in &mut module.aliases {
+        let renamed = map.iter().find(|(from, _)| *reg == format!("r{from}"));
+        if let Some((_, to)) = renamed {
+            *reg = format!("r{to}");
+        }
+    }
+    Some(module)
+}
+
+/// A register value like `r1=5` for the testbench to check.
+fn parse_expect(s: &str) -> Result<(u8, u32), String> {
+    let (reg, value) = s.split_once('=').ok_or("expected REG=VALUE")?;
+    let value = parse_address(value)? as u32;
+    Ok((parse_register(reg)?, value))
+}
+
+/// A register renaming like `r1=r3`.
+fn parse_remap(s: &str) -> Result<(u8, u8), String> {
+    let (from, to) = s.split_once('=').ok_or("expected FROM=TO")?;
+    Ok((parse_register(from)?, parse_register(to)?))
+}
+
+/// A constant like `FAST` or `SPEED=0x10`, 1 if it has no value.
+fn parse_define(s: &str) -> Result<(String, i32), String> {
+    let (name, value) = s.split_once('=').unwrap_or((s, "1"));
+    let value = match value.strip_prefix("0x") {
+        Some(hex) => i32::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    Ok((name.to_string(), value.map_err(|e| e.to_string())?))
+}
+
+/// A register number from its name, e.g. `r1`.
+fn parse_register(s: &str) -> Result<u8, String> {
+    s.strip_prefix('r')
+        .and_then(|n| n.parse().ok())
+        .ok_or(format!("invalid register {s}"))
+}
+
+/// An address like `0x40` or `64`.
+fn parse_address(s: &str) -> Result<usize, String> {
+    let n = match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    n.map_err(|e| e.to_string())
+}
+
+/// A count of at least 1.
+fn parse_nonzero(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>().map_err(|e| e.to_string())? {
+        0 => Err("must be at least 1".to_string()),
+        n => Ok(n),
+    }
+}
+
+/// A range of addresses like `0x10..0x30` or `16..48`.
+fn parse_range(s: &str) -> Result<Range<usize>, String> {
+    let (start, end) = s.split_once("..").ok_or("expected START..END")?;
+    let (start, end) = (parse_address(start)?, parse_address(end)?);
+    match start <= end {
+        true => Ok(start..end),
+        false => Err("START is after END".to_string()),
+    }
+}
+
+/// Write the parsed program, as its disassembly, `width`-bit instruction
+/// words and symbols for linking, in the requested format. Built-in and
+/// runtime-defined ISAs share this output path.
+///
+/// A `.data` section, or `data_memory` if the CPU has one, adds a second
+/// file initializing the data memory beside HEX and MIF outputs.
+fn save<W: Word>(
+    cli: &OutputArgs,
+    parsed: Option<Output<W>>,
+    width: u8,
+    data_memory: bool,
+    provenance: Provenance,
+) -> ExitCode {
+    let Some(Output {
+        text,
+        mut words,
+        branches,
+        data,
+        symbols,
+        gaps,
+    }) = parsed
+    else {
+        eprintln!("Exiting due to errors.");
+        return ExitCode::from(1);
+    };
+
+    if cli.verbose {
+        println!("---- Assembly ----");
+        println!("{}", text);
+
+        println!("---- Machine Code ----");
+        for word in &words {
+            println!("{:0width$b}", word, width = width as usize)
+        }
+    }
+
+    if cli.start_address > 0 && matches!(cli.format, OutputFmt::OBJ) {
+        eprintln!("Objects are placed by the linker and can't have a start address.");
+        return ExitCode::from(1);
+    }
+    // Place the program at its start address by filling below it with zeros.
+    let start = cli.start_address;
+    words.splice(0..0, vec![W::truncate(0); start]);
+    let branches: Vec<Option<i32>> = vec![None; start].into_iter().chain(branches).collect();
+
+    let crc = match cli.crc_at {
+        Some(_) if W::BYTES * 8 != width as usize => {
+            eprintln!("A CRC can only be embedded in 8 or 16-bit words.");
+            return ExitCode::from(1);
+        }
+        Some(addr) => match checksum::embed_crc32(&mut words, addr) {
+            Some(crc) => crc,
+            None => {
+                eprintln!("The CRC address {addr} is inside the program.");
+                return ExitCode::from(1);
+            }
+        },
+        None => checksum::image_crc32(&words),
+    };
+
+    if words.len() > cli.depth {
+        eprintln!(
+            "Program has {} words, more than the program memory depth {}.",
+            words.len(),
+            cli.depth
+        );
+        return ExitCode::from(1);
+    }
+    if data.len() > cli.data_depth {
+        eprintln!(
+            "Data has {} words, more than the data memory depth {}.",
+            data.len(),
+            cli.data_depth
+        );
+        return ExitCode::from(1);
+    }
+    if let Some(size) = cli.bank_size.filter(|_| !cli.allow_bank_crossing) {
+        let crossings = program::bank_crossings(&branches, size);
+        for addr in &crossings {
+            eprintln!("Branch at 0x{addr:04x} crosses into another bank.");
+        }
+        if !crossings.is_empty() {
+            return ExitCode::from(1);
+        }
+    }
+    if matches!(cli.format, OutputFmt::OBJ) && !data.is_empty() {
+        eprintln!("Object files can't hold a .data section.");
+        return ExitCode::from(1);
+    }
+
+    if cli.do_script && matches!(cli.format, OutputFmt::ASM | OutputFmt::OBJ) {
+        eprintln!("--do-script needs a memory image, from -f mif, hex or testbench.");
+        return ExitCode::from(2);
+    }
+
+    let outfilename = cli
+        .output
+        .clone()
+        .unwrap_or(format!("out.{}", cli.format.ext()));
+
+    // Banked HEX and MIF outputs are one image per bank instead of one file.
+    let banked = cli
+        .bank_size
+        .filter(|_| matches!(cli.format, OutputFmt::HEX | OutputFmt::MIF));
+    if let Some(size) = banked {
+        let mut banked = words.clone();
+        banked.resize(cli.depth.max(words.len()), W::truncate(0));
+        for (n, bank) in banked.chunks(size).enumerate() {
+            let contents = match cli.format {
+                OutputFmt::MIF => match words_as_mif(bank, width, size) {
+                    Ok(mif) => mif.into_bytes(),
+                    Err(e) => {
+                        eprintln!("{e}");
+                        return ExitCode::from(1);
+                    }
+                },
+                _ => word::to_bytes(bank),
+            };
+            let ext = format!("bank{n}.{}", cli.format.ext());
+            if let Err(code) = write_output(&Path::new(&outfilename).with_extension(ext), &contents)
+            {
+                return code;
+            }
+        }
+    }
+
+    let contents = match cli.format {
+        OutputFmt::ASM => text.as_bytes().to_vec(),
+        OutputFmt::HEX => word::to_bytes(&words),
+        OutputFmt::MIF => {
+            match segments_as_mif(&segments(&words, start, &gaps), width, cli.depth) {
+                Ok(mif) => mif.into_bytes(),
+                Err(e) => {
+                    eprintln!("{e}");
+                    return ExitCode::from(1);
+                }
+            }
+        }
+        OutputFmt::OBJ => Object {
+            width,
+            code: words.iter().map(|w| (*w).into()).collect(),
+            symbols,
+        }
+        .to_string()
+        .into_bytes(),
+        OutputFmt::TESTBENCH => {
+            let image = Path::new(&outfilename).with_extension("memh");
+            let memh = testbench::memh(&words, width);
+            if let Err(code) = write_output(&image, memh.as_bytes()) {
+                return code;
+            }
+            match testbench::testbench(&image.display().to_string(), cli.cycles, &cli.expect) {
+                Ok(bench) => bench.into_bytes(),
+                Err(e) => {
+                    eprintln!("Failed to write the testbench: {e}");
+                    return ExitCode::from(1);
+                }
+            }
+        }
+    };
+
+    if data_memory || !data.is_empty() {
+        let image = match cli.format {
+            OutputFmt::MIF => match words_as_mif(&data, cli.data_width, cli.data_depth) {
+                Ok(mif) => Some(("data.mif", mif.into_bytes())),
+                Err(e) => {
+                    eprintln!("Data memory: {e}");
+                    return ExitCode::from(1);
+                }
+            },
+            // a byte per word unless they are wider
+            OutputFmt::HEX => match cli.data_width {
+                ..=8 => Some(("data.hex", data.iter().map(|w| *w as u8).collect())),
+                _ => Some(("data.hex", word::to_bytes(&data))),
+            },
+            OutputFmt::ASM | OutputFmt::OBJ | OutputFmt::TESTBENCH => None,
+        };
+        if let Some((ext, contents)) = image {
+            let datafilename = Path::new(&outfilename).with_extension(ext);
+            if let Err(code) = write_output(&datafilename, &contents) {
+                return code;
+            }
+        }
+    }
+
+    if banked.is_none() {
+        if let Err(code) = write_output(Path::new(&outfilename), &contents) {
+            return code;
+        }
+    }
+
+    if cli.do_script {
+        // ModelSim loads MIF directly; anything else goes through a memh image.
+        let (image, format) = match cli.format {
+            OutputFmt::MIF => (PathBuf::from(&outfilename), "mif"),
+            _ => (Path::new(&outfilename).with_extension("memh"), "hex"),
+        };
+        if matches!(cli.format, OutputFmt::HEX) {
+            let memh = testbench::memh(&words, width);
+            if let Err(code) = write_output(&image, memh.as_bytes()) {
+                return code;
+            }
+        }
+        let script = testbench::do_script(
+            &cli.cpu_src,
+            &image.display().to_string(),
+            format,
+            cli.cycles,
+        );
+        let script = match script {
+            Ok(script) => script,
+            Err(e) => {
+                eprintln!("Failed to write the DO script: {e}");
+                return ExitCode::from(1);
+            }
+        };
+        let scriptfilename = Path::new(&outfilename).with_extension("do");
+        if let Err(code) = write_output(&scriptfilename, script.as_bytes()) {
+            return code;
+        }
+    }
+
+    if cli.emit_metadata {
+        let metafilename = Path::new(&outfilename).with_extension("meta");
+        if let Err(code) = write_output(&metafilename, provenance.to_string().as_bytes()) {
+            return code;
+        }
+    }
+
+    if cli.crc {
+        println!("CRC-32: 0x{crc:08x}");
+    }
+    if let Some(path) = &cli.crc_file {
+        if let Err(code) = write_output(Path::new(path), format!("0x{crc:08x}\n").as_bytes()) {
+            return code;
+        }
+    }
+    ExitCode::from(0)
+}
+
+/// The parts of `words` with their addresses, leaving out the zeros below
+/// `start` and in `gaps`, so a MIF can fill them with ranges. The gaps are
+/// indexed from `start`.
+fn segments<'a, W: Word>(
+    words: &'a [W],
+    start: usize,
+    gaps: &[Range<usize>],
+) -> Vec<(usize, &'a [W])> {
+    let zero = |r: &Range<usize>| words[r.clone()].iter().all(|w| (*w).into() == 0);
+    let gaps = gaps.iter().map(|g| g.start + start..g.end + start);
+    let mut segments = vec![];
+    let mut next = start;
+    for gap in gaps.filter(zero) {
+        segments.push((next, &words[next..gap.start]));
+        next = gap.end;
+    }
+    segments.push((next, &words[next..]));
+    segments
+}
+
+fn write_output(path: &Path, contents: &[u8]) -> Result<(), ExitCode> {
+    match write_atomic(path, contents) {
+        Ok(_) => {
+            eprintln!("Output saved to {}", path.display());
+            Ok(())
+        }
+        Err(_) => {
+            eprintln!("Failed to save output.");
+            Err(ExitCode::from(1))
+        }
+    }
+}
+
+fn load_symbols(path: &str) -> Option<Vec<(String, usize)>> {
+    let Ok(contents) = fs::read_to_string(path) else {
+        eprintln!("Failed to open {}", path);
+        return None;
+    };
+    object::parse_symbol_file(&contents)
+        .map_err(|e| eprintln!("{path}: {e}"))
+        .ok()
+}
+
+fn load_spec(path: &str) -> Option<IsaSpec> {
+    let Ok(contents) = fs::read_to_string(path) else {
+        eprintln!("Failed to open {}", path);
+        return None;
+    };
+    IsaSpec::from_toml(&contents)
+        .map_err(|e| eprintln!("{path}: {e}"))
+        .ok()
+}
+
+/// Write `contents` to a temporary file beside `path`, then rename it over
+/// `path` so a failed write never leaves a truncated output behind.
+fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmpname = path.as_os_str().to_owned();
+    tmpname.push(".tmp");
+    let tmppath = Path::new(&tmpname);
+
+    let result = File::create(tmppath).and_then(|mut f| {
+        f.write_all(contents)?;
+        f.sync_all()
+    });
+
+    match result.and_then(|_| fs::rename(tmppath, path)) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(tmppath);
+            Err(e)
+        }
+    }
 }
@@ -0,0 +1,260 @@
+use crate::assembly::{AsmError, AsmWarning};
+
+/// Language of the diagnostics students read, since the course is taught in
+/// English and French.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Lang {
+    #[default]
+    En,
+    Fr,
+}
+
+/// What went wrong with a line, in `lang`.
+pub fn asm_error(e: &AsmError, lang: Lang) -> String {
+    match lang {
+        Lang::En => match e {
+            AsmError::InvalidMnenomic(x) => format!("Invalid mnemonic \"{x}\"."),
+            AsmError::MissingImmediate => "Missing an immediate.".to_string(),
+            AsmError::InvalidImmediate(x) => format!("Invalid immediate \"{x}\"."),
+            AsmError::ImmediateOutOfRange(x) => format!("Immediate {x} is out of range."),
+            AsmError::MissingRegister => "Missing a register.".to_string(),
+            AsmError::InvalidRegister(x) => format!("Invalid register \"{x}\"."),
+            AsmError::ExtraToken(x) => format!("Unexpected token \"{x}\"."),
+            AsmError::InvalidUtf8 => "Line is not valid UTF-8.".to_string(),
+            AsmError::ExtensionDisabled(x, ext) => {
+                format!("\"{x}\" requires the {ext} extension.")
+            }
+            AsmError::InvalidPseudo(x) => {
+                format!("Invalid pseudo-instruction definition \"{x}\".")
+            }
+            AsmError::PseudoOperandCount(x, expected, found) => {
+                format!("\"{x}\" takes {expected} operands, found {found}.")
+            }
+            AsmError::InvalidEntry(x) => format!("Can't start the program at \"{x}\"."),
+            AsmError::DuplicateEntry => "The entry point is already set.".to_string(),
+            AsmError::DuplicateSymbol(x) => format!("Symbol \"{x}\" is declared twice."),
+            AsmError::NotData(x) => format!("\"{x}\" can't be used in the .data section."),
+            AsmError::MissingHash(x) => format!("Immediate \"{x}\" must start with #."),
+            AsmError::MissingComma => "Operands must be separated by commas.".to_string(),
+            AsmError::BranchOutOfRange(target, offset) => {
+                format!("Can't branch to {target}, {offset} instructions away.")
+            }
+            AsmError::Deprecated(old, new) => format!("\"{old}\" is deprecated, use \"{new}\"."),
+            AsmError::InvalidLabel(x) => format!("Invalid label \"{x}\"."),
+            AsmError::UndefinedConstant(x) => format!("Constant \"{x}\" is not defined."),
+            AsmError::InvalidConstant(x) => format!("Invalid constant \"{x}\"."),
+            AsmError::OrgOverlap(target, address) => {
+                format!("Can't move back to address {target}, already at {address}.")
+            }
+            AsmError::NotInstruction(x) => {
+                format!("{x} isn't an instruction, so can't be placed in program memory.")
+            }
+            AsmError::UnterminatedMacro(x) => format!("Macro \"{x}\" has no .endm."),
+            AsmError::UnterminatedRept => ".rept has no .endr.".to_string(),
+            AsmError::UnterminatedIf => "Conditional block has no .endif.".to_string(),
+            AsmError::UnmatchedDirective(x) => format!("{x} has no .if before it."),
+            AsmError::InvalidCondition(x) => format!("Invalid condition \"{x}\"."),
+            AsmError::InvalidExpression(x) => format!("Can't evaluate \"{x}\"."),
+            AsmError::InvalidAlias(x) => format!("Invalid register alias \"{x}\"."),
+            AsmError::InvalidAlignment(n) => format!("Can't align to {n}, not a power of two."),
+            AsmError::Redefined(x, line) => format!("\"{x}\" is already defined on line {line}."),
+            AsmError::PastDepth(words, depth) => {
+                format!("Needs {words} words of memory, more than its depth of {depth}.")
+            }
+            AsmError::UndefinedSymbol(x) => {
+                format!("\"{x}\" is not defined as a label or a constant.")
+            }
+            AsmError::UnknownWarning(x) if x.is_empty() => {
+                format!(
+                    "Missing a warning, one of {}.",
+                    AsmWarning::NAMES.join(", ")
+                )
+            }
+            AsmError::UnknownWarning(x) => {
+                format!(
+                    "Unknown warning \"{x}\", expected one of {}.",
+                    AsmWarning::NAMES.join(", ")
+                )
+            }
+            AsmError::InMacro(e, name, line) => {
+                format!(
+                    "{} In macro \"{name}\" from line {line}.",
+                    asm_error(e, lang)
+                )
+            }
+            AsmError::InStatement(e, n) => format!("{} In statement {n}.", asm_error(e, lang)),
+            AsmError::InOperand(e, _) => asm_error(e, lang),
+        },
+        Lang::Fr => match e {
+            AsmError::InvalidMnenomic(x) => format!("Mnémonique « {x} » invalide."),
+            AsmError::MissingImmediate => "Il manque une valeur immédiate.".to_string(),
+            AsmError::InvalidImmediate(x) => format!("Valeur immédiate « {x} » invalide."),
+            AsmError::ImmediateOutOfRange(x) => {
+                format!("La valeur immédiate {x} est hors limites.")
+            }
+            AsmError::MissingRegister => "Il manque un registre.".to_string(),
+            AsmError::InvalidRegister(x) => format!("Registre « {x} » invalide."),
+            AsmError::ExtraToken(x) => format!("Élément « {x} » inattendu."),
+            AsmError::InvalidUtf8 => "La ligne n'est pas en UTF-8 valide.".to_string(),
+            AsmError::ExtensionDisabled(x, ext) => {
+                format!("« {x} » nécessite l'extension {ext}.")
+            }
+            AsmError::InvalidPseudo(x) => {
+                format!("Définition de pseudo-instruction « {x} » invalide.")
+            }
+            AsmError::PseudoOperandCount(x, expected, found) => {
+                format!("« {x} » prend {expected} opérandes, {found} donnés.")
+            }
+            AsmError::InvalidEntry(x) => {
+                format!("Impossible de démarrer le programme à « {x} ».")
+            }
+            AsmError::DuplicateEntry => "Le point d'entrée est déjà défini.".to_string(),
+            AsmError::DuplicateSymbol(x) => format!("Le symbole « {x} » est déclaré deux fois."),
+            AsmError::NotData(x) => {
+                format!("« {x} » ne peut pas être utilisé dans la section .data.")
+            }
+            AsmError::MissingHash(x) => {
+                format!("La valeur immédiate « {x} » doit commencer par #.")
+            }
+            AsmError::MissingComma => {
+                "Les opérandes doivent être séparés par des virgules.".to_string()
+            }
+            AsmError::BranchOutOfRange(target, offset) => {
+                format!("Impossible de brancher vers {target}, à {offset} instructions.")
+            }
+            AsmError::Deprecated(old, new) => {
+                format!("« {old} » est obsolète, utilisez « {new} ».")
+            }
+            AsmError::InvalidLabel(x) => format!("Étiquette « {x} » invalide."),
+            AsmError::UndefinedConstant(x) => format!("La constante « {x} » n'est pas définie."),
+            AsmError::InvalidConstant(x) => format!("Constante « {x} » invalide."),
+            AsmError::OrgOverlap(target, address) => {
+                format!("Impossible de revenir à l'adresse {target}, déjà à {address}.")
+            }
+            AsmError::NotInstruction(x) => {
+                format!("{x} n'est pas une instruction et ne peut pas aller en mémoire programme.")
+            }
+            AsmError::UnterminatedMacro(x) => format!("La macro « {x} » n'a pas de .endm."),
+            AsmError::UnterminatedRept => ".rept n'a pas de .endr.".to_string(),
+            AsmError::UnterminatedIf => "Le bloc conditionnel n'a pas de .endif.".to_string(),
+            AsmError::UnmatchedDirective(x) => format!("{x} n'a pas de .if avant lui."),
+            AsmError::InvalidCondition(x) => format!("Condition « {x} » invalide."),
+            AsmError::InvalidExpression(x) => format!("Impossible d'évaluer « {x} »."),
+            AsmError::InvalidAlias(x) => format!("Alias de registre « {x} » invalide."),
+            AsmError::InvalidAlignment(n) => {
+                format!("Impossible d'aligner sur {n}, qui n'est pas une puissance de deux.")
+            }
+            AsmError::Redefined(x, line) => format!("« {x} » est déjà défini à la ligne {line}."),
+            AsmError::PastDepth(words, depth) => {
+                format!("Nécessite {words} mots de mémoire, plus que sa profondeur de {depth}.")
+            }
+            AsmError::UndefinedSymbol(x) => {
+                format!("« {x} » n'est défini ni comme étiquette ni comme constante.")
+            }
+            AsmError::UnknownWarning(x) if x.is_empty() => {
+                format!(
+                    "Avertissement manquant, parmi {}.",
+                    AsmWarning::NAMES.join(", ")
+                )
+            }
+            AsmError::UnknownWarning(x) => format!(
+                "Avertissement « {x} » inconnu, parmi {} attendus.",
+                AsmWarning::NAMES.join(", ")
+            ),
+            AsmError::InMacro(e, name, line) => {
+                format!(
+                    "{} Dans la macro « {name} », ligne {line}.",
+                    asm_error(e, lang)
+                )
+            }
+            AsmError::InStatement(e, n) => format!("{} Dans l'énoncé {n}.", asm_error(e, lang)),
+            AsmError::InOperand(e, _) => asm_error(e, lang),
+        },
+    }
+}
+
+/// What's suspicious about a line, in `lang`.
+pub fn asm_warning(w: &AsmWarning, lang: Lang) -> String {
+    match w {
+        AsmWarning::Deprecated(old, new) => {
+            asm_error(&AsmError::Deprecated(old.clone(), new.clone()), lang)
+        }
+        AsmWarning::NoEffect(instr) => match lang {
+            Lang::En => format!("\"{instr}\" has no effect."),
+            Lang::Fr => format!("« {instr} » n'a aucun effet."),
+        },
+    }
+}
+
+/// An error with its line and code, e.g.
+/// `Line 2: error[E0001]: Invalid mnemonic "SUB".`
+pub fn line_error(line: usize, code: &str, message: &str, lang: Lang) -> String {
+    match lang {
+        Lang::En => format!("Line {line}: error[{code}]: {message}"),
+        Lang::Fr => format!("Ligne {line} : erreur[{code}] : {message}"),
+    }
+}
+
+/// An error with its line and column, e.g.
+/// `Line 3, column 9: error[E0003]: Invalid immediate "x".`
+pub fn column_error(line: usize, column: usize, code: &str, message: &str, lang: Lang) -> String {
+    match lang {
+        Lang::En => format!("Line {line}, column {column}: error[{code}]: {message}"),
+        Lang::Fr => format!("Ligne {line}, colonne {column} : erreur[{code}] : {message}"),
+    }
+}
+
+/// A warning with its line, e.g.
+/// `Line 4: warning: "SRH0" is deprecated, use "SRH".`
+pub fn line_warning(line: usize, message: &str, lang: Lang) -> String {
+    match lang {
+        Lang::En => format!("Line {line}: warning: {message}"),
+        Lang::Fr => format!("Ligne {line} : avertissement : {message}"),
+    }
+}
+
+/// A warning reported as an error because warnings are denied, e.g.
+/// `Line 4: error: "SUBI r0, 0" has no effect.`
+pub fn line_denied(line: usize, message: &str, lang: Lang) -> String {
+    match lang {
+        Lang::En => format!("Line {line}: error: {message}"),
+        Lang::Fr => format!("Ligne {line} : erreur : {message}"),
+    }
+}
+
+/// The most errors printed for one input, after which [`more_errors`]
+/// counts the rest, so a badly broken file doesn't bury the first ones.
+pub const ERROR_LIMIT: usize = 50;
+
+/// The line after the first [`ERROR_LIMIT`] errors, counting the `n` that
+/// weren't printed.
+pub fn more_errors(n: usize, lang: Lang) -> String {
+    match (lang, n) {
+        (Lang::En, 1) => "... and 1 more error.".to_string(),
+        (Lang::En, n) => format!("... and {n} more errors."),
+        (Lang::Fr, 1) => "... et 1 autre erreur.".to_string(),
+        (Lang::Fr, n) => format!("... et {n} autres erreurs."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog() {
+        let e = AsmError::InvalidMnenomic("SUB".to_string());
+        assert_eq!(asm_error(&e, Lang::En), e.to_string());
+        assert_eq!(
+            line_error(2, e.code(), &asm_error(&e, Lang::Fr), Lang::Fr),
+            "Ligne 2 : erreur[E0001] : Mnémonique « SUB » invalide."
+        );
+    }
+
+    #[test]
+    fn test_more_errors() {
+        assert_eq!(more_errors(1, Lang::En), "... and 1 more error.");
+        assert_eq!(more_errors(12, Lang::En), "... and 12 more errors.");
+        assert_eq!(more_errors(12, Lang::Fr), "... et 12 autres erreurs.");
+    }
+}
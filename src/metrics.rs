@@ -0,0 +1,177 @@
+use std::fmt::Display;
+
+use crate::op::{Op, Operand};
+use crate::program::Program;
+use crate::reg::Reg;
+
+/// Structural measures of a program, from [`Program::metrics`].
+#[derive(Debug, PartialEq)]
+pub struct Metrics {
+    pub instructions: usize,
+    /// Runs of instructions entered only at the top and left only at the
+    /// bottom.
+    pub basic_blocks: usize,
+    pub max_block_len: usize,
+    pub branches: usize,
+    /// How often each register is an operand, by register number.
+    pub register_uses: [usize; Reg::COUNT],
+    /// Clock cycles for one pass through the program on a single-cycle CPU:
+    /// from address 0, taking forward `BR`s, never taking `BRZ`, and
+    /// stopping at the first branch back. `PAUSE` delays are not counted.
+    pub static_cycles: usize,
+}
+
+impl Metrics {
+    /// Branches per instruction.
+    pub fn branch_density(&self) -> f64 {
+        match self.instructions {
+            0 => 0.0,
+            n => self.branches as f64 / n as f64,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let uses: Vec<String> = Reg::iter()
+            .map(|r| format!("\"{r}\": {}", self.register_uses[r as usize]))
+            .collect();
+        format!(
+            "{{\"instructions\": {}, \"basic_blocks\": {}, \"max_block_len\": {}, \
+             \"branches\": {}, \"branch_density\": {:.3}, \"register_uses\": {{{}}}, \
+             \"static_cycles\": {}}}",
+            self.instructions,
+            self.basic_blocks,
+            self.max_block_len,
+            self.branches,
+            self.branch_density(),
+            uses.join(", "),
+            self.static_cycles
+        )
+    }
+}
+
+impl Display for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let uses: Vec<String> = Reg::iter()
+            .map(|r| format!("{r} {}", self.register_uses[r as usize]))
+            .collect();
+        writeln!(f, "Instructions: {}", self.instructions)?;
+        writeln!(
+            f,
+            "Basic blocks: {}, longest {} instructions",
+            self.basic_blocks, self.max_block_len
+        )?;
+        writeln!(
+            f,
+            "Branches: {} ({:.1}% of instructions)",
+            self.branches,
+            self.branch_density() * 100.0
+        )?;
+        writeln!(f, "Register uses: {}", uses.join(", "))?;
+        writeln!(f, "Static cycles: {}", self.static_cycles)
+    }
+}
+
+impl Program {
+    pub fn metrics(&self) -> Metrics {
+        let len = self.ops.len();
+        let offsets: Vec<Option<i32>> = self.ops.iter().map(Op::branch_offset).collect();
+
+        // A block starts at address 0, at each branch target and after each
+        // branch.
+        let mut leaders = vec![false; len];
+        for (addr, offset) in offsets.iter().enumerate() {
+            let Some(offset) = offset else {
+                continue;
+            };
+            let target = addr as i64 + *offset as i64;
+            if (0..len as i64).contains(&target) {
+                leaders[target as usize] = true;
+            }
+            if addr + 1 < len {
+                leaders[addr + 1] = true;
+            }
+        }
+        if len > 0 {
+            leaders[0] = true;
+        }
+        let starts: Vec<usize> = (0..len).filter(|a| leaders[*a]).collect();
+        let max_block_len = starts
+            .iter()
+            .zip(starts.iter().skip(1).chain([&len]))
+            .map(|(start, end)| end - start)
+            .max()
+            .unwrap_or(0);
+
+        let mut register_uses = [0; Reg::COUNT];
+        for operand in self.ops.iter().flat_map(Op::operands) {
+            if let Operand::Reg { reg, .. } = operand {
+                register_uses[reg as usize] += 1;
+            }
+        }
+
+        Metrics {
+            instructions: len,
+            basic_blocks: starts.len(),
+            max_block_len,
+            branches: offsets.iter().flatten().count(),
+            register_uses,
+            static_cycles: self.static_cycles(),
+        }
+    }
+
+    /// See [`Metrics::static_cycles`].
+    fn static_cycles(&self) -> usize {
+        let mut cycles = 0;
+        let mut pc = 0;
+        while let Some(op) = self.ops.get(pc) {
+            cycles += 1;
+            pc = match op {
+                Op::BR(i) if i.get() > 0 => pc + i.get() as usize,
+                Op::BR(_) => break,
+                _ => pc + 1,
+            };
+        }
+        cycles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        // CLR r0, BRZ 2, MOV r1 r0, ADDI r0 1, BR -3, PAUSE
+        let code = [
+            0b011000_00,
+            0b101_00010,
+            0b0111_01_00,
+            0b000_001_00,
+            0b100_11101,
+            0xff,
+        ];
+        let program = Program {
+            ops: code.iter().map(|c| Op::try_from(*c).unwrap()).collect(),
+            data: vec![],
+        };
+        let m = program.metrics();
+        assert_eq!(m.instructions, 6);
+        // [CLR], [BRZ], [MOV], [ADDI, BR], [PAUSE]
+        assert_eq!(m.basic_blocks, 5);
+        assert_eq!(m.max_block_len, 2);
+        assert_eq!(m.branches, 2);
+        assert!((m.branch_density() - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(m.register_uses, [3, 1, 0, 0]);
+        assert!(m
+            .to_json()
+            .contains("\"register_uses\": {\"r0\": 3, \"r1\": 1, \"r2\": 0, \"r3\": 0}"));
+        assert_eq!(m.static_cycles, 5);
+
+        let empty = Program {
+            ops: vec![],
+            data: vec![],
+        };
+        assert_eq!(empty.metrics().basic_blocks, 0);
+        assert_eq!(empty.metrics().max_block_len, 0);
+    }
+}
@@ -0,0 +1,336 @@
+use std::fmt::Display;
+
+use crate::{
+    imm::I5,
+    instruction_set::InstructionSet,
+    op::{Isa, Op},
+    word::Word,
+};
+
+/// A separately assembled module, for combining with others by [`link`].
+///
+/// Written as text, one record per line:
+///
+/// ```text
+/// width 8
+/// code 11000001
+/// global <name> <index>
+/// reloc <index> <name>
+/// ```
+///
+/// `code` lines are the instruction words in order. A `global` exports the
+/// instruction at `index` under `name`, and a `reloc` asks the linker to point
+/// the branch at `index` to the instruction exported as `name`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Object {
+    pub width: u8,
+    pub code: Vec<u32>,
+    pub symbols: Symbols,
+}
+
+/// Instructions a module exports by name, and instructions that refer to
+/// names, by index into the module's code.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Symbols {
+    pub globals: Vec<(String, usize)>,
+    pub relocations: Vec<(usize, String)>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ObjectError {
+    InvalidRecord(usize, String),
+    MissingWidth,
+}
+
+impl Display for ObjectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjectError::InvalidRecord(n, x) => write!(f, "Line {n}: Invalid record \"{x}\"."),
+            ObjectError::MissingWidth => write!(f, "Object has no width record."),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LinkError {
+    UnsupportedWidth(u8),
+    UndefinedSymbol(String),
+    DuplicateSymbol(String),
+    OutOfRange(String, i32),
+    NotABranch(usize),
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkError::UnsupportedWidth(w) => {
+                write!(f, "Can't link {w}-bit objects, only 8-bit.")
+            }
+            LinkError::UndefinedSymbol(s) => write!(f, "Symbol \"{s}\" is not defined."),
+            LinkError::DuplicateSymbol(s) => write!(f, "Symbol \"{s}\" is defined twice."),
+            LinkError::OutOfRange(s, d) => {
+                write!(f, "Branch to \"{s}\" is {d} instructions, out of range.")
+            }
+            LinkError::NotABranch(n) => {
+                write!(f, "Instruction {n} is relocated but is not a branch.")
+            }
+        }
+    }
+}
+
+impl Object {
+    pub fn parse(s: &str) -> Result<Object, ObjectError> {
+        let mut obj = Object::default();
+
+        for (n, line) in s.lines().enumerate() {
+            let invalid = || ObjectError::InvalidRecord(n + 1, line.to_string());
+            let record = line.split(';').next().unwrap_or_default();
+            let fields: Vec<&str> = record.split_whitespace().collect();
+            match fields[..] {
+                [] => {}
+                ["width", w] => obj.width = w.parse().map_err(|_| invalid())?,
+                ["code", word] => {
+                    let word = u32::from_str_radix(word, 2).map_err(|_| invalid())?;
+                    obj.code.push(word);
+                }
+                ["global", name, index] => {
+                    let index = index.parse().map_err(|_| invalid())?;
+                    obj.symbols.globals.push((name.to_string(), index));
+                }
+                ["reloc", index, name] => {
+                    let index = index.parse().map_err(|_| invalid())?;
+                    obj.symbols.relocations.push((index, name.to_string()));
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        match obj.width {
+            0 => Err(ObjectError::MissingWidth),
+            _ => Ok(obj),
+        }
+    }
+}
+
+impl Display for Object {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "width {}", self.width)?;
+        for word in &self.code {
+            writeln!(f, "code {:0width$b}", word, width = self.width as usize)?;
+        }
+        for (name, index) in &self.symbols.globals {
+            writeln!(f, "global {name} {index}")?;
+        }
+        for (index, name) in &self.symbols.relocations {
+            writeln!(f, "reloc {index} {name}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Read a symbol file listing one `<name> <address>` per line, with
+/// addresses in decimal or with a `0x` prefix and `;` starting a comment.
+pub fn parse_symbol_file(s: &str) -> Result<Vec<(String, usize)>, ObjectError> {
+    let mut symbols = vec![];
+    for (n, line) in s.lines().enumerate() {
+        let invalid = || ObjectError::InvalidRecord(n + 1, line.to_string());
+        let record = line.split(';').next().unwrap_or_default();
+        let fields: Vec<&str> = record.split_whitespace().collect();
+        match fields[..] {
+            [] => {}
+            [name, addr] => {
+                let addr = match addr.strip_prefix("0x") {
+                    Some(hex) => usize::from_str_radix(hex, 16),
+                    None => addr.parse(),
+                };
+                symbols.push((name.to_string(), addr.map_err(|_| invalid())?));
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(symbols)
+}
+
+/// Place `objects` one after another and resolve branches between them,
+/// returning the combined machine code.
+pub fn link(objects: &[Object]) -> Result<Vec<u8>, Vec<LinkError>> {
+    let mut errors = vec![];
+
+    if let Some(obj) = objects.iter().find(|o| o.width != 8) {
+        return Err(vec![LinkError::UnsupportedWidth(obj.width)]);
+    }
+
+    // address of each module's first instruction
+    let bases: Vec<usize> = objects
+        .iter()
+        .scan(0, |base, o| {
+            let start = *base;
+            *base += o.code.len();
+            Some(start)
+        })
+        .collect();
+
+    let mut symbols: Vec<(&str, usize)> = vec![];
+    for (obj, base) in objects.iter().zip(&bases) {
+        for (name, index) in &obj.symbols.globals {
+            match symbols.iter().any(|(s, _)| s == name) {
+                true => errors.push(LinkError::DuplicateSymbol(name.clone())),
+                false => symbols.push((name, base + index)),
+            }
+        }
+    }
+
+    let mut code: Vec<u8> = objects
+        .iter()
+        .flat_map(|o| o.code.iter().map(|w| *w as u8))
+        .collect();
+
+    for (obj, base) in objects.iter().zip(&bases) {
+        for (index, name) in &obj.symbols.relocations {
+            let Some((_, target)) = symbols.iter().find(|(s, _)| s == name) else {
+                errors.push(LinkError::UndefinedSymbol(name.clone()));
+                continue;
+            };
+            let address = base + index;
+            let offset = *target as i32 - address as i32;
+            let imm = i8::try_from(offset).ok().and_then(I5::new);
+
+            // branches are in the base instruction set
+            let op = code.get(address).map(|w| Op::decode(*w, &Isa::default()));
+            match (op, imm) {
+                (Some(Ok(Op::BR(_))), Some(imm)) => code[address] = Op::BR(imm).to_binary(),
+                (Some(Ok(Op::BRZ(_))), Some(imm)) => code[address] = Op::BRZ(imm).to_binary(),
+                (Some(Ok(Op::BR(_) | Op::BRZ(_))), None) => {
+                    errors.push(LinkError::OutOfRange(name.clone(), offset))
+                }
+                _ => errors.push(LinkError::NotABranch(address)),
+            }
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(code),
+        false => Err(errors),
+    }
+}
+
+/// Resolve a module's references to its own symbols, as if it were linked
+/// on its own.
+pub fn resolve<W: Word, I: InstructionSet<W>>(
+    isa: &I,
+    instrs: Vec<I::Instr>,
+    symbols: Symbols,
+) -> Result<Vec<I::Instr>, Vec<LinkError>> {
+    if symbols.relocations.is_empty() {
+        return Ok(instrs);
+    }
+
+    let obj = Object {
+        width: isa.width(),
+        code: instrs.iter().map(|i| isa.encode(i).into()).collect(),
+        symbols,
+    };
+    let code = link(&[obj])?;
+    code.into_iter()
+        .enumerate()
+        .map(|(address, w)| {
+            isa.decode(W::truncate(w as u32))
+                .map_err(|_| vec![LinkError::NotABranch(address)])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(code: &[u8], globals: &[(&str, usize)], relocations: &[(usize, &str)]) -> Object {
+        Object {
+            width: 8,
+            code: code.iter().map(|w| *w as u32).collect(),
+            symbols: Symbols {
+                globals: globals.iter().map(|(s, n)| (s.to_string(), *n)).collect(),
+                relocations: relocations
+                    .iter()
+                    .map(|(n, s)| (*n, s.to_string()))
+                    .collect(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let obj = object(&[0b100_00000, 0xff], &[("start", 1)], &[(0, "loop")]);
+        let text = obj.to_string();
+        assert_eq!(
+            text,
+            "width 8\ncode 10000000\ncode 11111111\nglobal start 1\nreloc 0 loop\n"
+        );
+        assert_eq!(Object::parse(&text), Ok(obj));
+        assert_eq!(
+            Object::parse("width 8\ncode 2"),
+            Err(ObjectError::InvalidRecord(2, "code 2".to_string()))
+        );
+        assert_eq!(Object::parse("code 0"), Err(ObjectError::MissingWidth));
+    }
+
+    #[test]
+    fn test_symbol_file() {
+        assert_eq!(
+            parse_symbol_file("main 0\n; comment\n\nloop 0x1f ; top of loop"),
+            Ok(vec![("main".to_string(), 0), ("loop".to_string(), 31)])
+        );
+        assert_eq!(
+            parse_symbol_file("loop"),
+            Err(ObjectError::InvalidRecord(1, "loop".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_link() {
+        // main branches forward into lib, lib branches back into main
+        let main = object(&[0b011000_00, 0b100_00000], &[("main", 0)], &[(1, "wait")]);
+        let lib = object(&[0xff, 0b101_00000], &[("wait", 0)], &[(1, "main")]);
+        assert_eq!(
+            link(&[main.clone(), lib.clone()]),
+            Ok(vec![0b011000_00, 0b100_00001, 0xff, 0b101_11101])
+        );
+
+        let errors = link(&[main.clone(), main.clone()]).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                LinkError::DuplicateSymbol("main".to_string()),
+                LinkError::UndefinedSymbol("wait".to_string()),
+                LinkError::UndefinedSymbol("wait".to_string()),
+            ]
+        );
+
+        let far = object(&[0xff; 20], &[], &[]);
+        let errors = link(&[main, far, lib]).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                LinkError::OutOfRange("wait".to_string(), 21),
+                LinkError::OutOfRange("main".to_string(), -23),
+            ]
+        );
+
+        let bad = object(&[0xff], &[("x", 0)], &[(0, "x")]);
+        assert_eq!(link(&[bad]), Err(vec![LinkError::NotABranch(0)]));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let isa = Isa::default();
+        let ops = vec![Op::PAUSE, Op::BR(I5::new(0).unwrap())];
+        let symbols = Symbols {
+            globals: vec![("top".to_string(), 0)],
+            relocations: vec![(1, "top".to_string())],
+        };
+        assert_eq!(
+            resolve(&isa, ops, symbols),
+            Ok(vec![Op::PAUSE, Op::BR(I5::new(-1).unwrap())])
+        );
+    }
+}
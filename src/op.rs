@@ -1,8 +1,11 @@
 use std::fmt::Display;
 
-use crate::imm::{I5, U3, U4};
+use crate::encoding::{deposit, gather, pattern_mask};
+use crate::imm::{Imm, I5, U3, U4};
 use crate::reg::Reg;
 
+// variants are named after the assembly mnemonics
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq)]
 pub enum Op {
     BR(I5),
@@ -17,46 +20,237 @@ pub enum Op {
     MOVR(Reg),
     MOVRHS(Reg),
     PAUSE,
+    // Logic extension
+    AND(Reg),
+    OR(Reg),
+    XOR(Reg),
+    NOT(Reg),
+    // Data memory extension
+    LD(Reg, Reg),
+    ST(Reg, Reg),
+    // I/O extension
+    IN(Reg),
+    OUT(Reg),
+    // Shift extension
+    SHL(Reg),
+    SHR(Reg),
+    ADD(Reg, Reg),
+}
+
+/// Optional groups of instructions on top of the base instruction set, for
+/// CPUs that implement more than the base design.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Extension {
+    /// `AND`, `OR` and `XOR` of r0 with a register into r0, and `NOT` of a
+    /// register in place.
+    Logic,
+    /// `LD` and `ST` between registers and a data memory addressed by a
+    /// register.
+    Mem,
+    /// `IN` from the switches and `OUT` to the LEDs and 7-segment display.
+    Io,
+    /// `SHL` and `SHR` of a register by one bit, and `ADD` of two registers.
+    /// Reuses opcodes of the logic and mem extensions.
+    Shift,
+}
+
+/// What an instruction does, for passes that treat similar instructions
+/// alike, see [`crate::program::Visitor`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Category {
+    /// `BR` and `BRZ`.
+    Branch,
+    /// Instructions that only change registers.
+    Alu,
+    /// Moving the stepper motor, and `PAUSE` between steps.
+    Motor,
+    /// `LD` and `ST`.
+    Memory,
+    /// `IN` and `OUT`.
+    Io,
+}
+
+impl Display for Extension {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Extension::Logic => write!(f, "logic"),
+            Extension::Mem => write!(f, "mem"),
+            Extension::Io => write!(f, "io"),
+            Extension::Shift => write!(f, "shift"),
+        }
+    }
+}
+
+impl Extension {
+    /// Do the two extensions assign any of the same opcodes?
+    pub fn conflicts_with(self, other: Extension) -> bool {
+        let layouts = |ext| {
+            LAYOUTS
+                .iter()
+                .filter(move |(_, e)| *e == Some(ext))
+                .map(|(l, _)| *l)
+        };
+        self != other && layouts(self).any(|a| layouts(other).any(|b| layouts_overlap(a, b)))
+    }
+}
+
+/// Can some opcode match both layouts?
+fn layouts_overlap(a: &str, b: &str) -> bool {
+    let fixed = |l| pattern_mask(l, '0') | pattern_mask(l, '1');
+    let common = fixed(a) & fixed(b);
+    (pattern_mask(a, '1') ^ pattern_mask(b, '1')) & common == 0
+}
+
+/// The base instruction set plus any enabled extensions.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Isa {
+    extensions: Vec<Extension>,
+}
+
+impl Display for Isa {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "base")?;
+        for ext in &self.extensions {
+            write!(f, "+{ext}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Isa {
+    pub fn with(mut self, ext: Extension) -> Isa {
+        if !self.has(ext) {
+            self.extensions.push(ext);
+        }
+        self
+    }
+
+    pub fn has(&self, ext: Extension) -> bool {
+        self.extensions.contains(&ext)
+    }
+
+    /// A pair of enabled extensions that can't be used together, if any.
+    pub fn conflict(&self) -> Option<(Extension, Extension)> {
+        self.extensions.iter().enumerate().find_map(|(n, a)| {
+            self.extensions[n + 1..]
+                .iter()
+                .find(|b| a.conflicts_with(**b))
+                .map(|b| (*a, *b))
+        })
+    }
+
+    /// Mnemonics of the base instructions and the enabled extensions.
+    pub fn mnemonics(&self) -> Vec<&'static str> {
+        MNEMONICS
+            .iter()
+            .filter(|(_, ext)| ext.is_none_or(|e| self.has(e)))
+            .map(|(m, _)| *m)
+            .collect()
+    }
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Op::BR(imm) => write!(f, "BR {}", imm.get()),
+            Op::BRZ(imm) => write!(f, "BRZ {}", imm.get()),
+            Op::ADDI(reg, imm) => write!(f, "ADDI {reg}, {}", imm.get()),
+            Op::SUBI(reg, imm) => write!(f, "SUBI {reg}, {}", imm.get()),
+            Op::SR0(imm) => write!(f, "SR0 {}", imm.get()),
+            Op::SRH0(imm) => write!(f, "SRH0 {}", imm.get()),
+            Op::CLR(reg) => write!(f, "CLR {reg}"),
+            Op::MOV(regd, regs) => write!(f, "MOV {regd}, {regs}"),
+            Op::MOVA(reg) => write!(f, "MOVA {reg}"),
+            Op::MOVR(reg) => write!(f, "MOVR {reg}"),
+            Op::MOVRHS(reg) => write!(f, "MOVRHS {reg}"),
+            Op::PAUSE => write!(f, "PAUSE"),
+            Op::AND(reg) => write!(f, "AND {reg}"),
+            Op::OR(reg) => write!(f, "OR {reg}"),
+            Op::XOR(reg) => write!(f, "XOR {reg}"),
+            Op::NOT(reg) => write!(f, "NOT {reg}"),
+            Op::LD(regd, rega) => write!(f, "LD {regd}, {rega}"),
+            Op::ST(regs, rega) => write!(f, "ST {regs}, {rega}"),
+            Op::IN(reg) => write!(f, "IN {reg}"),
+            Op::OUT(reg) => write!(f, "OUT {reg}"),
+            Op::SHL(reg) => write!(f, "SHL {reg}"),
+            Op::SHR(reg) => write!(f, "SHR {reg}"),
+            Op::ADD(regd, regs) => write!(f, "ADD {regd}, {regs}"),
+        }
+    }
 }
 
 impl Op {
-    pub fn to_string(&self) -> String {
+    /// Does the instruction do nothing? Adding or subtracting 0, moving a
+    /// register to itself and branching to the next instruction are all
+    /// no-ops.
+    pub fn is_nop(&self) -> bool {
         match self {
-            Op::BR(imm) => format!("BR {}", imm.get()),
-            Op::BRZ(imm) => format!("BRZ {}", imm.get()),
-            Op::ADDI(reg, imm) => format!("ADDI {reg}, {}", imm.get()),
-            Op::SUBI(reg, imm) => format!("SUBI {reg}, {}", imm.get()),
-            Op::SR0(imm) => format!("SR0 {}", imm.get()),
-            Op::SRH0(imm) => format!("SRH0 {}", imm.get()),
-            Op::CLR(reg) => format!("CLR {reg}"),
-            Op::MOV(regd, regs) => format!("MOV {regd}, {regs}"),
-            Op::MOVA(reg) => format!("MOVA {reg}"),
-            Op::MOVR(reg) => format!("MOVR {reg}"),
-            Op::MOVRHS(reg) => format!("MOVRHS {reg}"),
-            Op::PAUSE => format!("PAUSE"),
-        }
-    }
-
-    pub fn to_binary(&self) -> u8 {
+            Op::ADDI(_, i) | Op::SUBI(_, i) => i.get() == 0,
+            Op::MOV(d, s) => d == s,
+            Op::BR(i) | Op::BRZ(i) => i.get() == 1,
+            _ => false,
+        }
+    }
+
+    pub fn category(&self) -> Category {
         match self {
-            Op::BR(imm) => 0x80 | (imm.get() & 0x1f) as u8,
-            Op::BRZ(imm) => 0xA0 | (imm.get() & 0x1f) as u8,
-            Op::ADDI(reg, imm) => 0x00 | (imm.get() << 2) | *reg as u8,
-            Op::SUBI(reg, imm) => 0x20 | (imm.get() << 2) | *reg as u8,
-            Op::SR0(imm) => 0x40 | imm.get(),
-            Op::SRH0(imm) => 0x50 | imm.get(),
-            Op::CLR(reg) => 0x60 | *reg as u8,
-            Op::MOV(regd, regs) => 0x70 | (*regd as u8) << 2 | *regs as u8,
-            Op::MOVA(reg) => 0xc0 | *reg as u8,
-            Op::MOVR(reg) => 0xc4 | *reg as u8,
-            Op::MOVRHS(reg) => 0xc8 | *reg as u8,
-            Op::PAUSE => 0xff,
+            Op::BR(_) | Op::BRZ(_) => Category::Branch,
+            Op::MOVA(_) | Op::MOVR(_) | Op::MOVRHS(_) | Op::PAUSE => Category::Motor,
+            Op::LD(..) | Op::ST(..) => Category::Memory,
+            Op::IN(_) | Op::OUT(_) => Category::Io,
+            _ => Category::Alu,
         }
     }
+
+    /// How far a branch jumps from its own address, `None` if not a branch.
+    pub fn branch_offset(&self) -> Option<i32> {
+        match self {
+            Op::BR(i) | Op::BRZ(i) => Some(i.get() as i32),
+            _ => None,
+        }
+    }
+
+    /// The same instruction with its registers renamed by `map`, pairs of
+    /// register numbers `(from, to)` applied all at once, so `[(1, 3), (3, 1)]`
+    /// swaps r1 and r3. `None` if a register maps to one that doesn't exist.
+    pub fn remap_registers(&self, map: &[(u8, u8)]) -> Option<Op> {
+        let operands = self
+            .operands()
+            .into_iter()
+            .map(|operand| match operand {
+                Operand::Reg { field, reg } => {
+                    let to = map.iter().find(|(from, _)| *from == reg as u8);
+                    let reg = match to {
+                        Some((_, to)) => Reg::try_from(*to).ok()?,
+                        None => reg,
+                    };
+                    Some(Operand::Reg { field, reg })
+                }
+                imm => Some(imm),
+            })
+            .collect::<Option<Vec<Operand>>>()?;
+        self.with_operands(&operands)
+    }
+
+    /// The extension that provides a mnemonic, `None` for base instructions
+    /// and unknown mnemonics.
+    pub fn extension_of(mnemonic: &str) -> Option<Extension> {
+        MNEMONICS
+            .iter()
+            .find(|(m, _)| *m == mnemonic)
+            .and_then(|(_, ext)| *ext)
+    }
 }
 
 #[derive(Debug)]
-pub struct InvalidOpcode(u8);
+pub struct InvalidOpcode(pub u32);
+
+impl InvalidOpcode {
+    /// Stable code identifying the error, see `asp explain-error`.
+    pub fn code(&self) -> &'static str {
+        "E0009"
+    }
+}
 
 impl Display for InvalidOpcode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -64,52 +258,379 @@ impl Display for InvalidOpcode {
     }
 }
 
-impl TryFrom<u8> for Op {
-    type Error = InvalidOpcode;
+/// One operand of an instruction, for handling operands without matching
+/// every variant of [`Op`]. `field` is the operand's letter in the
+/// instruction's bit layout.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Reg {
+        field: char,
+        reg: Reg,
+    },
+    Imm {
+        field: char,
+        value: i32,
+        signed: bool,
+        bits: u8,
+    },
+}
+
+/// An operand that can be packed into a bit field of an instruction.
+trait Field: Sized {
+    fn to_field(self) -> u8;
+    /// `bits` is the raw field, already masked to the field width.
+    fn from_field(bits: u8) -> Self;
+    fn to_operand(self, field: char) -> Operand;
+    /// `None` if the operand is the wrong kind or out of range.
+    fn from_operand(operand: &Operand) -> Option<Self>;
+}
 
-    fn try_from(opcode: u8) -> Result<Self, Self::Error> {
-        // unwrapping is safe since the bit mask limits the value
-        fn to_reg(val: u8) -> Reg {
-            val.try_into().unwrap()
+impl Field for Reg {
+    fn to_field(self) -> u8 {
+        self as u8
+    }
+    fn from_field(bits: u8) -> Self {
+        Reg::ALL[bits as usize % Reg::COUNT]
+    }
+    fn to_operand(self, field: char) -> Operand {
+        Operand::Reg { field, reg: self }
+    }
+    fn from_operand(operand: &Operand) -> Option<Self> {
+        match operand {
+            Operand::Reg { reg, .. } => Some(*reg),
+            Operand::Imm { .. } => None,
         }
-        fn to_i5(val: u8) -> I5 {
-            let sign_extend: i8 = (val & 0x1f | if val & 0x10 != 0 { 0xe0 } else { 0x00 }) as i8;
-            sign_extend.try_into().unwrap()
+    }
+}
+impl<const N: u8> Field for Imm<u8, N> {
+    fn to_field(self) -> u8 {
+        self.bits()
+    }
+    fn from_field(bits: u8) -> Self {
+        Imm::<u8, N>::from_field(bits)
+    }
+    fn to_operand(self, field: char) -> Operand {
+        Operand::Imm {
+            field,
+            value: self.get() as i32,
+            signed: false,
+            bits: N,
         }
-        fn to_u3(val: u8) -> U3 {
-            val.try_into().unwrap()
+    }
+    fn from_operand(operand: &Operand) -> Option<Self> {
+        match operand {
+            Operand::Imm { value, .. } => u8::try_from(*value).ok().and_then(Imm::new),
+            Operand::Reg { .. } => None,
         }
-        fn to_u4(val: u8) -> U4 {
-            val.try_into().unwrap()
+    }
+}
+impl<const N: u8> Field for Imm<i8, N> {
+    fn to_field(self) -> u8 {
+        self.bits()
+    }
+    fn from_field(bits: u8) -> Self {
+        Imm::<i8, N>::from_field(bits)
+    }
+    fn to_operand(self, field: char) -> Operand {
+        Operand::Imm {
+            field,
+            value: self.get() as i32,
+            signed: true,
+            bits: N,
+        }
+    }
+    fn from_operand(operand: &Operand) -> Option<Self> {
+        match operand {
+            Operand::Imm { value, .. } => i8::try_from(*value).ok().and_then(Imm::new),
+            Operand::Reg { .. } => None,
         }
+    }
+}
+
+/// Derive both `Op::to_binary` and `Op::decode` from one table of bit
+/// layouts, so encoding and decoding can't disagree.
+///
+/// Each layout is written MSB first with `0`/`1` for the fixed opcode bits and
+/// the name of an operand for each of its bits. Operand names must be single
+/// letters, listed in the same order as the variant's fields. Instructions
+/// from an extension are followed by `if <Extension>`.
+macro_rules! encodings {
+    ($($variant:ident $(($($field:ident),+))? = $layout:literal $(if $ext:ident)?,)+) => {
+        /// Bit layout of every instruction and the extension providing it.
+        const LAYOUTS: &[(&str, Option<Extension>)] = &[
+            $(($layout, extension!($($ext)?))),+
+        ];
+
+        const MNEMONICS: &[(&str, Option<Extension>)] = &[
+            $((stringify!($variant), extension!($($ext)?))),+
+        ];
 
-        match opcode {
-            x if x >> 5 == 0b100 => Ok(Op::BR(to_i5(opcode & 0x1f))),
-            x if x >> 5 == 0b101 => Ok(Op::BRZ(to_i5(opcode & 0x1f))),
-            x if x >> 5 == 0b000 => {
-                Ok(Op::ADDI(to_reg(opcode & 0x03), to_u3((opcode >> 2) & 0x07)))
+        impl Op {
+            /// The extension this instruction belongs to, if any.
+            pub fn extension(&self) -> Option<Extension> {
+                match self {
+                    $(Op::$variant { .. } => extension!($($ext)?),)+
+                }
             }
-            x if x >> 5 == 0b001 => {
-                Ok(Op::SUBI(to_reg(opcode & 0x03), to_u3((opcode >> 2) & 0x07)))
+
+            /// Decode an instruction, recognizing only the extensions in `isa`.
+            pub fn decode(opcode: u8, isa: &Isa) -> Result<Op, InvalidOpcode> {
+                let word = opcode as u32;
+                $(
+                    let fixed = pattern_mask($layout, '0') | pattern_mask($layout, '1');
+                    if $(isa.has(Extension::$ext) &&)? word & fixed == pattern_mask($layout, '1') {
+                        return Ok(Op::$variant $(($(Field::from_field(gather(
+                            pattern_mask($layout, field_name(stringify!($field))),
+                            word,
+                        ) as u8)),+))?);
+                    }
+                )+
+                Err(InvalidOpcode(word))
+            }
+
+            /// The instruction's operands in assembly order.
+            pub fn operands(&self) -> Vec<Operand> {
+                match *self {
+                    $(Op::$variant $(($($field),+))? => vec![
+                        $($($field.to_operand(field_name(stringify!($field)))),+)?
+                    ],)+
+                }
+            }
+
+            /// The same instruction with new operands, `None` if they are the
+            /// wrong number or kind, or out of range.
+            pub fn with_operands(&self, operands: &[Operand]) -> Option<Op> {
+                let mut operands = operands.iter();
+                let op = match self {
+                    $(Op::$variant { .. } => {
+                        $($(let $field = Field::from_operand(operands.next()?)?;)+)?
+                        Op::$variant $(($($field),+))?
+                    })+
+                };
+                match operands.next() {
+                    Some(_) => None,
+                    None => Some(op),
+                }
             }
-            x if x >> 4 == 0b0100 => Ok(Op::SR0(to_u4(opcode & 0x0f))),
-            x if x >> 4 == 0b0101 => Ok(Op::SRH0(to_u4(opcode & 0x0f))),
-            x if x >> 2 == 0b011000 => Ok(Op::CLR(to_reg(opcode & 0x03))),
-            x if x >> 4 == 0b0111 => {
-                Ok(Op::MOV(to_reg((opcode >> 2) & 0x03), to_reg(opcode & 0x03)))
+
+            pub fn to_binary(&self) -> u8 {
+                match *self {
+                    $(Op::$variant $(($($field),+))? => {
+                        let word = pattern_mask($layout, '1');
+                        $($(let word = word | deposit(
+                            pattern_mask($layout, field_name(stringify!($field))),
+                            $field.to_field() as u32,
+                        );)+)?
+                        word as u8
+                    })+
+                }
             }
-            x if x >> 2 == 0b110000 => Ok(Op::MOVA(to_reg(opcode & 0x03))),
-            x if x >> 2 == 0b110001 => Ok(Op::MOVR(to_reg(opcode & 0x03))),
-            x if x >> 2 == 0b110010 => Ok(Op::MOVRHS(to_reg(opcode & 0x03))),
-            0xff => Ok(Op::PAUSE),
-            _ => Err(InvalidOpcode(opcode)),
         }
-    }
+
+        impl TryFrom<u8> for Op {
+            type Error = InvalidOpcode;
+
+            /// Decode an instruction of the base instruction set.
+            fn try_from(opcode: u8) -> Result<Self, Self::Error> {
+                Op::decode(opcode, &Isa::default())
+            }
+        }
+    };
+}
+
+/// `Some(Extension::$ext)`, or `None` for base instructions.
+macro_rules! extension {
+    () => {
+        None
+    };
+    ($ext:ident) => {
+        Some(Extension::$ext)
+    };
+}
+
+const fn field_name(name: &str) -> char {
+    name.as_bytes()[0] as char
+}
+
+encodings! {
+    BR(i) = "100_iiiii",
+    BRZ(i) = "101_iiiii",
+    ADDI(r, i) = "000_iii_rr",
+    SUBI(r, i) = "001_iii_rr",
+    SR0(i) = "0100_iiii",
+    SRH0(i) = "0101_iiii",
+    CLR(r) = "011000_rr",
+    MOV(d, s) = "0111_dd_ss",
+    MOVA(r) = "110000_rr",
+    MOVR(r) = "110001_rr",
+    MOVRHS(r) = "110010_rr",
+    PAUSE = "1111_1111",
+    AND(r) = "011001_rr" if Logic,
+    OR(r) = "011010_rr" if Logic,
+    XOR(r) = "011011_rr" if Logic,
+    NOT(r) = "110011_rr" if Logic,
+    LD(d, a) = "1101_dd_aa" if Mem,
+    ST(s, a) = "1110_ss_aa" if Mem,
+    IN(r) = "111100_rr" if Io,
+    OUT(r) = "111101_rr" if Io,
+    SHL(r) = "011001_rr" if Shift,
+    SHR(r) = "011010_rr" if Shift,
+    ADD(d, s) = "1101_dd_ss" if Shift,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::encoding::Encoding;
+
+    fn all_extensions() -> Isa {
+        Isa::default()
+            .with(Extension::Logic)
+            .with(Extension::Mem)
+            .with(Extension::Io)
+    }
+
+    #[test]
+    fn test_layouts_disjoint() {
+        // only instructions of conflicting extensions may share opcodes
+        let layouts: Vec<Encoding> = LAYOUTS
+            .iter()
+            .map(|(l, _)| Encoding::parse(l).unwrap())
+            .collect();
+        for (n, a) in layouts.iter().enumerate() {
+            assert_eq!(a.width(), 8, "{}", LAYOUTS[n].0);
+            for (m, b) in layouts.iter().enumerate().skip(n + 1) {
+                if a.overlaps(b) {
+                    let (ext_a, ext_b) = (LAYOUTS[n].1, LAYOUTS[m].1);
+                    let conflicting = ext_a.zip(ext_b).is_some_and(|(x, y)| x.conflicts_with(y));
+                    assert!(conflicting, "{} overlaps {}", LAYOUTS[n].0, LAYOUTS[m].0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_conflicts() {
+        use Extension::*;
+        assert!(Shift.conflicts_with(Logic));
+        assert!(Mem.conflicts_with(Shift));
+        assert!(!Shift.conflicts_with(Io));
+        assert!(!Logic.conflicts_with(Mem));
+        assert!(!Shift.conflicts_with(Shift));
+
+        assert_eq!(all_extensions().conflict(), None);
+        assert_eq!(all_extensions().to_string(), "base+logic+mem+io");
+        let isa = Isa::default().with(Io).with(Mem).with(Shift);
+        assert_eq!(isa.conflict(), Some((Mem, Shift)));
+    }
+
+    #[test]
+    fn test_shift() {
+        let isa = Isa::default().with(Extension::Shift);
+        let data: Vec<(Op, u8)> = vec![
+            (Op::SHL(Reg::R1), 0b011001_01),
+            (Op::SHR(Reg::R3), 0b011010_11),
+            (Op::ADD(Reg::R2, Reg::R0), 0b1101_10_00),
+        ];
+        for (op, code) in data {
+            assert_eq!(op.to_binary(), code);
+            assert_eq!(Op::decode(code, &isa).unwrap(), op);
+        }
+        for code in 0..=255u8 {
+            if let Ok(op) = Op::decode(code, &isa) {
+                assert_eq!(op.to_binary(), code, "{code:08b}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_all() {
+        // every byte that decodes must encode back to itself
+        let isa = all_extensions();
+        for code in 0..=255u8 {
+            if let Ok(op) = Op::decode(code, &isa) {
+                assert_eq!(op.to_binary(), code, "{code:08b}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_operands() {
+        let op = Op::ADDI(Reg::R1, U3::new(5).unwrap());
+        let operands = op.operands();
+        assert_eq!(
+            operands,
+            vec![
+                Operand::Reg {
+                    field: 'r',
+                    reg: Reg::R1
+                },
+                Operand::Imm {
+                    field: 'i',
+                    value: 5,
+                    signed: false,
+                    bits: 3
+                },
+            ]
+        );
+        assert_eq!(op.with_operands(&operands), Some(op));
+        assert_eq!(Op::PAUSE.operands(), vec![]);
+        assert_eq!(Op::PAUSE.with_operands(&[]), Some(Op::PAUSE));
+
+        let branch = Op::BR(I5::new(1).unwrap());
+        let imm = |value| Operand::Imm {
+            field: 'i',
+            value,
+            signed: true,
+            bits: 5,
+        };
+        assert_eq!(
+            branch.with_operands(&[imm(-16)]),
+            Some(Op::BR(I5::new(-16).unwrap()))
+        );
+        assert_eq!(branch.with_operands(&[imm(16)]), None);
+        assert_eq!(branch.with_operands(&[]), None);
+        assert_eq!(branch.with_operands(&[imm(1), imm(1)]), None);
+        assert_eq!(branch.with_operands(&operands[..1]), None);
+    }
+
+    #[test]
+    fn test_remap_registers() {
+        let swap = [(1, 3), (3, 1)];
+        assert_eq!(
+            Op::MOV(Reg::R1, Reg::R3).remap_registers(&swap),
+            Some(Op::MOV(Reg::R3, Reg::R1))
+        );
+        assert_eq!(
+            Op::MOV(Reg::R0, Reg::R2).remap_registers(&swap),
+            Some(Op::MOV(Reg::R0, Reg::R2))
+        );
+        assert_eq!(Op::PAUSE.remap_registers(&swap), Some(Op::PAUSE));
+        assert_eq!(Op::CLR(Reg::R1).remap_registers(&[(1, 4)]), None);
+    }
+
+    #[test]
+    fn test_extension() {
+        let data: Vec<(Op, u8)> = vec![
+            (Op::AND(Reg::R1), 0b011001_01),
+            (Op::OR(Reg::R2), 0b011010_10),
+            (Op::XOR(Reg::R3), 0b011011_11),
+            (Op::NOT(Reg::R0), 0b110011_00),
+            (Op::LD(Reg::R0, Reg::R3), 0b1101_00_11),
+            (Op::ST(Reg::R2, Reg::R1), 0b1110_10_01),
+            (Op::IN(Reg::R1), 0b111100_01),
+            (Op::OUT(Reg::R3), 0b111101_11),
+        ];
+        for (op, code) in data {
+            assert_eq!(op.to_binary(), code);
+            assert_eq!(Op::decode(code, &all_extensions()).unwrap(), op);
+            assert!(Op::try_from(code).is_err(), "{code:08b} is not base");
+        }
+        assert_eq!(Op::extension_of("XOR"), Some(Extension::Logic));
+        assert_eq!(Op::extension_of("ST"), Some(Extension::Mem));
+        assert_eq!(Op::extension_of("OUT"), Some(Extension::Io));
+        assert_eq!(Op::extension_of("MOV"), None);
+    }
 
     #[test]
     fn test_binary() {
@@ -162,12 +683,7 @@ mod tests {
         ];
         for (op, code) in data {
             assert_eq!(op, code.try_into().unwrap(), "Failed {code:08b} to ASM",);
-            assert_eq!(
-                op.to_binary(),
-                code,
-                "Failed \"{}\" to binary",
-                op.to_string()
-            );
+            assert_eq!(op.to_binary(), code, "Failed \"{op}\" to binary");
         }
     }
 }
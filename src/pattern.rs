@@ -0,0 +1,60 @@
+/// An instruction pattern for `asp grep`, like `MOVA *` or `ADDI r1, *`.
+///
+/// Each token is either written as in the disassembly or `*` for anything.
+/// A pattern with fewer operands than the instruction matches any remaining
+/// operands, so `BRZ` matches every `BRZ`. Case is ignored.
+#[derive(Debug, PartialEq)]
+pub struct Pattern {
+    tokens: Vec<String>,
+}
+
+impl Pattern {
+    /// `None` if the pattern is empty.
+    pub fn parse(s: &str) -> Option<Pattern> {
+        let tokens: Vec<String> = split(s).map(str::to_ascii_uppercase).collect();
+        match tokens.is_empty() {
+            true => None,
+            false => Some(Pattern { tokens }),
+        }
+    }
+
+    /// Does the assembly text of an instruction match?
+    pub fn matches(&self, instr: &str) -> bool {
+        let tokens: Vec<String> = split(instr).map(str::to_ascii_uppercase).collect();
+        self.tokens.len() <= tokens.len()
+            && self
+                .tokens
+                .iter()
+                .zip(&tokens)
+                .all(|(p, t)| p == "*" || p == t)
+    }
+}
+
+fn split(s: &str) -> impl Iterator<Item = &str> {
+    s.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|t| !t.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern() {
+        let mova = Pattern::parse("MOVA *").unwrap();
+        assert!(mova.matches("MOVA r1"));
+        assert!(!mova.matches("MOV r1, r2"));
+        assert!(!mova.matches("MOVR r1"));
+
+        let brz = Pattern::parse("brz").unwrap();
+        assert!(brz.matches("BRZ -2"));
+
+        let addi = Pattern::parse("ADDI r1,*").unwrap();
+        assert!(addi.matches("ADDI r1, 5"));
+        assert!(!addi.matches("ADDI r2, 5"));
+
+        assert!(!Pattern::parse("PAUSE r0").unwrap().matches("PAUSE"));
+        assert!(Pattern::parse("* r3").unwrap().matches("CLR r3"));
+        assert_eq!(Pattern::parse(" , "), None);
+    }
+}
@@ -1,9 +1,112 @@
-use crate::op::Op;
+use crate::op::{Category, Op, Operand};
+use crate::word::Word;
 use core::fmt;
 use std::fmt::Write;
+use std::ops::Range;
 
 pub struct Program {
     pub ops: Vec<Op>,
+    /// Initial contents of the data memory.
+    pub data: Vec<u8>,
+}
+
+/// A listing of a program's instructions, one per line, from
+/// [`Program::display`].
+pub struct Listing<'a> {
+    program: &'a Program,
+    addresses: bool,
+    bytes: bool,
+}
+
+impl Listing<'_> {
+    /// Start each line with the instruction's address, e.g. `0x0004:`.
+    pub fn with_addresses(mut self) -> Self {
+        self.addresses = true;
+        self
+    }
+
+    /// Show each instruction's machine code in hex before it.
+    pub fn with_bytes(mut self) -> Self {
+        self.bytes = true;
+        self
+    }
+}
+
+impl fmt::Display for Listing<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (addr, op) in self.program.ops.iter().enumerate() {
+            if addr > 0 {
+                writeln!(f)?;
+            }
+            if self.addresses {
+                write!(f, "0x{addr:04x}: ")?;
+            }
+            if self.bytes {
+                write!(f, "{:02x}  ", op.to_binary())?;
+            }
+            write!(f, "{op}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.display().fmt(f)
+    }
+}
+
+/// Hooks called by [`Program::visit`] for each instruction with its address,
+/// one per [`Category`]. Every hook does nothing by default.
+pub trait Visitor {
+    fn branch(&mut self, _addr: usize, _op: &Op) {}
+    fn alu(&mut self, _addr: usize, _op: &Op) {}
+    fn motor(&mut self, _addr: usize, _op: &Op) {}
+    fn memory(&mut self, _addr: usize, _op: &Op) {}
+    fn io(&mut self, _addr: usize, _op: &Op) {}
+}
+
+/// A region of a program, from [`Program::slice`].
+#[derive(Debug, PartialEq)]
+pub struct Slice<'a> {
+    pub range: Range<usize>,
+    pub ops: &'a [Op],
+    /// Branches in the slice to outside it, by address.
+    pub exits: Vec<usize>,
+    /// Branches outside the slice into it, by address.
+    pub entries: Vec<usize>,
+}
+
+/// Why [`Program::insert`] or [`Program::remove`] couldn't edit a program.
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    /// The address is past the end of the program.
+    InvalidAddress(usize),
+    /// The branch at this address, before the edit, can't reach its target
+    /// after it.
+    BranchOutOfRange(usize),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::InvalidAddress(addr) => {
+                write!(f, "Address 0x{addr:04x} is past the end of the program.")
+            }
+            PatchError::BranchOutOfRange(addr) => {
+                write!(f, "The branch at 0x{addr:04x} can't reach its target.")
+            }
+        }
+    }
+}
+
+/// What [`Program::equivalent`] ignores.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Equivalence {
+    /// Trailing zero words, which are the same as the fill in a MIF file.
+    pub ignore_padding: bool,
+    /// Which no-op is used, see [`Op::is_nop`].
+    pub any_nop: bool,
 }
 
 impl Program {
@@ -18,34 +121,509 @@ impl Program {
             .join("\n")
     }
 
-    pub fn as_mif(&self) -> Result<String, fmt::Error> {
-        let width = 8;
-        let depth = 256;
-        let len = self.ops.len();
-
-        if len > depth {
-            panic!("Program is too long!");
+    /// A listing of the instructions, with options for addresses and
+    /// machine code.
+    pub fn display(&self) -> Listing<'_> {
+        Listing {
+            program: self,
+            addresses: false,
+            bytes: false,
         }
+    }
+
+    pub fn as_mif(&self) -> Result<String, MifError> {
+        words_as_mif(&self.as_binary(), 8, 256)
+    }
+
+    pub fn data_as_mif(&self) -> Result<String, MifError> {
+        words_as_mif(&self.data, 8, 256)
+    }
 
-        let mut s = String::new();
-        writeln!(s, "WIDTH={width};")?;
-        writeln!(s, "DEPTH={depth};")?;
-        writeln!(s, "")?;
-        writeln!(s, "ADDRESS_RADIX=UNS;")?;
-        writeln!(s, "DATA_RADIX=BIN;")?;
-        writeln!(s, "")?;
-        writeln!(s, "CONTENT BEGIN")?;
+    /// Replace each instruction with `f(address, instruction)`.
+    pub fn map_ops(&mut self, mut f: impl FnMut(usize, Op) -> Op) {
+        self.ops = std::mem::take(&mut self.ops)
+            .into_iter()
+            .enumerate()
+            .map(|(n, op)| f(n, op))
+            .collect();
+    }
 
+    /// Call the `visitor` hook for each instruction's category in order.
+    pub fn visit(&self, visitor: &mut impl Visitor) {
         for (n, op) in self.ops.iter().enumerate() {
-            writeln!(s, "\t{n}\t:\t{:08b};", op.to_binary())?;
+            match op.category() {
+                Category::Branch => visitor.branch(n, op),
+                Category::Alu => visitor.alu(n, op),
+                Category::Motor => visitor.motor(n, op),
+                Category::Memory => visitor.memory(n, op),
+                Category::Io => visitor.io(n, op),
+            }
+        }
+    }
+
+    /// Do the programs behave the same, ignoring differences allowed by
+    /// `options`?
+    pub fn equivalent(&self, other: &Program, options: Equivalence) -> bool {
+        self.first_difference(other, options).is_none()
+            && trim(&self.data, options) == trim(&other.data, options)
+    }
+
+    /// Address of the first instruction that differs between the programs,
+    /// ignoring differences allowed by `options`.
+    pub fn first_difference(&self, other: &Program, options: Equivalence) -> Option<usize> {
+        let a = self.canonical(options);
+        let b = other.canonical(options);
+        let (a, b) = (trim(&a, options), trim(&b, options));
+        (0..a.len().max(b.len())).find(|n| a.get(*n) != b.get(*n))
+    }
+
+    /// The instructions in `range`, which is clipped to the program, and the
+    /// branches that cross its edges.
+    pub fn slice(&self, range: Range<usize>) -> Slice<'_> {
+        let range = range.start.min(self.ops.len())..range.end.min(self.ops.len());
+        let offsets: Vec<Option<i32>> = self.ops.iter().map(Op::branch_offset).collect();
+        let (exits, entries) = crossings(&offsets, &range);
+        Slice {
+            ops: &self.ops[range.clone()],
+            range,
+            exits,
+            entries,
+        }
+    }
+
+    /// Insert `ops` before the instruction at `addr`, moving branches so they
+    /// reach the same instructions as before. Branches to `addr` reach the
+    /// new instructions, whose own branches are left as they are. Nothing
+    /// changes if a branch can no longer reach.
+    pub fn insert(&mut self, addr: usize, ops: Vec<Op>) -> Result<(), PatchError> {
+        if addr > self.ops.len() {
+            return Err(PatchError::InvalidAddress(addr));
+        }
+        let n = ops.len();
+        let moved = |a: usize| if a < addr { a } else { a + n };
+        self.fix_branches(|a| Some(moved(a)), |t| if t <= addr { t } else { t + n })?;
+        self.ops.splice(addr..addr, ops);
+        Ok(())
+    }
+
+    /// Remove the instructions in `range`, moving branches so they reach the
+    /// same instructions as before. Branches into the removed instructions
+    /// reach the one after them. Nothing changes if a branch can no longer
+    /// reach.
+    pub fn remove(&mut self, range: Range<usize>) -> Result<(), PatchError> {
+        if range.end > self.ops.len() {
+            return Err(PatchError::InvalidAddress(range.end));
+        }
+        let n = range.len();
+        let moved = |a: usize| match a {
+            a if a < range.start => a,
+            a if a < range.end => range.start,
+            a => a - n,
+        };
+        self.fix_branches(|a| (!range.contains(&a)).then(|| moved(a)), moved)?;
+        self.ops.drain(range);
+        Ok(())
+    }
+
+    /// Rewrite branches for instructions about to move. The instruction at
+    /// `addr` moves to `from(addr)`, or is about to be removed if `None`, and
+    /// a branch to `addr` should land on `to(addr)`. Branches out of the
+    /// program are left alone.
+    fn fix_branches(
+        &mut self,
+        from: impl Fn(usize) -> Option<usize>,
+        to: impl Fn(usize) -> usize,
+    ) -> Result<(), PatchError> {
+        let mut fixed = vec![];
+        for (addr, op) in self.ops.iter().enumerate() {
+            let (Some(offset), Some(new_addr)) = (op.branch_offset(), from(addr)) else {
+                continue;
+            };
+            let Some(target) = addr
+                .checked_add_signed(offset as isize)
+                .filter(|t| *t <= self.ops.len())
+            else {
+                continue;
+            };
+            let offset = to(target) as i32 - new_addr as i32;
+            let operands: Vec<Operand> = op
+                .operands()
+                .into_iter()
+                .map(|o| match o {
+                    Operand::Imm {
+                        field,
+                        signed,
+                        bits,
+                        ..
+                    } => Operand::Imm {
+                        field,
+                        value: offset,
+                        signed,
+                        bits,
+                    },
+                    reg => reg,
+                })
+                .collect();
+            match op.with_operands(&operands) {
+                Some(op) => fixed.push((addr, op)),
+                None => return Err(PatchError::BranchOutOfRange(addr)),
+            }
+        }
+        for (addr, op) in fixed {
+            self.ops[addr] = op;
+        }
+        Ok(())
+    }
+
+    /// Machine code with every no-op written as `ADDI r0, 0` if allowed.
+    fn canonical(&self, options: Equivalence) -> Vec<u8> {
+        self.ops
+            .iter()
+            .map(|o| match options.any_nop && o.is_nop() {
+                true => 0,
+                false => o.to_binary(),
+            })
+            .collect()
+    }
+}
+
+/// Branches leaving and entering `range`, by address, given how far each
+/// instruction branches.
+pub fn crossings(offsets: &[Option<i32>], range: &Range<usize>) -> (Vec<usize>, Vec<usize>) {
+    let mut exits = vec![];
+    let mut entries = vec![];
+    for (addr, offset) in offsets.iter().enumerate() {
+        let Some(offset) = offset else { continue };
+        let target = addr as i64 + *offset as i64;
+        let inside = |a: i64| range.start as i64 <= a && a < range.end as i64;
+        match (inside(addr as i64), inside(target)) {
+            (true, false) => exits.push(addr),
+            (false, true) => entries.push(addr),
+            _ => {}
         }
+    }
+    (exits, entries)
+}
 
-        match len {
-            x if x == depth => {}
-            x if x == depth - 1 => writeln!(s, "\t255\t:\t{:08b}", 0)?,
-            _ => writeln!(s, "\t[{}..{}]\t:\t{:08b};", self.ops.len(), depth - 1, 0)?,
+/// Branches, by address, that go to a different bank of `bank_size` words
+/// than they are in, given how far each instruction branches.
+pub fn bank_crossings(offsets: &[Option<i32>], bank_size: usize) -> Vec<usize> {
+    let bank = |addr: i64| addr.div_euclid(bank_size as i64);
+    offsets
+        .iter()
+        .enumerate()
+        .filter_map(|(addr, offset)| offset.map(|o| (addr, addr as i64 + o as i64)))
+        .filter(|(addr, target)| bank(*addr as i64) != bank(*target))
+        .map(|(addr, _)| addr)
+        .collect()
+}
+
+/// `words` without trailing zeros if padding is ignored.
+fn trim(words: &[u8], options: Equivalence) -> &[u8] {
+    match options.ignore_padding {
+        true => {
+            let len = words.iter().rposition(|w| *w != 0).map_or(0, |n| n + 1);
+            &words[..len]
         }
-        writeln!(s, "END;")?;
-        Ok(s)
+        false => words,
+    }
+}
+
+/// Why words don't fit in a memory initialization file.
+#[derive(Debug, PartialEq)]
+pub enum MifError {
+    /// A segment starts at this address, inside the one before it.
+    Overlap(usize),
+    /// The words reach this address, past the memory's depth.
+    TooLong(usize, usize),
+    /// Writing the file failed.
+    Format(fmt::Error),
+}
+
+impl fmt::Display for MifError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MifError::Overlap(addr) => write!(f, "Segments overlap at 0x{addr:04x}."),
+            MifError::TooLong(end, depth) => {
+                write!(f, "Program needs {end} words but memory holds {depth}.")
+            }
+            MifError::Format(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<fmt::Error> for MifError {
+    fn from(e: fmt::Error) -> Self {
+        MifError::Format(e)
+    }
+}
+
+/// Memory initialization file for `depth` words of `width` bits, filling
+/// past the end of `words` with zero.
+pub fn words_as_mif<W: Word>(words: &[W], width: u8, depth: usize) -> Result<String, MifError> {
+    words_as_mif_at(words, width, depth, 0)
+}
+
+/// Like [`words_as_mif`] with `words` placed from address `start`, filling
+/// below it with zero too.
+pub fn words_as_mif_at<W: Word>(
+    words: &[W],
+    width: u8,
+    depth: usize,
+    start: usize,
+) -> Result<String, MifError> {
+    segments_as_mif(&[(start, words)], width, depth)
+}
+
+/// Like [`words_as_mif`] with each segment of words placed from its own
+/// address, filling every gap between them with zero.
+pub fn segments_as_mif<W: Word>(
+    segments: &[(usize, &[W])],
+    width: u8,
+    depth: usize,
+) -> Result<String, MifError> {
+    let mut segments = segments.to_vec();
+    segments.sort_by_key(|(start, _)| *start);
+
+    let mut s = String::new();
+    writeln!(s, "WIDTH={width};")?;
+    writeln!(s, "DEPTH={depth};")?;
+    writeln!(s)?;
+    writeln!(s, "ADDRESS_RADIX=UNS;")?;
+    writeln!(s, "DATA_RADIX=BIN;")?;
+    writeln!(s)?;
+    writeln!(s, "CONTENT BEGIN")?;
+
+    let width = width as usize;
+    let mut next = 0;
+    for (start, words) in segments {
+        if start < next {
+            return Err(MifError::Overlap(start));
+        }
+        fill(&mut s, next..start, width)?;
+        for (n, word) in words.iter().enumerate() {
+            writeln!(s, "\t{}\t:\t{:0width$b};", start + n, (*word).into())?;
+        }
+        next = start + words.len();
+    }
+
+    if next > depth {
+        return Err(MifError::TooLong(next, depth));
+    }
+    fill(&mut s, next..depth, width)?;
+    writeln!(s, "END;")?;
+    Ok(s)
+}
+
+/// Zero the addresses in `range` of a MIF's CONTENT, as a single address or
+/// an `[a..b]` range.
+fn fill(s: &mut String, range: Range<usize>, width: usize) -> fmt::Result {
+    match range.len() {
+        0 => Ok(()),
+        1 => writeln!(s, "\t{}\t:\t{:0width$b};", range.start, 0),
+        _ => writeln!(
+            s,
+            "\t[{}..{}]\t:\t{:0width$b};",
+            range.start,
+            range.end - 1,
+            0
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(code: &[u8]) -> Program {
+        Program {
+            ops: code.iter().map(|c| Op::try_from(*c).unwrap()).collect(),
+            data: vec![],
+        }
+    }
+
+    #[test]
+    fn test_equivalent() {
+        let exact = Equivalence::default();
+        let semantic = Equivalence {
+            ignore_padding: true,
+            any_nop: true,
+        };
+
+        let a = program(&[0b011000_01, 0xff]);
+        let padded = program(&[0b011000_01, 0xff, 0, 0]);
+        assert!(a.equivalent(&a, exact));
+        assert!(!a.equivalent(&padded, exact));
+        assert_eq!(a.first_difference(&padded, exact), Some(2));
+        assert!(a.equivalent(&padded, semantic));
+
+        // MOV r1, r1 and BR 1 are no-ops, like ADDI r0, 0
+        let nops = program(&[0b0111_01_01, 0xff, 0b100_00001]);
+        let zeros = program(&[0, 0xff]);
+        assert_eq!(nops.first_difference(&zeros, exact), Some(0));
+        assert!(nops.equivalent(&zeros, semantic));
+
+        let other = program(&[0b011000_01, 0b011000_01]);
+        assert_eq!(a.first_difference(&other, semantic), Some(1));
+    }
+
+    #[test]
+    fn test_display() {
+        let p = program(&[0b011000_00, 0xff]);
+        assert_eq!(p.to_string(), p.as_text());
+        assert_eq!(
+            p.display().with_addresses().with_bytes().to_string(),
+            "0x0000: 60  CLR r0\n0x0001: ff  PAUSE"
+        );
+        assert_eq!(
+            p.display().with_addresses().to_string(),
+            "0x0000: CLR r0\n0x0001: PAUSE"
+        );
+    }
+
+    #[test]
+    fn test_slice() {
+        // BR 2, PAUSE, BRZ -2, PAUSE, BR -1
+        let p = program(&[0b100_00010, 0xff, 0b101_11110, 0xff, 0b100_11111]);
+        let slice = p.slice(1..3);
+        assert_eq!(slice.ops, &p.ops[1..3]);
+        assert_eq!(slice.exits, vec![2]);
+        assert_eq!(slice.entries, vec![0]);
+
+        let slice = p.slice(3..10);
+        assert_eq!(slice.range, 3..5);
+        assert_eq!(slice.exits, vec![]);
+        assert_eq!(slice.entries, vec![]);
+    }
+
+    #[test]
+    fn test_insert() {
+        // BR 2, PAUSE, BRZ -2, PAUSE, BR -1
+        let code = [0b100_00010, 0xff, 0b101_11110, 0xff, 0b100_11111];
+        let mova = || vec![Op::try_from(0b110000_00).unwrap()];
+
+        let mut p = program(&code);
+        p.insert(1, mova()).unwrap();
+        let expected = [
+            0b100_00011,
+            0b110000_00,
+            0xff,
+            0b101_11101,
+            0xff,
+            0b100_11111,
+        ];
+        assert_eq!(p.as_binary(), expected);
+
+        // branches to the insertion point reach the new instruction
+        let mut p = program(&code);
+        p.insert(2, mova()).unwrap();
+        assert_eq!(
+            p.as_binary()[..4],
+            [0b100_00010, 0xff, 0b110000_00, 0b101_11101]
+        );
+
+        let mut p = program(&code);
+        assert_eq!(p.insert(6, mova()), Err(PatchError::InvalidAddress(6)));
+
+        // BR 15 can't be pushed any further
+        let mut far = vec![0b100_01111];
+        far.extend([0xff; 15]);
+        let mut p = program(&far);
+        assert_eq!(p.insert(1, mova()), Err(PatchError::BranchOutOfRange(0)));
+        assert_eq!(p.as_binary(), far);
+    }
+
+    #[test]
+    fn test_remove() {
+        // BR 2, PAUSE, BRZ -2, PAUSE, BR -1
+        let mut p = program(&[0b100_00010, 0xff, 0b101_11110, 0xff, 0b100_11111]);
+        p.remove(1..2).unwrap();
+        assert_eq!(p.as_binary(), [0b100_00001, 0b101_11111, 0xff, 0b100_11111]);
+
+        // branches into the removed instructions reach the next one
+        p.remove(1..3).unwrap();
+        assert_eq!(p.as_binary(), [0b100_00001, 0b100_00000]);
+
+        assert_eq!(p.remove(1..3), Err(PatchError::InvalidAddress(3)));
+    }
+
+    #[test]
+    fn test_bank_crossings() {
+        let offsets = [Some(1), None, Some(-2), Some(1), Some(-1)];
+        assert_eq!(bank_crossings(&offsets, 2), vec![2, 3, 4]);
+        assert_eq!(bank_crossings(&offsets, 8), vec![]);
+    }
+
+    #[test]
+    fn test_mif_at() {
+        let mif = words_as_mif_at(&[0xffu8], 8, 4, 2).unwrap();
+        assert!(mif.contains("\t[0..1]\t:\t00000000;\n\t2\t:\t11111111;\n\t3\t:\t00000000;\n"));
+        let mif = words_as_mif_at(&[0xffu8], 8, 2, 1).unwrap();
+        assert!(mif.contains("\t0\t:\t00000000;\n\t1\t:\t11111111;\nEND;"));
+    }
+
+    #[test]
+    fn test_segments_as_mif() {
+        let high: &[u8] = &[0x0f, 0xf0];
+        let low: &[u8] = &[0xff];
+        let mif = segments_as_mif(&[(4, high), (1, low)], 8, 8).unwrap();
+        let content = "\t0\t:\t00000000;\n\
+                       \t1\t:\t11111111;\n\
+                       \t[2..3]\t:\t00000000;\n\
+                       \t4\t:\t00001111;\n\
+                       \t5\t:\t11110000;\n\
+                       \t[6..7]\t:\t00000000;\n\
+                       END;";
+        assert!(mif.contains(content));
+
+        // a full memory has no fill, one word short fills a single address
+        let full = segments_as_mif(&[(0, low), (1, high)], 8, 3).unwrap();
+        assert!(full.contains("\t2\t:\t11110000;\nEND;"));
+        let short = segments_as_mif(&[(0, high)], 8, 3).unwrap();
+        assert!(short.contains("\t1\t:\t11110000;\n\t2\t:\t00000000;\nEND;"));
+
+        assert_eq!(
+            segments_as_mif(&[(0, high), (1, low)], 8, 8),
+            Err(MifError::Overlap(1))
+        );
+        assert_eq!(
+            segments_as_mif(&[(2, high)], 8, 3),
+            Err(MifError::TooLong(4, 3))
+        );
+    }
+
+    #[test]
+    fn test_map_ops() {
+        // turn every no-op into PAUSE
+        let mut p = program(&[0b011000_01, 0, 0b100_00001]);
+        p.map_ops(|_, op| if op.is_nop() { Op::PAUSE } else { op });
+        assert_eq!(p.as_binary(), vec![0b011000_01, 0xff, 0xff]);
+
+        let mut addresses = vec![];
+        p.map_ops(|n, op| {
+            addresses.push(n);
+            op
+        });
+        assert_eq!(addresses, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_visit() {
+        #[derive(Default)]
+        struct Branches(Vec<usize>, usize);
+        impl Visitor for Branches {
+            fn branch(&mut self, addr: usize, _op: &Op) {
+                self.0.push(addr);
+            }
+            fn motor(&mut self, _addr: usize, _op: &Op) {
+                self.1 += 1;
+            }
+        }
+
+        let p = program(&[0b100_00010, 0xff, 0, 0b101_11110, 0b110000_01]);
+        let mut v = Branches::default();
+        p.visit(&mut v);
+        assert_eq!(v.0, vec![0, 3]);
+        assert_eq!(v.1, 2);
     }
 }
@@ -9,6 +9,16 @@ pub enum Reg {
     R3 = 3,
 }
 
+impl Reg {
+    pub const COUNT: usize = 4;
+    /// Every register, in order of number.
+    pub const ALL: [Reg; Reg::COUNT] = [Reg::R0, Reg::R1, Reg::R2, Reg::R3];
+
+    pub fn iter() -> impl Iterator<Item = Reg> {
+        Reg::ALL.into_iter()
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum ParseError {
     MissingPrefix,
@@ -26,13 +36,10 @@ impl TryFrom<u8> for Reg {
     type Error = ParseError;
 
     fn try_from(value: u8) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(Reg::R0),
-            1 => Ok(Reg::R1),
-            2 => Ok(Reg::R2),
-            3 => Ok(Reg::R3),
-            _ => Err(ParseError::OutOfRange),
-        }
+        Reg::ALL
+            .get(value as usize)
+            .copied()
+            .ok_or(ParseError::OutOfRange)
     }
 }
 
@@ -47,3 +54,17 @@ impl TryFrom<&str> for Reg {
         Reg::try_from(number).map_err(|_| ParseError::OutOfRange)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all() {
+        for (n, reg) in Reg::iter().enumerate() {
+            assert_eq!(reg as usize, n);
+            assert_eq!(Reg::try_from(n as u8), Ok(reg));
+        }
+        assert_eq!(Reg::try_from(Reg::COUNT as u8), Err(ParseError::OutOfRange));
+    }
+}
@@ -0,0 +1,69 @@
+/// A starter program, commented for first-time users.
+pub const MAIN: &str = "\
+; A starter program for the stepper motor ASIP.
+; Build it with `make`, or run `asp main.s` yourself.
+; Everything after a semicolon is a comment.
+
+CLR r0
+SR0 0
+SRH0 3          ; r0 = 0x30, the motor command
+MOV r1, r0      ; keep the command in r1
+CLR r0
+SR0 10          ; r0 = 10, the number of steps
+MOVR r1         ; step the motor
+SUBI r0, 1      ; count down the steps
+BRZ 2           ; once r0 reaches zero, skip the next branch
+BR -3           ; otherwise step again
+PAUSE           ; wait for the next button press
+BR -11          ; and start over
+";
+
+/// A Makefile building `main.s` into program memory with asp. The memory
+/// depth and output format are variables, e.g. `make FORMAT=hex`.
+pub fn makefile(depth: usize, format: &str) -> String {
+    format!(
+        "\
+ASP ?= asp
+FORMAT ?= {format}
+DEPTH ?= {depth}
+
+all: main.$(FORMAT)
+
+main.$(FORMAT): main.s
+\t$(ASP) main.s -f $(FORMAT) --depth $(DEPTH) -o $@
+
+stats: main.s
+\t$(ASP) stats main.s
+
+clean:
+\trm -f main.$(FORMAT)
+
+.PHONY: all stats clean
+"
+    )
+}
+
+/// The files of a new project, as (file name, contents).
+pub fn files(depth: usize, format: &str) -> Vec<(&'static str, String)> {
+    vec![
+        ("main.s", MAIN.to_string()),
+        ("Makefile", makefile(depth, format)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::Session;
+
+    #[test]
+    fn test_scaffold() {
+        let program = Session::default().assemble(MAIN.as_bytes()).unwrap();
+        assert_eq!(program.ops.len(), 12);
+
+        let files = files(128, "hex");
+        assert_eq!(files[1].0, "Makefile");
+        assert!(files[1].1.contains("FORMAT ?= hex\nDEPTH ?= 128\n"));
+        assert!(files[1].1.contains("\n\t$(ASP) main.s -f $(FORMAT)"));
+    }
+}
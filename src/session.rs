@@ -0,0 +1,108 @@
+use crate::{
+    assembly::{self, AssembleOptions, Syntax},
+    binary,
+    messages::Lang,
+    object,
+    op::Isa,
+    Program,
+};
+
+/// Assembles and disassembles programs for one instruction set, returning
+/// errors instead of printing them.
+///
+/// A session holds no mutable state and the library keeps none globally, so
+/// one session can be shared between threads, e.g. behind an `Arc` in a web
+/// service assembling submissions concurrently.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    isa: Isa,
+    syntax: Syntax,
+    lang: Lang,
+}
+
+impl Session {
+    pub fn new(isa: Isa) -> Session {
+        Session {
+            isa,
+            syntax: Syntax::default(),
+            lang: Lang::default(),
+        }
+    }
+
+    pub fn with_syntax(mut self, syntax: Syntax) -> Session {
+        self.syntax = syntax;
+        self
+    }
+
+    /// Report assembly errors in `lang`.
+    pub fn with_lang(mut self, lang: Lang) -> Session {
+        self.lang = lang;
+        self
+    }
+
+    /// Assemble source into a program, resolving branches to its own
+    /// `.global` symbols, or report every error as the CLI prints it.
+    pub fn assemble(&self, source: &[u8]) -> Result<Program, Vec<String>> {
+        let opts = AssembleOptions {
+            syntax: self.syntax,
+            ..Default::default()
+        };
+        let module = assembly::assemble_with(source, &self.isa, &opts).map_err(|errors| {
+            errors
+                .iter()
+                .map(|e| e.localized_in(source, e.line(), self.lang))
+                .collect::<Vec<_>>()
+        })?;
+        let ops = object::resolve(&self.isa, module.instrs, module.symbols)
+            .map_err(|errors| errors.iter().map(|e| e.to_string()).collect::<Vec<_>>())?;
+        // the default options keep data to bytes
+        Ok(Program {
+            ops,
+            data: module.data.iter().map(|w| *w as u8).collect(),
+        })
+    }
+
+    /// Decode machine code into a program, or report every word that isn't
+    /// an instruction.
+    pub fn disassemble(&self, code: &[u8]) -> Result<Program, Vec<String>> {
+        binary::decode(code, &self.isa, 0)
+            .map(|ops| Program { ops, data: vec![] })
+            .map_err(|errors| errors.iter().map(|e| e.to_string()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session() {
+        fn shareable<T: Send + Sync>() {}
+        shareable::<Session>();
+
+        let session = Session::new(Isa::default());
+        std::thread::scope(|s| {
+            let a = s.spawn(|| session.assemble(b"CLR r0\nPAUSE"));
+            let b = s.spawn(|| session.disassemble(&[0b011000_00, 0xff]));
+            let (a, b) = (a.join().unwrap().unwrap(), b.join().unwrap().unwrap());
+            assert_eq!(a.as_binary(), b.as_binary());
+        });
+
+        let errors = session.assemble(b"CLR r0\nSUB r0, 1").err().unwrap();
+        assert_eq!(
+            errors,
+            vec![
+                "Line 2, column 1: error[E0001]: Invalid mnemonic \"SUB\".\n    SUB r0, 1\n    ^^^"
+            ]
+        );
+
+        let strict = Session::new(Isa::default()).with_syntax(Syntax::Strict);
+        assert!(strict.assemble(b"ADDI r0 1").is_err());
+
+        let french = Session::new(Isa::default()).with_lang(Lang::Fr);
+        assert_eq!(
+            french.assemble(b"CLR r5").err().unwrap(),
+            vec!["Ligne 1, colonne 5 : erreur[E0005] : Registre « r5 » invalide.\n    CLR r5\n        ^^"]
+        );
+    }
+}
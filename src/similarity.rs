@@ -0,0 +1,108 @@
+use std::collections::HashSet;
+
+use crate::assembly::is_register;
+
+/// The normalized n-grams of a program's instructions, for finding programs
+/// that share long runs of code.
+///
+/// Registers are renamed in order of first use within each n-gram, so
+/// `MOV r2, r1` and `MOV r3, r0` look the same. Branches are already offsets,
+/// so label names never matter.
+#[derive(Debug, PartialEq)]
+pub struct Fingerprint(HashSet<String>);
+
+impl Fingerprint {
+    /// Fingerprint of the assembly text of each instruction, using runs of
+    /// `n` instructions. A program shorter than `n` is a single run.
+    pub fn new(instrs: &[String], n: usize) -> Fingerprint {
+        let n = n.clamp(1, instrs.len().max(1));
+        Fingerprint(instrs.windows(n).map(normalize).collect())
+    }
+
+    /// Jaccard similarity, from 0 for nothing in common to 1 for the same
+    /// n-grams.
+    pub fn similarity(&self, other: &Fingerprint) -> f64 {
+        let union = self.0.union(&other.0).count();
+        match union {
+            0 => 1.0,
+            u => self.0.intersection(&other.0).count() as f64 / u as f64,
+        }
+    }
+}
+
+/// Every pair of fingerprints at least `threshold` similar, most similar
+/// first, as indices into `fingerprints` and their score.
+pub fn similar_pairs(fingerprints: &[Fingerprint], threshold: f64) -> Vec<(usize, usize, f64)> {
+    let mut pairs = vec![];
+    for (i, a) in fingerprints.iter().enumerate() {
+        for (j, b) in fingerprints.iter().enumerate().skip(i + 1) {
+            let score = a.similarity(b);
+            if score >= threshold {
+                pairs.push((i, j, score));
+            }
+        }
+    }
+    pairs.sort_by(|a, b| b.2.total_cmp(&a.2));
+    pairs
+}
+
+/// Join instructions into one n-gram, renaming registers to `%0`, `%1`, ...
+fn normalize(instrs: &[String]) -> String {
+    let mut registers: Vec<&str> = vec![];
+    let mut gram = vec![];
+    for instr in instrs {
+        let tokens: Vec<String> = instr
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|t| !t.is_empty())
+            .map(|t| match is_register(t) {
+                true => {
+                    let n = match registers.iter().position(|r| *r == t) {
+                        Some(n) => n,
+                        None => {
+                            registers.push(t);
+                            registers.len() - 1
+                        }
+                    };
+                    format!("%{n}")
+                }
+                false => t.to_string(),
+            })
+            .collect();
+        gram.push(tokens.join(" "));
+    }
+    gram.join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint(program: &str, n: usize) -> Fingerprint {
+        let lines: Vec<String> = program.lines().map(str::to_string).collect();
+        Fingerprint::new(&lines, n)
+    }
+
+    #[test]
+    fn test_similarity() {
+        let a = fingerprint("ADDI r1, 1\nMOV r2, r1\nBRZ -2\nPAUSE", 2);
+        let renamed = fingerprint("ADDI r3, 1\nMOV r0, r3\nBRZ -2\nPAUSE", 2);
+        assert_eq!(a, renamed);
+        assert_eq!(a.similarity(&renamed), 1.0);
+
+        // shares one of three n-grams with a, out of five in total
+        let other = fingerprint("ADDI r1, 1\nMOV r2, r1\nCLR r0\nOUT r0", 2);
+        assert_eq!(a.similarity(&other), 0.2);
+
+        let short = fingerprint("PAUSE", 4);
+        assert_eq!(short.similarity(&fingerprint("PAUSE", 4)), 1.0);
+
+        let pairs = similar_pairs(&[a, other, renamed], 0.5);
+        assert_eq!(pairs, vec![(0, 2, 1.0)]);
+
+        // more registers than letters, in either case
+        let many: Vec<String> = (0..30).map(|n| format!("CLR R{n}")).collect();
+        let gram = normalize(&many);
+        assert!(gram.starts_with("CLR %0; CLR %1;"));
+        assert!(gram.ends_with("CLR %29"));
+    }
+}
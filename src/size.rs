@@ -0,0 +1,127 @@
+use std::fmt::Display;
+
+/// How much of a memory a program fills.
+#[derive(Debug, PartialEq)]
+pub struct Usage {
+    pub used: usize,
+    pub depth: usize,
+    /// Words used past the end of the memory, counted in `used` too.
+    pub overflow: usize,
+    /// Start and length of the longest run of unused words.
+    pub largest_free: (usize, usize),
+}
+
+impl Usage {
+    /// Usage of a memory of `depth` words where `occupied[n]` says whether
+    /// word `n` is used, and words past the end of `occupied` are free.
+    pub fn new(occupied: &[bool], depth: usize) -> Usage {
+        let in_memory = (0..depth).map(|n| occupied.get(n).copied().unwrap_or_default());
+        let mut largest_free = (0, 0);
+        let mut run_start = 0;
+        for (n, used) in in_memory.chain([true]).enumerate() {
+            if used {
+                if n - run_start > largest_free.1 {
+                    largest_free = (run_start, n - run_start);
+                }
+                run_start = n + 1;
+            }
+        }
+
+        let past_end = occupied.get(depth..).unwrap_or_default();
+        Usage {
+            used: occupied.iter().filter(|o| **o).count(),
+            depth,
+            overflow: past_end.iter().filter(|o| **o).count(),
+            largest_free,
+        }
+    }
+
+    /// Usage of a memory filled from address 0 with `len` words.
+    pub fn contiguous(len: usize, depth: usize) -> Usage {
+        Usage::new(&vec![true; len], depth)
+    }
+
+    pub fn free(&self) -> usize {
+        self.depth.saturating_sub(self.used - self.overflow)
+    }
+
+    /// Percentage of the memory used, over 100 if words overflow it, and 0
+    /// for an empty memory.
+    pub fn percent(&self) -> f64 {
+        match self.depth {
+            0 => 0.0,
+            d => self.used as f64 * 100.0 / d as f64,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"used\": {}, \"free\": {}, \"depth\": {}, \"overflow\": {}, \"percent\": {:.1}, \
+             \"largest_free\": {{\"start\": {}, \"length\": {}}}}}",
+            self.used,
+            self.free(),
+            self.depth,
+            self.overflow,
+            self.percent(),
+            self.largest_free.0,
+            self.largest_free.1
+        )
+    }
+}
+
+impl Display for Usage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} / {} words used ({:.1}%), {} free",
+            self.used,
+            self.depth,
+            self.percent(),
+            self.free()
+        )?;
+        if self.overflow > 0 {
+            write!(f, ", {} past the end", self.overflow)?;
+        }
+        match self.largest_free {
+            (_, 0) => Ok(()),
+            (start, len) => write!(f, ", largest free region {len} words at 0x{start:04x}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage() {
+        let u = Usage::contiguous(64, 256);
+        assert_eq!(u.free(), 192);
+        assert_eq!(u.percent(), 25.0);
+        assert_eq!(u.largest_free, (64, 192));
+        assert_eq!(
+            u.to_string(),
+            "64 / 256 words used (25.0%), 192 free, largest free region 192 words at 0x0040"
+        );
+        assert_eq!(
+            u.to_json(),
+            "{\"used\": 64, \"free\": 192, \"depth\": 256, \"overflow\": 0, \"percent\": 25.0, \
+             \"largest_free\": {\"start\": 64, \"length\": 192}}"
+        );
+
+        let u = Usage::new(&[false, true, false, false, true, false], 8);
+        assert_eq!(u.used, 2);
+        assert_eq!(u.largest_free, (5, 3));
+
+        let u = Usage::contiguous(301, 256);
+        assert_eq!((u.used, u.overflow, u.free()), (301, 45, 0));
+        assert!(u.percent() > 100.0);
+        assert_eq!(
+            u.to_string(),
+            "301 / 256 words used (117.6%), 0 free, 45 past the end"
+        );
+
+        assert_eq!(Usage::contiguous(4, 4).largest_free, (0, 0));
+        assert_eq!(Usage::contiguous(0, 0).percent(), 0.0);
+    }
+}
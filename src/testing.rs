@@ -0,0 +1,218 @@
+use std::{
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::session::Session;
+
+/// A source file and the output it should assemble to, found by
+/// [`fixtures`]. The output is a HEX file of bytes or a MIF of program
+/// memory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fixture {
+    pub source: PathBuf,
+    pub expected: PathBuf,
+}
+
+/// Why a program failed a regression check.
+#[derive(Debug, PartialEq)]
+pub enum Failure {
+    /// A fixture's file couldn't be read.
+    Io(PathBuf, String),
+    /// The source didn't assemble, with the errors as the CLI prints them.
+    Errors(Vec<String>),
+    /// The disassembly of the source didn't assemble again.
+    Reassembly(Vec<String>),
+    /// The first byte that differs, `None` past the end of either output.
+    Byte {
+        address: usize,
+        expected: Option<u8>,
+        actual: Option<u8>,
+    },
+    /// The first line of a MIF that differs, counting from 1.
+    Line {
+        line: usize,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let byte = |b: &Option<u8>| match b {
+            Some(b) => format!("0x{b:02x}"),
+            None => "nothing".to_string(),
+        };
+        match self {
+            Failure::Io(path, e) => write!(f, "Failed to read {}: {e}", path.display()),
+            Failure::Errors(errors) => write!(f, "Failed to assemble:\n{}", errors.join("\n")),
+            Failure::Reassembly(errors) => {
+                write!(f, "Disassembly failed to assemble:\n{}", errors.join("\n"))
+            }
+            Failure::Byte {
+                address,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Expected {} at 0x{address:04x}, found {}.",
+                byte(expected),
+                byte(actual)
+            ),
+            Failure::Line {
+                line,
+                expected,
+                actual,
+            } => write!(f, "Line {line}: expected {expected:?}, found {actual:?}."),
+        }
+    }
+}
+
+/// Every `.s` file in `dir` with a `.hex` or `.mif` file of the same name
+/// beside it, sorted by path. A `.hex` is used if both exist.
+pub fn fixtures(dir: &Path) -> io::Result<Vec<Fixture>> {
+    let mut fixtures = vec![];
+    for entry in fs::read_dir(dir)? {
+        let source = entry?.path();
+        if source.extension().is_none_or(|ext| ext != "s") {
+            continue;
+        }
+        let expected = ["hex", "mif"]
+            .iter()
+            .map(|ext| source.with_extension(ext))
+            .find(|path| path.is_file());
+        if let Some(expected) = expected {
+            fixtures.push(Fixture { source, expected });
+        }
+    }
+    fixtures.sort_by(|a, b| a.source.cmp(&b.source));
+    Ok(fixtures)
+}
+
+/// Assemble a fixture's source and compare it to the expected output.
+pub fn check_golden(session: &Session, fixture: &Fixture) -> Result<(), Failure> {
+    let read =
+        |path: &PathBuf| fs::read(path).map_err(|e| Failure::Io(path.clone(), e.to_string()));
+    let program = session
+        .assemble(&read(&fixture.source)?)
+        .map_err(Failure::Errors)?;
+    let expected = read(&fixture.expected)?;
+
+    if fixture.expected.extension().is_some_and(|ext| ext == "mif") {
+        // MIFs are compared line by line, so line endings don't matter
+        let actual = program
+            .as_mif()
+            .map_err(|e| Failure::Errors(vec![e.to_string()]))?;
+        let expected = String::from_utf8_lossy(&expected);
+        let (mut expected, mut actual) = (expected.lines(), actual.lines());
+        for line in 1.. {
+            match (expected.next(), actual.next()) {
+                (None, None) => break,
+                (e, a) if e == a => continue,
+                (e, a) => {
+                    return Err(Failure::Line {
+                        line,
+                        expected: e.unwrap_or_default().to_string(),
+                        actual: a.unwrap_or_default().to_string(),
+                    })
+                }
+            }
+        }
+        Ok(())
+    } else {
+        compare_bytes(&expected, &program.as_binary())
+    }
+}
+
+/// Check every fixture in `dir`, returning the ones that failed.
+pub fn check_fixtures(session: &Session, dir: &Path) -> io::Result<Vec<(Fixture, Failure)>> {
+    Ok(fixtures(dir)?
+        .into_iter()
+        .filter_map(|fixture| {
+            let failure = check_golden(session, &fixture).err()?;
+            Some((fixture, failure))
+        })
+        .collect())
+}
+
+/// Assemble `source`, disassemble the result and assemble that again,
+/// checking both assemblies give the same machine code.
+pub fn round_trip(session: &Session, source: &[u8]) -> Result<(), Failure> {
+    let first = session.assemble(source).map_err(Failure::Errors)?;
+    let second = session
+        .assemble(first.as_text().as_bytes())
+        .map_err(Failure::Reassembly)?;
+    compare_bytes(&first.as_binary(), &second.as_binary())
+}
+
+fn compare_bytes(expected: &[u8], actual: &[u8]) -> Result<(), Failure> {
+    let len = expected.len().max(actual.len());
+    match (0..len).find(|&n| expected.get(n) != actual.get(n)) {
+        Some(address) => Err(Failure::Byte {
+            address,
+            expected: expected.get(address).copied(),
+            actual: actual.get(address).copied(),
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_golden() {
+        let dir = std::env::temp_dir().join(format!("asp-testing-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.s"), "CLR r0\nPAUSE").unwrap();
+        fs::write(dir.join("a.hex"), [0b011000_00, 0xff]).unwrap();
+        fs::write(dir.join("b.s"), "CLR r1").unwrap();
+        fs::write(dir.join("b.hex"), [0b011000_00]).unwrap();
+        fs::write(dir.join("c.s"), "PAUSE").unwrap();
+
+        let session = Session::default();
+        let found = fixtures(&dir).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].expected, dir.join("a.hex"));
+
+        let failed = check_fixtures(&session, &dir).unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0.source, dir.join("b.s"));
+        assert_eq!(
+            failed[0].1,
+            Failure::Byte {
+                address: 0,
+                expected: Some(0b011000_00),
+                actual: Some(0b011000_01)
+            }
+        );
+
+        let mif = Fixture {
+            source: dir.join("c.s"),
+            expected: dir.join("c.mif"),
+        };
+        let expected = session.assemble(b"PAUSE").unwrap().as_mif().unwrap();
+        fs::write(&mif.expected, expected.replace('\n', "\r\n")).unwrap();
+        assert_eq!(check_golden(&session, &mif), Ok(()));
+        fs::write(&mif.expected, expected.replace("11111111", "00000000")).unwrap();
+        assert!(matches!(
+            check_golden(&session, &mif),
+            Err(Failure::Line { line: 8, .. })
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let session = Session::default();
+        let example = include_bytes!("../examples/example.s");
+        assert_eq!(round_trip(&session, example), Ok(()));
+        assert!(matches!(
+            round_trip(&session, b"SUB r0"),
+            Err(Failure::Errors(_))
+        ));
+    }
+}
@@ -0,0 +1,62 @@
+use std::fmt::Binary;
+
+/// Storage for one instruction word. An ISA narrower than the word type uses
+/// only the low bits.
+pub trait Word: Copy + Binary + Into<u32> {
+    /// Bytes per word in machine code files.
+    const BYTES: usize;
+
+    /// The low bits of `value`.
+    fn truncate(value: u32) -> Self;
+
+    /// Read a big-endian word from exactly `Self::BYTES` bytes.
+    fn read_be(bytes: &[u8]) -> Self {
+        Self::truncate(bytes.iter().fold(0, |w, b| w << 8 | *b as u32))
+    }
+
+    /// Append the word to `out` in big-endian order.
+    fn write_be(self, out: &mut Vec<u8>) {
+        let value: u32 = self.into();
+        for n in (0..Self::BYTES).rev() {
+            out.push((value >> (8 * n)) as u8);
+        }
+    }
+}
+
+impl Word for u8 {
+    const BYTES: usize = 1;
+
+    fn truncate(value: u32) -> Self {
+        value as u8
+    }
+}
+
+impl Word for u16 {
+    const BYTES: usize = 2;
+
+    fn truncate(value: u32) -> Self {
+        value as u16
+    }
+}
+
+/// Machine code file contents for a sequence of words.
+pub fn to_bytes<W: Word>(words: &[W]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(words.len() * W::BYTES);
+    for w in words {
+        w.write_be(&mut out);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bytes() {
+        assert_eq!(to_bytes(&[0x12u8, 0xff]), vec![0x12, 0xff]);
+        assert_eq!(to_bytes(&[0x1234u16, 0x00ff]), vec![0x12, 0x34, 0x00, 0xff]);
+        assert_eq!(u16::read_be(&[0xab, 0xcd]), 0xabcd);
+        assert_eq!(u8::read_be(&[0xab]), 0xab);
+    }
+}
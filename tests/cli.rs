@@ -0,0 +1,51 @@
+#![cfg(feature = "cli")]
+
+use std::{fs, path::PathBuf, process::Command};
+
+/// A fresh directory for one test's files.
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("asp-cli-{name}-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Run `asp` with `args`, returning whether it succeeded and its stdout.
+fn asp(args: &[&str]) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_asp"))
+        .args(args)
+        .output()
+        .unwrap();
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+#[test]
+fn test_include_in_every_command() {
+    let dir = scratch("include");
+    fs::write(
+        dir.join("main.s"),
+        ".include \"consts.s\"\nCLR r0\nADDI r0, SPEED\nPAUSE",
+    )
+    .unwrap();
+    fs::write(dir.join("consts.s"), "SPEED EQU 5").unwrap();
+    let main = dir.join("main.s");
+    let main = main.to_str().unwrap();
+
+    let (ok, out) = asp(&["size", main]);
+    assert!(ok);
+    assert!(out.contains("3 / 256 words used"), "{out}");
+
+    let (ok, out) = asp(&["stats", main]);
+    assert!(ok, "{out}");
+
+    let (ok, out) = asp(&["grep", "ADDI r0, 5", main]);
+    assert!(ok);
+    assert!(out.contains("ADDI r0, 5"), "{out}");
+
+    let (ok, _) = asp(&["diff", main, main]);
+    assert!(ok);
+
+    fs::remove_dir_all(&dir).unwrap();
+}